@@ -4,6 +4,7 @@ use futures::executor::block_on;
 use mapbox_maps::{Config, Map};
 use std::env;
 use std::rc::Rc;
+use std::sync::Mutex;
 use winit::{
     event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
@@ -12,22 +13,19 @@ use winit::{
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let event_loop = EventLoop::new();
+    let event_loop = EventLoop::with_user_event();
     let window = WindowBuilder::new().build(&event_loop)?;
     let window = Rc::new(window);
 
     let token =
         env::var("MAPBOX_ACCESS_TOKEN").expect("Provide MAPBOX_ACCESS_TOKEN as env variable.");
-    let mut map = Map::new(Config::new(
-        &token,
-        window.clone(),
-        0.0,
-        22.0,
-        0.0,
-        60.0,
-        true,
-    ))
-    .await?;
+    let mut config = Config::new(&token, window.clone(), 0.0, 22.0, 0.0, 60.0, true);
+    // Tiles arrive in the background, wake up the event loop to draw them.
+    let proxy = Mutex::new(event_loop.create_proxy());
+    config.set_redraw_callback(move || {
+        proxy.lock().unwrap().send_event(()).ok();
+    });
+    let mut map = Map::new(config).await?;
 
     map.load_style("mapbox/streets-v11").await?;
 
@@ -49,6 +47,7 @@ async fn main() -> Result<()> {
                 }
                 _ => {}
             },
+            Event::UserEvent(()) => window.request_redraw(),
             Event::RedrawRequested(_) => {
                 println!("redraw requested");
                 match block_on(map.render()) {
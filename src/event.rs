@@ -0,0 +1,148 @@
+use crate::source::TileCoordinates;
+use crate::util::unique_id;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapEvent {
+    StyleLoaded,
+    SourceLoaded {
+        source: String,
+    },
+    // Something other than a tile failed, like decoding a video frame.
+    SourceErrored {
+        source: String,
+        error: String,
+    },
+    TileLoaded {
+        source: String,
+        tile: TileCoordinates,
+    },
+    TileErrored {
+        source: String,
+        tile: TileCoordinates,
+        error: String,
+    },
+    // The tile left the viewport or was reloaded before it finished loading.
+    TileAborted {
+        source: String,
+        tile: TileCoordinates,
+    },
+    // Every tile of the viewport is loaded. Sent once until the map is busy
+    // again.
+    Idle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Subscription(usize);
+
+// Whether the last frame had everything loaded, `Idle` is due when a frame
+// is the first one to.
+#[derive(Debug, Default)]
+pub(crate) struct IdleTracker {
+    idle: bool,
+}
+
+impl IdleTracker {
+    // Returns whether `Idle` should be sent after this frame.
+    pub fn update(&mut self, loaded: bool) -> bool {
+        let became_idle = loaded && !self.idle;
+        self.idle = loaded;
+        became_idle
+    }
+
+    pub fn reset(&mut self) {
+        self.idle = false;
+    }
+}
+
+type ListenerFn = dyn Fn(&MapEvent) + Send + Sync;
+
+#[derive(Default)]
+pub(crate) struct EventEmitter {
+    listeners: Vec<(Subscription, Box<ListenerFn>)>,
+}
+
+impl EventEmitter {
+    pub fn subscribe<F>(&mut self, listener: F) -> Subscription
+    where
+        F: Fn(&MapEvent) + Send + Sync + 'static,
+    {
+        let subscription = Subscription(unique_id());
+        self.listeners.push((subscription, Box::new(listener)));
+        subscription
+    }
+
+    pub fn unsubscribe(&mut self, subscription: Subscription) {
+        self.listeners.retain(|(id, _)| *id != subscription);
+    }
+
+    pub fn emit(&self, event: &MapEvent) {
+        for (_, listener) in &self.listeners {
+            listener(event);
+        }
+    }
+}
+
+type RedrawFn = dyn Fn() + Send + Sync;
+
+// Called from the loading tasks when new data can be drawn, so the
+// application can schedule a render.
+#[derive(Clone)]
+pub(crate) struct RedrawCallback(Arc<RedrawFn>);
+
+impl RedrawCallback {
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        Self(Arc::new(callback))
+    }
+
+    pub fn notify(&self) {
+        (self.0)()
+    }
+}
+
+impl Debug for RedrawCallback {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("RedrawCallback")
+    }
+}
+
+impl Debug for EventEmitter {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("EventEmitter")
+            .field("listeners", &self.listeners.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn event_emitter_unsubscribe() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut emitter = EventEmitter::default();
+        let first = {
+            let received = received.clone();
+            emitter.subscribe(move |event| received.lock().unwrap().push(event.clone()))
+        };
+        let second = {
+            let received = received.clone();
+            emitter.subscribe(move |_| received.lock().unwrap().push(MapEvent::Idle))
+        };
+        assert_ne!(first, second);
+
+        emitter.emit(&MapEvent::StyleLoaded);
+        emitter.unsubscribe(second);
+        emitter.emit(&MapEvent::StyleLoaded);
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![MapEvent::StyleLoaded, MapEvent::Idle, MapEvent::StyleLoaded]
+        );
+    }
+}
@@ -23,7 +23,7 @@ impl<T: Float> LngLatBounds<T> {
     }
 
     pub fn get_south(&self) -> T {
-        self.ne.lat()
+        self.sw.lat()
     }
 
     pub fn get_east(&self) -> T {
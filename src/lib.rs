@@ -1,13 +1,22 @@
 #![allow(dead_code)]
 
+mod event;
 mod geo;
 mod map;
 mod network;
+mod offline;
 mod render;
 mod source;
 mod style;
 mod style_spec;
 mod util;
+mod worker;
 
+pub use event::{MapEvent, Subscription};
 pub use map::Config;
 pub use map::Map;
+pub use network::{
+    CacheStatus, NetworkError, NetworkStats, Request, RequestEvent, RequestStats, ResourceKind,
+};
+pub use offline::{OfflineProgress, OfflineRegion};
+pub use source::{CustomSource, CustomTile, TileCacheStats, TileCoordinates};
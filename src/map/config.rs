@@ -1,6 +1,13 @@
+use crate::event::RedrawCallback;
+use crate::network::{Request, RequestEvent, RequestObserver, ResourceKind, TransformRequest};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use winit::window::Window;
 
+const DEFAULT_MAX_CACHE_SIZE: u64 = 50 * 1024 * 1024;
+const DEFAULT_MAX_REQUESTS_PER_HOST: usize = 6;
+const DEFAULT_MAX_TILE_CACHE_SIZE: u64 = 128 * 1024 * 1024;
+
 pub struct Config {
     token: String,
     window: Rc<Window>,
@@ -9,6 +16,13 @@ pub struct Config {
     min_pitch: f32,
     max_pitch: f32,
     render_world_copies: bool,
+    cache_path: Option<PathBuf>,
+    max_cache_size: u64,
+    max_requests_per_host: usize,
+    max_tile_cache_size: u64,
+    transform_request: Option<TransformRequest>,
+    request_observer: Option<RequestObserver>,
+    redraw_callback: Option<RedrawCallback>,
 }
 
 impl<'a> Config {
@@ -29,6 +43,13 @@ impl<'a> Config {
             min_pitch,
             max_pitch,
             render_world_copies,
+            cache_path: None,
+            max_cache_size: DEFAULT_MAX_CACHE_SIZE,
+            max_requests_per_host: DEFAULT_MAX_REQUESTS_PER_HOST,
+            max_tile_cache_size: DEFAULT_MAX_TILE_CACHE_SIZE,
+            transform_request: None,
+            request_observer: None,
+            redraw_callback: None,
         }
     }
 
@@ -59,4 +80,73 @@ impl<'a> Config {
     pub fn render_world_copies(&self) -> bool {
         self.render_world_copies
     }
+
+    pub fn cache_path(&self) -> Option<&Path> {
+        self.cache_path.as_deref()
+    }
+
+    pub fn set_cache_path(&mut self, cache_path: &Path) {
+        self.cache_path = Some(cache_path.to_owned());
+    }
+
+    pub fn max_cache_size(&self) -> u64 {
+        self.max_cache_size
+    }
+
+    pub fn set_max_cache_size(&mut self, max_cache_size: u64) {
+        self.max_cache_size = max_cache_size;
+    }
+
+    pub fn max_requests_per_host(&self) -> usize {
+        self.max_requests_per_host
+    }
+
+    // At least one request per host is always allowed.
+    pub fn set_max_requests_per_host(&mut self, max_requests_per_host: usize) {
+        self.max_requests_per_host = max_requests_per_host.max(1);
+    }
+
+    // Memory budget in bytes for recently used tiles of all sources.
+    pub fn max_tile_cache_size(&self) -> u64 {
+        self.max_tile_cache_size
+    }
+
+    pub fn set_max_tile_cache_size(&mut self, max_tile_cache_size: u64) {
+        self.max_tile_cache_size = max_tile_cache_size;
+    }
+
+    pub(crate) fn transform_request(&self) -> Option<TransformRequest> {
+        self.transform_request.clone()
+    }
+
+    pub fn set_transform_request<F>(&mut self, transform_request: F)
+    where
+        F: Fn(&str, ResourceKind) -> Request + Send + Sync + 'static,
+    {
+        self.transform_request = Some(TransformRequest::new(transform_request));
+    }
+
+    pub(crate) fn request_observer(&self) -> Option<RequestObserver> {
+        self.request_observer.clone()
+    }
+
+    pub fn set_request_observer<F>(&mut self, request_observer: F)
+    where
+        F: Fn(&RequestEvent) + Send + Sync + 'static,
+    {
+        self.request_observer = Some(RequestObserver::new(request_observer));
+    }
+
+    pub(crate) fn redraw_callback(&self) -> Option<RedrawCallback> {
+        self.redraw_callback.clone()
+    }
+
+    // Called from another thread whenever a tile arrives, `Map::render` needs
+    // to be called to draw it.
+    pub fn set_redraw_callback<F>(&mut self, redraw_callback: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.redraw_callback = Some(RedrawCallback::new(redraw_callback));
+    }
 }
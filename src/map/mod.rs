@@ -1,23 +1,44 @@
 mod config;
 
+use crate::event::{EventEmitter, IdleTracker, MapEvent, RedrawCallback, Subscription};
 use crate::geo::Transform;
-use crate::network::NetworkManager;
+use crate::network::{NetworkManager, NetworkStats, ResourceCache};
+use crate::offline::{OfflineProgress, OfflineRegion};
 use crate::render::Painter;
+use crate::source::{validate_custom_source, CustomSource, GeoJSON, TileCacheStats, Video};
 use crate::style::Style;
+use crate::worker::WorkerPool;
 pub use config::Config;
-use eyre::Result;
+use eyre::{eyre, Result};
+use serde_json::Value;
 use std::sync::Arc;
+use std::time::Duration;
 
 pub struct Map {
     nm: Arc<NetworkManager>,
+    workers: Arc<WorkerPool>,
     style: Option<Style>,
+    max_tile_cache_size: u64,
     painter: Painter,
     transform: Transform,
+    events: EventEmitter,
+    redraw_callback: Option<RedrawCallback>,
+    idle: IdleTracker,
 }
 
 impl Map {
     pub async fn new(config: Config) -> Result<Self> {
-        let nm = NetworkManager::new(config.token())?;
+        let cache = match config.cache_path() {
+            Some(path) => Some(ResourceCache::open(path, config.max_cache_size())?),
+            None => None,
+        };
+        let nm = NetworkManager::new(
+            config.token(),
+            cache,
+            config.max_requests_per_host(),
+            config.transform_request(),
+            config.request_observer(),
+        )?;
         let painter = Painter::new(config.window()).await?;
         let transform = Transform::new(
             config.min_zoom(),
@@ -28,9 +49,14 @@ impl Map {
         );
         let mut map = Self {
             nm: Arc::new(nm),
+            workers: Arc::new(WorkerPool::new(WorkerPool::default_count())?),
             style: None,
+            max_tile_cache_size: config.max_tile_cache_size(),
             painter,
             transform,
+            events: EventEmitter::default(),
+            redraw_callback: config.redraw_callback(),
+            idle: IdleTracker::default(),
         };
 
         // let window_size = config.window().inner_size();
@@ -41,8 +67,18 @@ impl Map {
     }
 
     pub async fn load_style(&mut self, uri: &str) -> Result<()> {
-        let style = Style::new(uri, self.nm.clone()).await?;
+        let style = Style::new(
+            uri,
+            self.nm.clone(),
+            self.workers.clone(),
+            self.max_tile_cache_size,
+            self.redraw_callback.clone(),
+        )
+        .await?;
         self.style = Some(style);
+        self.idle.reset();
+        self.emit_style_events();
+        self.events.emit(&MapEvent::StyleLoaded);
         Ok(())
     }
 
@@ -53,13 +89,160 @@ impl Map {
         };
 
         style.update_sources(&self.transform).await?;
+        let loaded = style.is_loaded();
 
         self.painter.render()?;
 
         println!("Map rendered");
+        self.emit_style_events();
+        if self.idle.update(loaded) {
+            self.events.emit(&MapEvent::Idle);
+        }
+        Ok(())
+    }
+
+    // Listeners are called during `render` and the calls changing the style.
+    pub fn subscribe<F>(&mut self, listener: F) -> Subscription
+    where
+        F: Fn(&MapEvent) + Send + Sync + 'static,
+    {
+        self.events.subscribe(listener)
+    }
+
+    pub fn unsubscribe(&mut self, subscription: Subscription) {
+        self.events.unsubscribe(subscription);
+    }
+
+    fn emit_style_events(&mut self) {
+        if let Some(style) = &mut self.style {
+            for event in style.take_events() {
+                self.events.emit(&event);
+            }
+        }
+    }
+
+    pub async fn download_region<F>(
+        &self,
+        region: &OfflineRegion,
+        on_progress: F,
+    ) -> Result<OfflineProgress>
+    where
+        F: FnMut(&OfflineProgress),
+    {
+        region.download(&self.nm, on_progress).await
+    }
+
+    // Layers of the style refer to the source by `id`, like to any other.
+    pub async fn add_custom_source(
+        &mut self,
+        id: &str,
+        source: Arc<dyn CustomSource>,
+    ) -> Result<()> {
+        validate_custom_source(&*source)?;
+        self.style_mut()?.add_custom_source(id, source).await?;
+        self.emit_style_events();
         Ok(())
     }
 
+    // `data` is a GeoJSON object or the URL of one.
+    pub async fn set_geojson_data(&mut self, source_id: &str, data: Value) -> Result<()> {
+        self.style_mut()?.set_geojson_data(source_id, data).await
+    }
+
+    // Features need an id, existing features with the same id are replaced.
+    pub fn add_geojson_features(&mut self, source_id: &str, features: Vec<Value>) -> Result<()> {
+        self.style_mut()?.add_geojson_features(source_id, features)
+    }
+
+    // Fails without changes when one of the features isn't in the source.
+    pub fn update_geojson_features(&mut self, source_id: &str, features: Vec<Value>) -> Result<()> {
+        self.style_mut()?
+            .update_geojson_features(source_id, features)
+    }
+
+    pub fn remove_geojson_features(&mut self, source_id: &str, ids: &[Value]) -> Result<()> {
+        self.style_mut()?.remove_geojson_features(source_id, ids)
+    }
+
+    // Corners go clockwise from the top left one, as [longitude, latitude].
+    pub async fn update_image(
+        &mut self,
+        source_id: &str,
+        url: &str,
+        coordinates: [[f64; 2]; 4],
+    ) -> Result<()> {
+        self.style_mut()?
+            .update_image(source_id, url, &coordinates)
+            .await
+    }
+
+    pub fn play_video(&self, source_id: &str) -> Result<()> {
+        self.video_source(source_id)?.play();
+        Ok(())
+    }
+
+    pub fn pause_video(&self, source_id: &str) -> Result<()> {
+        self.video_source(source_id)?.pause();
+        Ok(())
+    }
+
+    pub fn seek_video(&self, source_id: &str, position: Duration) -> Result<()> {
+        self.video_source(source_id)?.seek(position);
+        Ok(())
+    }
+
+    pub fn get_cluster_expansion_zoom(&self, source_id: &str, cluster_id: u64) -> Result<u32> {
+        self.geojson_source(source_id)?
+            .get_cluster_expansion_zoom(cluster_id)
+    }
+
+    pub fn get_cluster_children(&self, source_id: &str, cluster_id: u64) -> Result<Vec<Value>> {
+        self.geojson_source(source_id)?
+            .get_cluster_children(cluster_id)
+    }
+
+    pub fn get_cluster_leaves(
+        &self,
+        source_id: &str,
+        cluster_id: u64,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<Value>> {
+        self.geojson_source(source_id)?
+            .get_cluster_leaves(cluster_id, limit, offset)
+    }
+
+    fn geojson_source(&self, source_id: &str) -> Result<&GeoJSON> {
+        match &self.style {
+            Some(style) => style.geojson_source(source_id),
+            None => Err(eyre!("Style is not loaded")),
+        }
+    }
+
+    fn video_source(&self, source_id: &str) -> Result<&Video> {
+        match &self.style {
+            Some(style) => style.video_source(source_id),
+            None => Err(eyre!("Style is not loaded")),
+        }
+    }
+
+    fn style_mut(&mut self) -> Result<&mut Style> {
+        self.style
+            .as_mut()
+            .ok_or_else(|| eyre!("Style is not loaded"))
+    }
+
+    pub fn network_stats(&self) -> NetworkStats {
+        self.nm.stats()
+    }
+
+    pub fn tile_cache_stats(&self) -> TileCacheStats {
+        match &self.style {
+            Some(style) => style.tile_cache_stats(),
+            None => TileCacheStats::default(),
+        }
+    }
+
     pub fn resize(&mut self, width: f32, height: f32) {
         self.transform.resize(width, height);
     }
@@ -0,0 +1,113 @@
+use reqwest::StatusCode;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug)]
+pub enum NetworkError {
+    NotFound { url: String, status: u16 },
+    Unauthorized { url: String, status: u16 },
+    Status { url: String, status: u16 },
+    Request { url: String, source: reqwest::Error },
+}
+
+impl NetworkError {
+    pub(crate) fn from_status(url: &str, status: StatusCode) -> Self {
+        let url = url.to_owned();
+        match status {
+            StatusCode::NOT_FOUND | StatusCode::GONE => NetworkError::NotFound {
+                url,
+                status: status.as_u16(),
+            },
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => NetworkError::Unauthorized {
+                url,
+                status: status.as_u16(),
+            },
+            _ => NetworkError::Status {
+                url,
+                status: status.as_u16(),
+            },
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        match self {
+            NetworkError::NotFound { url, .. }
+            | NetworkError::Unauthorized { url, .. }
+            | NetworkError::Status { url, .. }
+            | NetworkError::Request { url, .. } => url,
+        }
+    }
+
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            NetworkError::NotFound { status, .. }
+            | NetworkError::Unauthorized { status, .. }
+            | NetworkError::Status { status, .. } => Some(*status),
+            NetworkError::Request { .. } => None,
+        }
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, NetworkError::Unauthorized { .. })
+    }
+}
+
+impl Display for NetworkError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            NetworkError::NotFound { url, .. } => write!(f, "Resource \"{}\" not found", url),
+            NetworkError::Unauthorized { url, status } => write!(
+                f,
+                "Access to \"{}\" denied with status {}, check the access token",
+                url, status
+            ),
+            NetworkError::Status { url, status } => {
+                write!(f, "Request to \"{}\" failed with status {}", url, status)
+            }
+            NetworkError::Request { url, source } => {
+                write!(f, "Request to \"{}\" failed: {}", url, source)
+            }
+        }
+    }
+}
+
+impl Error for NetworkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NetworkError::Request { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_error_from_status() {
+        assert!(matches!(
+            NetworkError::from_status("a", StatusCode::NOT_FOUND),
+            NetworkError::NotFound { .. }
+        ));
+        assert_eq!(
+            NetworkError::from_status("a", StatusCode::GONE).status(),
+            Some(410)
+        );
+        assert!(NetworkError::from_status("a", StatusCode::UNAUTHORIZED).is_fatal());
+        assert!(NetworkError::from_status("a", StatusCode::FORBIDDEN).is_fatal());
+        assert!(!NetworkError::from_status("a", StatusCode::BAD_GATEWAY).is_fatal());
+    }
+
+    #[test]
+    fn retryable_statuses() {
+        assert!(is_retryable(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!is_retryable(StatusCode::NOT_FOUND));
+        assert!(!is_retryable(StatusCode::UNAUTHORIZED));
+    }
+}
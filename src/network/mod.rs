@@ -1,3 +1,16 @@
+mod error;
 mod network_manager;
+mod request;
+mod resource_cache;
+mod response;
+mod retry;
+mod stats;
 
-pub(crate) use network_manager::NetworkManager;
\ No newline at end of file
+pub use error::NetworkError;
+pub(crate) use network_manager::NetworkManager;
+pub(crate) use request::TransformRequest;
+pub use request::{Request, ResourceKind};
+pub(crate) use resource_cache::ResourceCache;
+pub(crate) use response::Response;
+pub(crate) use stats::RequestObserver;
+pub use stats::{CacheStatus, NetworkStats, RequestEvent, RequestStats};
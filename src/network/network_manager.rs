@@ -1,55 +1,634 @@
-use eyre::Result;
-use mvt::{decode, FeatureWithCoordinates, Tile};
-use reqwest::{Client, ClientBuilder};
+use super::error::{is_retryable, NetworkError};
+use super::request::{Request, ResourceKind, TransformRequest};
+use super::resource_cache::{strip_access_token, CachedResource, ResourceCache};
+use super::response::{CacheHeaders, Response};
+use super::retry::{backoff, retry_after};
+use super::stats::{CacheStatus, NetworkStats, RequestEvent, RequestObserver};
+use eyre::{eyre, Result};
+use image::RgbaImage;
+use reqwest::header::{IF_MODIFIED_SINCE, IF_NONE_MATCH};
+use reqwest::{Client, ClientBuilder, RequestBuilder, StatusCode, Url};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+use tokio::sync::Semaphore;
+use tokio::time::delay_for;
 
 const MAPBOX_API_ENDPOINT: &str = "https://api.mapbox.com";
 
+struct Fetched {
+    response: Response,
+    cache: CacheStatus,
+    status: Option<u16>,
+}
+
+impl Fetched {
+    // A stored no content marker is served as the 404 it was stored for.
+    fn cached(
+        url: &str,
+        cached: &CachedResource,
+        cache: CacheStatus,
+        status: Option<u16>,
+    ) -> Result<Self, NetworkError> {
+        if cached.no_content {
+            return Err(NetworkError::NotFound {
+                url: url.to_owned(),
+                status: StatusCode::NOT_FOUND.as_u16(),
+            });
+        }
+        Ok(Self {
+            response: Response {
+                data: cached.data.clone(),
+                expires: cached.expires,
+            },
+            cache,
+            status,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct NetworkManager {
     token: String,
     client: Client,
+    cache: Option<ResourceCache>,
+    max_requests_per_host: usize,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+    transform_request: Option<TransformRequest>,
+    request_observer: Option<RequestObserver>,
+    stats: Mutex<NetworkStats>,
 }
 
 impl NetworkManager {
-    pub fn new(token: &str) -> Result<Self> {
+    pub fn new(
+        token: &str,
+        cache: Option<ResourceCache>,
+        max_requests_per_host: usize,
+        transform_request: Option<TransformRequest>,
+        request_observer: Option<RequestObserver>,
+    ) -> Result<Self> {
         let client = ClientBuilder::new().gzip(true).build()?;
         Ok(Self {
             token: token.to_owned(),
             client,
+            cache,
+            max_requests_per_host: max_requests_per_host.max(1),
+            hosts: Mutex::new(HashMap::new()),
+            transform_request,
+            request_observer,
+            stats: Mutex::new(NetworkStats::default()),
         })
     }
 
-    pub async fn load_style(&self, uri: &str) -> Result<String> {
-        let url = format!(
+    pub fn style_url(&self, uri: &str) -> String {
+        format!(
             "{}/styles/v1/{}?access_token={}",
             MAPBOX_API_ENDPOINT, uri, self.token
-        );
-        let res = self.client.get(&url).send().await?;
-        let body = res.text().await?;
-        Ok(body)
+        )
     }
 
-    pub async fn load_tilejson(&self, uri: &str) -> Result<String> {
-        let url = format!(
+    pub fn tilejson_url(&self, uri: &str) -> String {
+        format!(
             "{}/v4/{}.json?access_token={}",
             MAPBOX_API_ENDPOINT,
             uri.to_string().split_off("mapbox://".len()),
             self.token
-        );
-        let res = self.client.get(&url).send().await?;
-        let body = res.text().await?;
-        Ok(body)
+        )
     }
 
-    pub async fn load_vector_tile(&self, uri: &str) -> Result<(mvt::Tile<FeatureWithCoordinates>)> {
-        let url = uri
-            .to_string()
-            .replace("http", "https")
+    pub fn tile_url(&self, uri: &str) -> String {
+        uri.to_string()
+            .replace("http://", "https://")
             .replace("a.tiles", "api")
-            .replace("b.tiles", "api");
-        let res = self.client.get(&url).send().await?;
-        let body = res.bytes().await?;
-        let tile: Tile<FeatureWithCoordinates> = decode(&body)?;
-        Ok((tile))
+            .replace("b.tiles", "api")
+    }
+
+    // Mapbox raster tilesets have a 512px variant of every tile.
+    pub fn raster_tile_url(&self, uri: &str, tile_size: u32) -> String {
+        let url = self.tile_url(uri);
+        if tile_size != 512 || !url.starts_with(MAPBOX_API_ENDPOINT) || url.contains("@2x") {
+            return url;
+        }
+        let (path, query) = url.split_at(url.find('?').unwrap_or(url.len()));
+        match path.rfind('.') {
+            Some(dot) if !path[dot..].contains('/') => {
+                format!("{}@2x{}{}", &path[..dot], &path[dot..], query)
+            }
+            _ => url,
+        }
+    }
+
+    pub fn sprite_url(&self, uri: &str, ratio: &str, extension: &str) -> String {
+        match uri.strip_prefix("mapbox://sprites/") {
+            Some(path) => format!(
+                "{}/styles/v1/{}/sprite{}.{}?access_token={}",
+                MAPBOX_API_ENDPOINT, path, ratio, extension, self.token
+            ),
+            None => format!("{}{}.{}", uri, ratio, extension),
+        }
+    }
+
+    pub fn glyphs_url(&self, uri: &str, font_stack: &str, range: &str) -> String {
+        let url = uri
+            .replace("{fontstack}", &font_stack.replace(' ', "%20"))
+            .replace("{range}", range);
+        match url.strip_prefix("mapbox://fonts/") {
+            Some(path) => format!(
+                "{}/fonts/v1/{}?access_token={}",
+                MAPBOX_API_ENDPOINT, path, self.token
+            ),
+            None => url,
+        }
+    }
+
+    pub async fn load_style(&self, uri: &str) -> Result<String> {
+        let res = self
+            .fetch(&self.style_url(uri), ResourceKind::Style, None)
+            .await?;
+        Ok(String::from_utf8(res.data)?)
+    }
+
+    pub async fn load_tilejson(&self, uri: &str, source: &str) -> Result<String> {
+        let res = self
+            .fetch(&self.tilejson_url(uri), ResourceKind::Source, Some(source))
+            .await?;
+        Ok(String::from_utf8(res.data)?)
+    }
+
+    pub async fn load_geojson(&self, url: &str, source: &str) -> Result<String> {
+        let res = self.fetch(url, ResourceKind::Source, Some(source)).await?;
+        Ok(String::from_utf8(res.data)?)
+    }
+
+    // A missing tile is an empty tile rather than an error, so it is `None`.
+    // Parsing is left to the workers.
+    pub async fn load_vector_tile(&self, uri: &str, source: &str) -> Result<Option<Response>> {
+        let url = self.tile_url(uri);
+        self.load_tile(&url, source).await
+    }
+
+    pub async fn load_raster_tile(
+        &self,
+        uri: &str,
+        tile_size: u32,
+        source: &str,
+    ) -> Result<Option<Response>> {
+        let url = self.raster_tile_url(uri, tile_size);
+        self.load_tile(&url, source).await
+    }
+
+    async fn load_tile(&self, url: &str, source: &str) -> Result<Option<Response>> {
+        match self.fetch(url, ResourceKind::Tile, Some(source)).await {
+            Ok(res) => Ok(Some(res)),
+            Err(NetworkError::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn load_image(&self, url: &str, source: &str) -> Result<RgbaImage> {
+        let res = self.fetch(url, ResourceKind::Image, Some(source)).await?;
+        Ok(image::load_from_memory(&res.data)?.to_rgba8())
+    }
+
+    pub fn is_downloaded(&self, url: &str) -> bool {
+        match &self.cache {
+            Some(cache) => cache.is_pinned(url),
+            None => false,
+        }
+    }
+
+    pub async fn download(
+        &self,
+        url: &str,
+        kind: ResourceKind,
+        source: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return Err(eyre!("Offline downloads require a cache path")),
+        };
+        if cache.is_pinned(url) {
+            if let Some(cached) = cache.get(url).await {
+                return Ok(cached.data);
+            }
+        }
+
+        let res = match self.fetch(url, kind, source).await {
+            Ok(res) => res,
+            // Sparse tilesets don't have every tile, the gap is stored so
+            // it isn't requested again.
+            Err(NetworkError::NotFound { .. }) if kind == ResourceKind::Tile => {
+                cache.pin_no_content(url).await?;
+                return Ok(Vec::new());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        cache.pin(url, &res.data).await?;
+        Ok(res.data)
+    }
+
+    pub fn max_requests_per_host(&self) -> usize {
+        self.max_requests_per_host
+    }
+
+    pub fn stats(&self) -> NetworkStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    pub async fn fetch(
+        &self,
+        url: &str,
+        kind: ResourceKind,
+        source: Option<&str>,
+    ) -> Result<Response, NetworkError> {
+        let start = Instant::now();
+        let result = self.fetch_resource(url, kind).await;
+
+        // Events end up in logs and analytics, tokens shouldn't.
+        let event = RequestEvent {
+            url: strip_access_token(url),
+            kind,
+            source: source.map(|source| source.to_owned()),
+            status: match &result {
+                Ok(fetched) => fetched.status,
+                Err(e) => e.status(),
+            },
+            bytes: match &result {
+                Ok(fetched) => fetched.response.data.len(),
+                Err(_) => 0,
+            },
+            duration: start.elapsed(),
+            cache: match &result {
+                Ok(fetched) => fetched.cache,
+                Err(_) => CacheStatus::Miss,
+            },
+            failed: result.is_err(),
+        };
+        self.stats.lock().unwrap().record(&event);
+        if let Some(observer) = &self.request_observer {
+            observer.notify(&event);
+        }
+
+        result.map(|fetched| fetched.response)
+    }
+
+    // Resources are cached under their original url, the transformed request
+    // is only what goes over the wire.
+    async fn fetch_resource(&self, url: &str, kind: ResourceKind) -> Result<Fetched, NetworkError> {
+        let now = SystemTime::now();
+        let cached = match &self.cache {
+            Some(cache) => cache.get(url).await,
+            None => None,
+        };
+        if let Some(cached) = &cached {
+            if cached.is_fresh(now) {
+                return Fetched::cached(url, cached, CacheStatus::Hit, None);
+            }
+        }
+
+        // Waiting requests are let through in the order they were issued.
+        let request = match &self.transform_request {
+            Some(transform_request) => transform_request.apply(url, kind),
+            None => Request::new(url),
+        };
+        let host_limit = self.host_limit(request.url());
+
+        // The permit is given back while waiting to retry, so other requests
+        // to the host go ahead in the meantime.
+        let mut attempt = 0;
+        let (result, _permit) = loop {
+            let permit = host_limit.acquire().await;
+            let result = self.request(&request, cached.as_ref()).send().await;
+            let delay = match &result {
+                Ok(res) if is_retryable(res.status()) => {
+                    backoff(attempt, retry_after(res.headers(), SystemTime::now()))
+                }
+                Ok(_) => None,
+                Err(e) if e.is_builder() => None,
+                Err(_) => backoff(attempt, None),
+            };
+            match delay {
+                Some(delay) => {
+                    drop(permit);
+                    attempt += 1;
+                    delay_for(delay).await;
+                }
+                None => break (result, permit),
+            }
+        };
+        let res = match (result, cached.as_ref()) {
+            (Ok(res), _) => res,
+            // Stale data is better than no data when the network is unavailable.
+            (Err(_), Some(cached)) => {
+                return Fetched::cached(url, cached, CacheStatus::Stale, None);
+            }
+            (Err(e), None) => {
+                return Err(NetworkError::Request {
+                    url: url.to_owned(),
+                    source: e,
+                })
+            }
+        };
+
+        let status = res.status();
+        let headers = CacheHeaders::from_headers(res.headers(), now);
+
+        if let (StatusCode::NOT_MODIFIED, Some(cache), Some(cached)) =
+            (status, &self.cache, &cached)
+        {
+            // The cached data is still good when its entry can't be updated.
+            let expires = match cache.refresh(url, &headers).await {
+                Ok(expires) => expires,
+                Err(_) => headers.expires,
+            };
+            return Ok(Fetched {
+                response: Response {
+                    data: cached.data.clone(),
+                    expires,
+                },
+                cache: CacheStatus::Revalidated,
+                status: Some(status.as_u16()),
+            });
+        }
+
+        if !status.is_success() {
+            return match cached {
+                Some(cached) if is_retryable(status) => {
+                    Fetched::cached(url, &cached, CacheStatus::Stale, Some(status.as_u16()))
+                }
+                _ => Err(NetworkError::from_status(url, status)),
+            };
+        }
+
+        let data = match res.bytes().await {
+            Ok(data) => data.to_vec(),
+            Err(e) => {
+                return Err(NetworkError::Request {
+                    url: url.to_owned(),
+                    source: e,
+                })
+            }
+        };
+        // Failing to cache the response doesn't fail the request.
+        if let Some(cache) = &self.cache {
+            cache.put(url, &data, &headers).await.ok();
+        }
+
+        Ok(Fetched {
+            response: Response {
+                data,
+                expires: headers.expires,
+            },
+            cache: CacheStatus::Miss,
+            status: Some(status.as_u16()),
+        })
+    }
+
+    fn host_limit(&self, url: &str) -> Arc<Semaphore> {
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| host.to_owned()))
+            .unwrap_or_default();
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts
+            .entry(host)
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max_requests_per_host)))
+            .clone()
+    }
+
+    fn request(&self, request: &Request, cached: Option<&CachedResource>) -> RequestBuilder {
+        let mut builder = self.client.get(request.url());
+        for (name, value) in request.headers() {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        if let Some(cached) = cached {
+            if let Some(etag) = &cached.etag {
+                builder = builder.header(IF_NONE_MATCH, etag.as_str());
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                builder = builder.header(IF_MODIFIED_SINCE, last_modified.as_str());
+            }
+        }
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    // Answers `count` requests with `body` and returns the address to request
+    // and the received request heads.
+    fn serve(count: usize, body: &'static str) -> (String, thread::JoinHandle<Vec<String>>) {
+        serve_with(count, move |_, _| Some(ok(body)))
+    }
+
+    fn ok(body: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    }
+
+    // Like `serve`, `respond` gets the index and head of each request and
+    // returns the response, or `None` to close the connection without one.
+    fn serve_with<F>(count: usize, respond: F) -> (String, thread::JoinHandle<Vec<String>>)
+    where
+        F: Fn(usize, &str) -> Option<String> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (i, stream) in listener.incoming().take(count).enumerate() {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    request.push_str(&line);
+                }
+                if let Some(response) = respond(i, &request) {
+                    stream.write_all(response.as_bytes()).unwrap();
+                }
+                requests.push(request);
+            }
+            requests
+        });
+        (address, handle)
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "mapbox_maps_{}_{}_{}",
+            name,
+            std::process::id(),
+            crate::util::unique_id()
+        ))
+    }
+
+    #[tokio::test]
+    async fn transform_request_is_fetched_and_cached() {
+        let (address, server) = serve(1, "tile");
+        let transform_request = {
+            let address = address.clone();
+            TransformRequest::new(move |url, kind| {
+                assert_eq!(kind, ResourceKind::Tile);
+                let mut request = Request::new(&url.replace("custom://", &address));
+                request.add_header("X-Custom", "value");
+                request
+            })
+        };
+        let path = temp_path("transform");
+        let cache = ResourceCache::open(&path, 1024).unwrap();
+        let nm =
+            NetworkManager::new("token", Some(cache), 6, Some(transform_request), None).unwrap();
+
+        let url = "custom:///tiles/0/0/0.pbf";
+        let res = nm.fetch(url, ResourceKind::Tile, None).await.unwrap();
+        assert_eq!(res.data, b"tile");
+
+        let request = server.join().unwrap().remove(0).to_lowercase();
+        assert!(request.starts_with("get /tiles/0/0/0.pbf "));
+        assert!(request.contains("x-custom: value"));
+        // Cached under the url before the transform.
+        let cache = nm.cache.as_ref().unwrap();
+        assert_eq!(cache.get(url).await.unwrap().data, b"tile");
+        assert!(cache
+            .get(&format!("{}/tiles/0/0/0.pbf", address))
+            .await
+            .is_none());
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn request_events_without_access_token() {
+        let (address, server) = serve(1, "tile");
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let observer = {
+            let events = events.clone();
+            RequestObserver::new(move |event| events.lock().unwrap().push(event.url.clone()))
+        };
+        let nm = NetworkManager::new("token", None, 6, None, Some(observer)).unwrap();
+
+        let url = format!("{}/0/0/0.pbf?access_token=secret&fresh=true", address);
+        let res = nm.fetch(&url, ResourceKind::Tile, None).await.unwrap();
+        assert_eq!(res.data, b"tile");
+        // The token is still sent, it just isn't reported.
+        assert!(server.join().unwrap()[0].contains("access_token=secret"));
+
+        let expected = format!("{}/0/0/0.pbf?fresh=true", address);
+        assert_eq!(*events.lock().unwrap(), vec![expected]);
+    }
+
+    #[tokio::test]
+    async fn retry_waits_without_host_permit() {
+        let (address, server) = serve_with(3, |i, request| {
+            if i == 0 {
+                assert!(request.starts_with("GET /a "));
+                return Some(
+                    "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 1\r\n\
+                     Content-Length: 0\r\nConnection: close\r\n\r\n"
+                        .to_owned(),
+                );
+            }
+            Some(ok(&request[5..6]))
+        });
+        let nm = NetworkManager::new("token", None, 1, None, None).unwrap();
+
+        // The only request allowed to the host waits for a second before
+        // retrying, the other one doesn't wait for it.
+        let a = async {
+            let url = format!("{}/a", address);
+            let res = nm.fetch(&url, ResourceKind::Tile, None).await.unwrap();
+            assert_eq!(res.data, b"a");
+            Instant::now()
+        };
+        let b = async {
+            delay_for(Duration::from_millis(100)).await;
+            let url = format!("{}/b", address);
+            let res = nm.fetch(&url, ResourceKind::Tile, None).await.unwrap();
+            assert_eq!(res.data, b"b");
+            Instant::now()
+        };
+        let (a, b) = futures::join!(a, b);
+        assert!(b < a);
+        server.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn stale_data_only_after_retries() {
+        // The first connection is dropped without a response.
+        let (address, server) = serve_with(2, |i, _| if i == 0 { None } else { Some(ok("new")) });
+        let path = temp_path("stale");
+        let cache = ResourceCache::open(&path, 1024).unwrap();
+        let url = format!("{}/0/0/0.pbf", address);
+        cache
+            .put(&url, b"old", &CacheHeaders::default())
+            .await
+            .unwrap();
+        let nm = NetworkManager::new("token", Some(cache), 6, None, None).unwrap();
+
+        let res = nm.fetch(&url, ResourceKind::Tile, None).await.unwrap();
+        assert_eq!(res.data, b"new");
+        assert_eq!(server.join().unwrap().len(), 2);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_tiles_are_stored_offline() {
+        let (address, server) = serve_with(2, |i, _| match i {
+            0 => Some(
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_owned(),
+            ),
+            _ => Some(ok("tile")),
+        });
+        let path = temp_path("no_content");
+        let cache = ResourceCache::open(&path, 1024).unwrap();
+        let nm = NetworkManager::new("token", Some(cache), 6, None, None).unwrap();
+        let url = format!("{}/0/0/0.pbf", address);
+
+        let data = nm.download(&url, ResourceKind::Tile, None).await.unwrap();
+        assert!(data.is_empty());
+        assert!(nm.is_downloaded(&url));
+        let cache = nm.cache.as_ref().unwrap();
+        assert!(cache.get(&url).await.unwrap().no_content);
+        // Downloading the region again doesn't request it.
+        assert!(nm
+            .download(&url, ResourceKind::Tile, None)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Online it is still revalidated, in case the tile was added since.
+        let res = nm.fetch(&url, ResourceKind::Tile, None).await.unwrap();
+        assert_eq!(res.data, b"tile");
+        assert!(!cache.get(&url).await.unwrap().no_content);
+        assert!(nm.is_downloaded(&url));
+        assert_eq!(server.join().unwrap().len(), 2);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn raster_tile_url_high_resolution() {
+        let nm = NetworkManager::new("token", None, 6, None, None).unwrap();
+        let url = "https://api.mapbox.com/v4/mapbox.satellite/1/0/0.webp?access_token=token";
+        assert_eq!(
+            nm.raster_tile_url(url, 512),
+            "https://api.mapbox.com/v4/mapbox.satellite/1/0/0@2x.webp?access_token=token"
+        );
+        assert_eq!(nm.raster_tile_url(url, 256), url);
+        let url = "https://tile.openstreetmap.org/1/0/0.png";
+        assert_eq!(nm.raster_tile_url(url, 512), url);
     }
 }
@@ -0,0 +1,68 @@
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    Style,
+    Source,
+    Tile,
+    Glyphs,
+    SpriteImage,
+    SpriteJson,
+    Image,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    url: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn set_url(&mut self, url: &str) {
+        self.url = url.to_owned();
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        self.headers.push((name.to_owned(), value.to_owned()));
+    }
+}
+
+type TransformFn = dyn Fn(&str, ResourceKind) -> Request + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct TransformRequest(Arc<TransformFn>);
+
+impl TransformRequest {
+    pub fn new<F>(transform: F) -> Self
+    where
+        F: Fn(&str, ResourceKind) -> Request + Send + Sync + 'static,
+    {
+        Self(Arc::new(transform))
+    }
+
+    pub fn apply(&self, url: &str, kind: ResourceKind) -> Request {
+        (self.0)(url, kind)
+    }
+}
+
+impl Debug for TransformRequest {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("TransformRequest")
+    }
+}
@@ -0,0 +1,819 @@
+use super::response::CacheHeaders;
+use crate::util::fnv1a;
+use eyre::Result;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const INDEX_FILE: &str = "index.json";
+const JOURNAL_FILE: &str = "index.journal";
+// Changes are appended to the journal and only folded into the index once
+// there are this many of them.
+const JOURNAL_LIMIT: usize = 1000;
+// Reads only update the access order, they are saved in batches.
+const TOUCH_BATCH: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CacheEntry {
+    file: String,
+    size: u64,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    expires: Option<u64>,
+    accessed: u64,
+    #[serde(default)]
+    pinned: bool,
+    // The server has nothing at the url, the file only holds the key.
+    #[serde(default)]
+    no_content: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum JournalRecord {
+    Insert { key: String, entry: CacheEntry },
+    Remove { key: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct CacheIndex {
+    counter: u64,
+    size: u64,
+    #[serde(default)]
+    pinned_size: u64,
+    entries: HashMap<String, CacheEntry>,
+    // Keys changed since the last save and records in the journal file.
+    #[serde(skip)]
+    changed: HashSet<String>,
+    #[serde(skip)]
+    journal_len: usize,
+    // File names in use and unpinned keys by access, derived from the entries.
+    #[serde(skip)]
+    files: HashSet<String>,
+    #[serde(skip)]
+    by_access: BTreeSet<(u64, String)>,
+}
+
+impl CacheIndex {
+    fn from_entries(entries: HashMap<String, CacheEntry>, counter: u64) -> Self {
+        let mut index = Self {
+            counter,
+            ..Default::default()
+        };
+        for (key, entry) in entries {
+            index.insert(key, entry);
+        }
+        index.changed.clear();
+        index
+    }
+
+    fn replay(&mut self, record: JournalRecord) {
+        match record {
+            JournalRecord::Insert { key, entry } => {
+                self.counter = self.counter.max(entry.accessed);
+                self.remove(&key);
+                self.insert(key, entry);
+            }
+            JournalRecord::Remove { key } => {
+                self.remove(&key);
+            }
+        }
+        self.journal_len += 1;
+    }
+
+    // Other keys can hash to the same file name, the next free one is used
+    // for them.
+    fn file_name(&self, key: &str) -> String {
+        let hash = format!("{:016x}", fnv1a(key.as_bytes()));
+        if !self.files.contains(&hash) {
+            return hash;
+        }
+        (1..)
+            .map(|i| format!("{}-{}", hash, i))
+            .find(|file| !self.files.contains(file))
+            .unwrap()
+    }
+
+    fn touch(&mut self, key: &str) {
+        self.counter += 1;
+        let counter = self.counter;
+        if let Some(entry) = self.entries.get_mut(key) {
+            if !entry.pinned {
+                self.by_access.remove(&(entry.accessed, key.to_owned()));
+                self.by_access.insert((counter, key.to_owned()));
+            }
+            entry.accessed = counter;
+            self.changed.insert(key.to_owned());
+        }
+    }
+
+    // Takes the least recently used entries out of the index and returns
+    // their files.
+    fn evict(&mut self, max_size: u64) -> Vec<String> {
+        let mut files = Vec::new();
+        while self.size > max_size {
+            let key = match self.oldest() {
+                Some(key) => key,
+                None => break,
+            };
+            if let Some(entry) = self.remove(&key) {
+                files.push(entry.file);
+            }
+        }
+        files
+    }
+
+    // The whole index, replacing the journal.
+    fn snapshot(&mut self) -> Result<Vec<u8>> {
+        let data = serde_json::to_vec(self)?;
+        self.changed.clear();
+        self.journal_len = 0;
+        Ok(data)
+    }
+
+    // The least recently used key that isn't pinned.
+    fn oldest(&self) -> Option<String> {
+        self.by_access.iter().next().map(|(_, key)| key.clone())
+    }
+
+    fn insert(&mut self, key: String, entry: CacheEntry) {
+        if entry.pinned {
+            self.pinned_size += entry.size;
+        } else {
+            self.size += entry.size;
+            self.by_access.insert((entry.accessed, key.clone()));
+        }
+        self.files.insert(entry.file.clone());
+        self.changed.insert(key.clone());
+        self.entries.insert(key, entry);
+    }
+
+    fn remove(&mut self, key: &str) -> Option<CacheEntry> {
+        let entry = self.entries.remove(key)?;
+        if entry.pinned {
+            self.pinned_size -= entry.size;
+        } else {
+            self.size -= entry.size;
+            self.by_access.remove(&(entry.accessed, key.to_owned()));
+        }
+        self.files.remove(&entry.file);
+        self.changed.insert(key.to_owned());
+        Some(entry)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct CachedResource {
+    pub data: Vec<u8>,
+    pub expires: Option<SystemTime>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub no_content: bool,
+}
+
+impl CachedResource {
+    pub fn is_fresh(&self, now: SystemTime) -> bool {
+        CacheHeaders::is_fresh(self.expires, now)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ResourceCache {
+    path: PathBuf,
+    max_size: u64,
+    // Only held while the index changes, files are read and written on the
+    // blocking threads without it.
+    index: Arc<Mutex<CacheIndex>>,
+    // Held while saving, so records reach the journal in the order they were
+    // taken from the index.
+    journal: Arc<Mutex<()>>,
+}
+
+impl ResourceCache {
+    pub fn open(path: &Path, max_size: u64) -> Result<Self> {
+        fs::create_dir_all(path)?;
+        let mut index = fs::read(path.join(INDEX_FILE))
+            .ok()
+            .and_then(|data| serde_json::from_slice::<CacheIndex>(&data).ok())
+            .map(|index| CacheIndex::from_entries(index.entries, index.counter))
+            .unwrap_or_default();
+        // A record cut short by a crash ends the journal.
+        if let Ok(journal) = fs::read(path.join(JOURNAL_FILE)) {
+            journal
+                .split(|byte| *byte == b'\n')
+                .map_while(|line| serde_json::from_slice::<JournalRecord>(line).ok())
+                .for_each(|record| index.replay(record));
+        }
+        index.changed.clear();
+
+        let cache = Self {
+            path: path.to_owned(),
+            max_size,
+            index: Arc::new(Mutex::new(index)),
+            journal: Arc::new(Mutex::new(())),
+        };
+        {
+            let mut index = cache.index.lock().unwrap();
+            remove_files(path, index.evict(max_size));
+            write_index(path, index.snapshot()?)?;
+        }
+        Ok(cache)
+    }
+
+    pub async fn get(&self, url: &str) -> Option<CachedResource> {
+        let key = strip_access_token(url);
+        let entry = self.index.lock().unwrap().entries.get(&key)?.clone();
+
+        // Files start with the key they were written for.
+        let file = self.path.join(&entry.file);
+        let data = blocking(move || fs::read(file))
+            .await
+            .ok()
+            .and_then(|data| data.ok())
+            .filter(|data| data.starts_with(key.as_bytes()) && data.get(key.len()) == Some(&b'\n'))
+            .map(|mut data| data.split_off(key.len() + 1));
+
+        let save = {
+            let mut index = self.index.lock().unwrap();
+            // The entry may have been replaced while its file was read, the
+            // new one is left alone.
+            let current = index.entries.get(&key).map(|current| current.file.as_str());
+            match &data {
+                _ if current != Some(entry.file.as_str()) => false,
+                Some(_) => {
+                    index.touch(&key);
+                    index.changed.len() >= TOUCH_BATCH
+                }
+                None => {
+                    index.remove(&key);
+                    true
+                }
+            }
+        };
+        if save {
+            self.save().await.ok();
+        }
+
+        Some(CachedResource {
+            data: data?,
+            expires: entry.expires.map(from_timestamp),
+            etag: entry.etag,
+            last_modified: entry.last_modified,
+            no_content: entry.no_content,
+        })
+    }
+
+    pub async fn put(&self, url: &str, data: &[u8], headers: &CacheHeaders) -> Result<()> {
+        let key = strip_access_token(url);
+        let removed = {
+            let mut index = self.index.lock().unwrap();
+            let pinned = match index.entries.get(&key) {
+                Some(entry) => entry.pinned,
+                None => false,
+            };
+            if pinned || (!headers.no_store && data.len() as u64 <= self.max_size) {
+                None
+            } else {
+                Some(index.remove(&key))
+            }
+        };
+
+        match removed {
+            // Not cached, a previous copy is dropped.
+            Some(removed) => {
+                let files = removed.into_iter().map(|entry| entry.file).collect();
+                let path = self.path.clone();
+                blocking(move || remove_files(&path, files)).await?;
+            }
+            None => self.write_entry(key, Some(data), headers, false).await?,
+        }
+        self.save().await
+    }
+
+    // Pinned resources belong to offline regions: they are never evicted and
+    // don't count towards the size budget of the ambient cache.
+    pub async fn pin(&self, url: &str, data: &[u8]) -> Result<()> {
+        let key = strip_access_token(url);
+        let pinned = {
+            let mut index = self.index.lock().unwrap();
+            match index.remove(&key) {
+                Some(mut entry) => {
+                    entry.pinned = true;
+                    index.insert(key.clone(), entry);
+                    true
+                }
+                None => false,
+            }
+        };
+
+        if !pinned {
+            self.write_entry(key, Some(data), &CacheHeaders::default(), true)
+                .await?;
+        }
+        self.save().await
+    }
+
+    // Offline regions remember the tiles missing from sparse tilesets, so
+    // they aren't requested again.
+    pub async fn pin_no_content(&self, url: &str) -> Result<()> {
+        let key = strip_access_token(url);
+        self.write_entry(key, None, &CacheHeaders::default(), true)
+            .await?;
+        self.save().await
+    }
+
+    pub fn is_pinned(&self, url: &str) -> bool {
+        let index = self.index.lock().unwrap();
+        match index.entries.get(&strip_access_token(url)) {
+            Some(entry) => entry.pinned,
+            None => false,
+        }
+    }
+
+    pub async fn refresh(&self, url: &str, headers: &CacheHeaders) -> Result<Option<SystemTime>> {
+        let key = strip_access_token(url);
+        let expires = {
+            let mut index = self.index.lock().unwrap();
+            let entry = match index.entries.get_mut(&key) {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+
+            entry.expires = headers.expires.map(to_timestamp);
+            if headers.etag.is_some() {
+                entry.etag = headers.etag.clone();
+            }
+            if headers.last_modified.is_some() {
+                entry.last_modified = headers.last_modified.clone();
+            }
+            let expires = entry.expires.map(from_timestamp);
+            index.changed.insert(key);
+            expires
+        };
+
+        self.save().await?;
+        Ok(expires)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.index.lock().unwrap().size
+    }
+
+    pub fn pinned_size(&self) -> u64 {
+        self.index.lock().unwrap().pinned_size
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    // The file name is reserved before the file is written, the entry only
+    // points to it once it is complete. Without data the entry is a no
+    // content marker.
+    async fn write_entry(
+        &self,
+        key: String,
+        data: Option<&[u8]>,
+        headers: &CacheHeaders,
+        pinned: bool,
+    ) -> Result<()> {
+        let file = {
+            let mut index = self.index.lock().unwrap();
+            let file = index.file_name(&key);
+            index.files.insert(file.clone());
+            file
+        };
+
+        let no_content = data.is_none();
+        let data = data.unwrap_or_default();
+        let mut contents = Vec::with_capacity(key.len() + 1 + data.len());
+        contents.extend_from_slice(key.as_bytes());
+        contents.push(b'\n');
+        contents.extend_from_slice(data);
+        let path = self.path.join(&file);
+        let written = blocking(move || fs::write(path, contents)).await;
+        if let Err(e) = written.and_then(|written| Ok(written?)) {
+            self.index.lock().unwrap().files.remove(&file);
+            return Err(e);
+        }
+
+        let removed = {
+            let mut index = self.index.lock().unwrap();
+            let mut removed = Vec::new();
+            let mut pinned = pinned;
+            if let Some(entry) = index.remove(&key) {
+                pinned |= entry.pinned;
+                removed.push(entry.file);
+            }
+            index.counter += 1;
+            let entry = CacheEntry {
+                file,
+                size: data.len() as u64,
+                etag: headers.etag.clone(),
+                last_modified: headers.last_modified.clone(),
+                expires: headers.expires.map(to_timestamp),
+                accessed: index.counter,
+                pinned,
+                no_content,
+            };
+            index.insert(key, entry);
+            removed.extend(index.evict(self.max_size));
+            removed
+        };
+
+        let path = self.path.clone();
+        blocking(move || remove_files(&path, removed)).await
+    }
+
+    async fn save(&self) -> Result<()> {
+        let (path, index, journal) = (self.path.clone(), self.index.clone(), self.journal.clone());
+        blocking(move || save(&path, &index, &journal)).await?
+    }
+}
+
+impl Drop for ResourceCache {
+    // Reads since the last save would be lost otherwise.
+    fn drop(&mut self) {
+        save(&self.path, &self.index, &self.journal).ok();
+    }
+}
+
+// Runs file system work on the blocking threads, off the async tasks.
+async fn blocking<F, T>(f: F) -> Result<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    Ok(tokio::task::spawn_blocking(f).await?)
+}
+
+// Replaces the index and the journal folded into it.
+fn write_index(path: &Path, data: Vec<u8>) -> Result<()> {
+    let tmp = path.join(format!("{}.tmp", INDEX_FILE));
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path.join(INDEX_FILE))?;
+    fs::remove_file(path.join(JOURNAL_FILE)).ok();
+    Ok(())
+}
+
+fn remove_files(path: &Path, files: Vec<String>) {
+    for file in files {
+        fs::remove_file(path.join(file)).ok();
+    }
+}
+
+// Appends the changed entries to the journal instead of rewriting the whole
+// index for every resource, until the journal gets too long.
+fn save(path: &Path, index: &Mutex<CacheIndex>, journal: &Mutex<()>) -> Result<()> {
+    let _journal = journal.lock().unwrap();
+    let mut index = index.lock().unwrap();
+    if index.changed.is_empty() {
+        return Ok(());
+    }
+
+    if index.journal_len + index.changed.len() > JOURNAL_LIMIT {
+        let data = index.snapshot()?;
+        drop(index);
+        return write_index(path, data);
+    }
+
+    let mut records = Vec::new();
+    let changed = index.changed.drain().collect::<Vec<_>>();
+    for key in changed {
+        let record = match index.entries.get(&key) {
+            Some(entry) => JournalRecord::Insert {
+                key,
+                entry: entry.clone(),
+            },
+            None => JournalRecord::Remove { key },
+        };
+        serde_json::to_writer(&mut records, &record)?;
+        records.push(b'\n');
+        index.journal_len += 1;
+    }
+    drop(index);
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path.join(JOURNAL_FILE))?
+        .write_all(&records)?;
+    Ok(())
+}
+
+pub(crate) fn strip_access_token(url: &str) -> String {
+    let mut parsed = match Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(_) => return url.to_owned(),
+    };
+
+    // Access tokens rotate, the resource behind the url doesn't.
+    let query = parsed
+        .query_pairs()
+        .filter(|(name, _)| name != "access_token")
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+    if query.is_empty() {
+        parsed.set_query(None);
+    } else {
+        parsed.query_pairs_mut().clear().extend_pairs(query);
+    }
+    parsed.to_string()
+}
+
+fn to_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn from_timestamp(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::unique_id;
+
+    fn temp_cache(max_size: u64) -> (ResourceCache, PathBuf) {
+        let path = std::env::temp_dir().join(format!(
+            "mapbox_maps_cache_{}_{}",
+            std::process::id(),
+            unique_id()
+        ));
+        (ResourceCache::open(&path, max_size).unwrap(), path)
+    }
+
+    #[test]
+    fn strip_access_token_from_url() {
+        assert_eq!(
+            strip_access_token("https://api.mapbox.com/v4/a.json?access_token=pk.1&secure"),
+            "https://api.mapbox.com/v4/a.json?secure="
+        );
+        assert_eq!(
+            strip_access_token("https://api.mapbox.com/v4/a.json?access_token=pk.1"),
+            "https://api.mapbox.com/v4/a.json"
+        );
+    }
+
+    #[tokio::test]
+    async fn resource_cache_put_get() {
+        let (cache, path) = temp_cache(1024);
+        let headers = CacheHeaders {
+            etag: Some("\"1\"".to_owned()),
+            ..Default::default()
+        };
+        cache
+            .put("https://a.com/1?access_token=x", b"data", &headers)
+            .await
+            .unwrap();
+
+        let cached = cache.get("https://a.com/1?access_token=y").await.unwrap();
+        assert_eq!(cached.data, b"data");
+        assert_eq!(cached.etag, Some("\"1\"".to_owned()));
+        assert!(!cached.is_fresh(SystemTime::now()));
+
+        let reopened = ResourceCache::open(&path, 1024).unwrap();
+        assert_eq!(reopened.size(), 4);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resource_cache_journal() {
+        let (cache, path) = temp_cache(1024);
+        let headers = CacheHeaders::default();
+        cache
+            .put("https://a.com/1", b"1111", &headers)
+            .await
+            .unwrap();
+        cache
+            .put("https://a.com/2", b"2222", &headers)
+            .await
+            .unwrap();
+        cache.pin("https://a.com/1", b"1111").await.unwrap();
+        let index = fs::read(path.join(INDEX_FILE)).unwrap();
+        assert!(cache.get("https://a.com/2").await.is_some());
+
+        // The index itself is only rewritten when reopening.
+        assert_eq!(fs::read(path.join(INDEX_FILE)).unwrap(), index);
+        drop(cache);
+        let reopened = ResourceCache::open(&path, 1024).unwrap();
+        assert!(!path.join(JOURNAL_FILE).exists());
+        assert!(reopened.is_pinned("https://a.com/1"));
+        assert_eq!(reopened.get("https://a.com/2").await.unwrap().data, b"2222");
+        assert_eq!(reopened.size(), 4);
+        assert_eq!(reopened.pinned_size(), 4);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resource_cache_file_name_collision() {
+        let (cache, path) = temp_cache(1024);
+        let headers = CacheHeaders::default();
+        cache
+            .put("https://a.com/1", b"1111", &headers)
+            .await
+            .unwrap();
+        // Pretend the first url hashes to the file name of the second one.
+        let collision = format!("{:016x}", fnv1a("https://a.com/2".as_bytes()));
+        {
+            let mut index = cache.index.lock().unwrap();
+            let mut entry = index.remove("https://a.com/1").unwrap();
+            fs::rename(path.join(&entry.file), path.join(&collision)).unwrap();
+            entry.file = collision.clone();
+            index.insert("https://a.com/1".to_owned(), entry);
+        }
+        cache
+            .put("https://a.com/2", b"2222", &headers)
+            .await
+            .unwrap();
+        assert_eq!(cache.get("https://a.com/1").await.unwrap().data, b"1111");
+        assert_eq!(cache.get("https://a.com/2").await.unwrap().data, b"2222");
+
+        // A file holding the data of another url is a miss.
+        fs::copy(path.join(format!("{}-1", collision)), path.join(&collision)).unwrap();
+        assert!(cache.get("https://a.com/1").await.is_none());
+        assert_eq!(cache.get("https://a.com/2").await.unwrap().data, b"2222");
+        assert_eq!(cache.size(), 4);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resource_cache_evicts_least_recently_used() {
+        let (cache, path) = temp_cache(8);
+        let headers = CacheHeaders::default();
+        cache
+            .put("https://a.com/1", b"1111", &headers)
+            .await
+            .unwrap();
+        cache
+            .put("https://a.com/2", b"2222", &headers)
+            .await
+            .unwrap();
+        assert!(cache.get("https://a.com/1").await.is_some());
+        cache
+            .put("https://a.com/3", b"3333", &headers)
+            .await
+            .unwrap();
+
+        assert!(cache.get("https://a.com/1").await.is_some());
+        assert!(cache.get("https://a.com/2").await.is_none());
+        assert!(cache.get("https://a.com/3").await.is_some());
+        assert_eq!(cache.size(), 8);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resource_cache_index_lookups() {
+        let (cache, path) = temp_cache(8);
+        let headers = CacheHeaders::default();
+        cache
+            .put("https://a.com/1", b"1111", &headers)
+            .await
+            .unwrap();
+        cache
+            .put("https://a.com/2", b"2222", &headers)
+            .await
+            .unwrap();
+        cache.pin("https://a.com/2", b"2222").await.unwrap();
+        cache
+            .put("https://a.com/3", b"3333", &headers)
+            .await
+            .unwrap();
+        assert!(cache.get("https://a.com/1").await.is_some());
+
+        // Pinned entries are never up for eviction, every entry has a file.
+        let index = cache.index.lock().unwrap();
+        let keys = index
+            .by_access
+            .iter()
+            .map(|(_, key)| key.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["https://a.com/3", "https://a.com/1"]);
+        assert_eq!(index.oldest().as_deref(), Some("https://a.com/3"));
+        assert_eq!(index.files.len(), 3);
+        assert!(index
+            .entries
+            .values()
+            .all(|entry| index.files.contains(&entry.file)));
+        drop(index);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resource_cache_access_order_survives_reopen() {
+        let (cache, path) = temp_cache(8);
+        let headers = CacheHeaders::default();
+        cache
+            .put("https://a.com/1", b"1111", &headers)
+            .await
+            .unwrap();
+        cache
+            .put("https://a.com/2", b"2222", &headers)
+            .await
+            .unwrap();
+        assert!(cache.get("https://a.com/1").await.is_some());
+        drop(cache);
+
+        // Read after the second one was written, the first one is kept.
+        let reopened = ResourceCache::open(&path, 8).unwrap();
+        reopened
+            .put("https://a.com/3", b"3333", &headers)
+            .await
+            .unwrap();
+        assert!(reopened.get("https://a.com/1").await.is_some());
+        assert!(reopened.get("https://a.com/2").await.is_none());
+        drop(reopened);
+
+        fs::remove_dir_all(path).unwrap();
+
+        // Touches are also saved in batches while the cache is open.
+        let (cache, path) = temp_cache(1024);
+        let urls = (0..TOUCH_BATCH)
+            .map(|i| format!("https://a.com/{}", i))
+            .collect::<Vec<_>>();
+        for url in &urls {
+            cache.put(url, b"1", &headers).await.unwrap();
+        }
+        for url in &urls[1..] {
+            cache.get(url).await.unwrap();
+        }
+        assert_eq!(cache.index.lock().unwrap().changed.len(), TOUCH_BATCH - 1);
+        cache.get(&urls[0]).await.unwrap();
+        assert!(cache.index.lock().unwrap().changed.is_empty());
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resource_cache_concurrent_access() {
+        let (cache, path) = temp_cache(1024);
+        let headers = CacheHeaders::default();
+        let urls = (0..20)
+            .map(|i| format!("https://a.com/{}", i))
+            .collect::<Vec<_>>();
+        futures::future::join_all(urls.iter().map(|url| cache.put(url, b"1111", &headers)))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        // Writing a url again while it is read.
+        let (read, written) =
+            futures::join!(cache.get(&urls[0]), cache.put(&urls[0], b"2222", &headers));
+        written.unwrap();
+        if let Some(read) = read {
+            assert!(read.data == b"1111" || read.data == b"2222");
+        }
+        let reads = futures::future::join_all(urls.iter().map(|url| cache.get(url))).await;
+        assert!(reads.iter().all(Option::is_some));
+        assert_eq!(reads[0].as_ref().unwrap().data, b"2222");
+        assert_eq!(cache.size(), 80);
+        drop(cache);
+
+        let reopened = ResourceCache::open(&path, 1024).unwrap();
+        assert_eq!(reopened.size(), 80);
+        assert_eq!(reopened.get(&urls[0]).await.unwrap().data, b"2222");
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resource_cache_keeps_pinned() {
+        let (cache, path) = temp_cache(4);
+        let headers = CacheHeaders::default();
+        cache.pin("https://a.com/1", b"11111111").await.unwrap();
+        cache
+            .put("https://a.com/2", b"2222", &headers)
+            .await
+            .unwrap();
+        cache
+            .put("https://a.com/3", b"3333", &headers)
+            .await
+            .unwrap();
+
+        assert!(cache.is_pinned("https://a.com/1"));
+        assert!(cache.get("https://a.com/1").await.is_some());
+        assert!(cache.get("https://a.com/2").await.is_none());
+        assert_eq!(cache.size(), 4);
+        assert_eq!(cache.pinned_size(), 8);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resource_cache_skips_no_store() {
+        let (cache, path) = temp_cache(1024);
+        let headers = CacheHeaders {
+            no_store: true,
+            ..Default::default()
+        };
+        cache
+            .put("https://a.com/1", b"data", &headers)
+            .await
+            .unwrap();
+        assert!(cache.get("https://a.com/1").await.is_none());
+        fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+use reqwest::header::{HeaderMap, HeaderName, CACHE_CONTROL, ETAG, EXPIRES, LAST_MODIFIED};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone)]
+pub(crate) struct Response {
+    pub data: Vec<u8>,
+    pub expires: Option<SystemTime>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct CacheHeaders {
+    pub expires: Option<SystemTime>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub no_store: bool,
+}
+
+impl CacheHeaders {
+    pub fn from_headers(headers: &HeaderMap, now: SystemTime) -> Self {
+        let mut cache_headers = CacheHeaders {
+            expires: header_value(headers, EXPIRES)
+                .and_then(|value| httpdate::parse_http_date(&value).ok()),
+            etag: header_value(headers, ETAG),
+            last_modified: header_value(headers, LAST_MODIFIED),
+            no_store: false,
+        };
+
+        if let Some(cache_control) = header_value(headers, CACHE_CONTROL) {
+            cache_headers.apply_cache_control(&cache_control, now);
+        }
+
+        cache_headers
+    }
+
+    fn apply_cache_control(&mut self, cache_control: &str, now: SystemTime) {
+        let mut max_age = None;
+        let mut shared_max_age = None;
+        let mut no_cache = false;
+        for directive in cache_control.split(',') {
+            let directive = directive.trim().to_ascii_lowercase();
+            if directive == "no-store" {
+                self.no_store = true;
+            } else if directive == "no-cache" {
+                no_cache = true;
+            } else if let Some(value) = directive.strip_prefix("s-maxage=") {
+                shared_max_age = value.trim_matches('"').parse::<u64>().ok();
+            } else if let Some(value) = directive.strip_prefix("max-age=") {
+                max_age = value.trim_matches('"').parse::<u64>().ok();
+            }
+        }
+
+        // Cache-Control takes precedence over the Expires header.
+        let max_age = if no_cache {
+            Some(0)
+        } else {
+            shared_max_age.or(max_age)
+        };
+        if let Some(max_age) = max_age {
+            self.expires = Some(now + Duration::from_secs(max_age));
+        }
+    }
+
+    pub fn is_fresh(expires: Option<SystemTime>, now: SystemTime) -> bool {
+        match expires {
+            Some(expires) => expires > now,
+            None => false,
+        }
+    }
+}
+
+fn header_value(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(HeaderName, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(name.clone(), HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn cache_headers_max_age_overrides_expires() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let h = headers(&[
+            (CACHE_CONTROL, "public, max-age=60"),
+            (EXPIRES, "Thu, 01 Jan 1970 00:00:10 GMT"),
+            (ETAG, "\"abc\""),
+        ]);
+        let cache_headers = CacheHeaders::from_headers(&h, now);
+        assert_eq!(cache_headers.expires, Some(now + Duration::from_secs(60)));
+        assert_eq!(cache_headers.etag, Some("\"abc\"".to_owned()));
+        assert!(!cache_headers.no_store);
+    }
+
+    #[test]
+    fn cache_headers_expires() {
+        let now = SystemTime::UNIX_EPOCH;
+        let h = headers(&[(EXPIRES, "Thu, 01 Jan 1970 00:00:10 GMT")]);
+        let cache_headers = CacheHeaders::from_headers(&h, now);
+        assert_eq!(
+            cache_headers.expires,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn cache_headers_no_cache_and_no_store() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let h = headers(&[(CACHE_CONTROL, "no-cache, no-store")]);
+        let cache_headers = CacheHeaders::from_headers(&h, now);
+        assert_eq!(cache_headers.expires, Some(now));
+        assert!(cache_headers.no_store);
+        assert!(!CacheHeaders::is_fresh(cache_headers.expires, now));
+    }
+}
@@ -0,0 +1,80 @@
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use std::time::{Duration, SystemTime};
+
+const MAX_RETRIES: u32 = 4;
+const BASE_DELAY: Duration = Duration::from_millis(250);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+pub(crate) fn retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    match value.parse::<u64>() {
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => {
+            let date = httpdate::parse_http_date(value).ok()?;
+            Some(date.duration_since(now).unwrap_or_default())
+        }
+    }
+}
+
+// Returns `None` once the request should no longer be retried.
+pub(crate) fn backoff(attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+    if attempt >= MAX_RETRIES {
+        return None;
+    }
+
+    if let Some(retry_after) = retry_after {
+        return if retry_after <= MAX_DELAY {
+            Some(retry_after)
+        } else {
+            None
+        };
+    }
+
+    let delay = BASE_DELAY
+        .checked_mul(1 << attempt)
+        .unwrap_or(MAX_DELAY)
+        .min(MAX_DELAY);
+    // Equal jitter: half of the delay is fixed, the other half is random.
+    let half = delay / 2;
+    Some(half + half.mul_f64(rand::thread_rng().gen::<f64>()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn backoff_grows_exponentially() {
+        for attempt in 0..MAX_RETRIES {
+            let delay = backoff(attempt, None).unwrap();
+            let max = BASE_DELAY * (1 << attempt);
+            assert!(delay >= max / 2 && delay <= max);
+        }
+        assert_eq!(backoff(MAX_RETRIES, None), None);
+    }
+
+    #[test]
+    fn backoff_honors_retry_after() {
+        assert_eq!(
+            backoff(0, Some(Duration::from_secs(3))),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(backoff(0, Some(Duration::from_secs(3600))), None);
+    }
+
+    #[test]
+    fn retry_after_seconds_and_date() {
+        let now = SystemTime::UNIX_EPOCH;
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(120)));
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Thu, 01 Jan 1970 00:00:10 GMT"),
+        );
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(10)));
+    }
+}
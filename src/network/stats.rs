@@ -0,0 +1,161 @@
+use super::request::ResourceKind;
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    Hit,
+    Revalidated,
+    Stale,
+    Miss,
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestEvent {
+    pub url: String,
+    pub kind: ResourceKind,
+    pub source: Option<String>,
+    pub status: Option<u16>,
+    pub bytes: usize,
+    pub duration: Duration,
+    pub cache: CacheStatus,
+    pub failed: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequestStats {
+    pub requests: u64,
+    // Served from the cache without contacting the server.
+    pub cache_hits: u64,
+    // Cached copies the server confirmed are still current.
+    pub revalidations: u64,
+    // Cached copies served because the server couldn't be reached.
+    pub stale: u64,
+    pub failures: u64,
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+impl RequestStats {
+    fn record(&mut self, event: &RequestEvent) {
+        self.requests += 1;
+        match event.cache {
+            CacheStatus::Hit => self.cache_hits += 1,
+            CacheStatus::Revalidated => self.revalidations += 1,
+            CacheStatus::Stale => self.stale += 1,
+            CacheStatus::Miss => {}
+        }
+        if event.failed {
+            self.failures += 1;
+        }
+        self.bytes += event.bytes as u64;
+        self.duration += event.duration;
+    }
+
+    pub fn average_duration(&self) -> Duration {
+        if self.requests == 0 {
+            return Duration::default();
+        }
+        self.duration.div_f64(self.requests as f64)
+    }
+
+    pub fn cache_hit_ratio(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.cache_hits as f64 / self.requests as f64
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NetworkStats {
+    pub total: RequestStats,
+    pub kinds: HashMap<ResourceKind, RequestStats>,
+    pub sources: HashMap<String, RequestStats>,
+}
+
+impl NetworkStats {
+    pub(crate) fn record(&mut self, event: &RequestEvent) {
+        self.total.record(event);
+        self.kinds.entry(event.kind).or_default().record(event);
+        if let Some(source) = &event.source {
+            self.sources
+                .entry(source.clone())
+                .or_default()
+                .record(event);
+        }
+    }
+}
+
+type ObserverFn = dyn Fn(&RequestEvent) + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct RequestObserver(Arc<ObserverFn>);
+
+impl RequestObserver {
+    pub fn new<F>(observer: F) -> Self
+    where
+        F: Fn(&RequestEvent) + Send + Sync + 'static,
+    {
+        Self(Arc::new(observer))
+    }
+
+    pub fn notify(&self, event: &RequestEvent) {
+        (self.0)(event)
+    }
+}
+
+impl Debug for RequestObserver {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("RequestObserver")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(source: Option<&str>, cache: CacheStatus, bytes: usize, millis: u64) -> RequestEvent {
+        RequestEvent {
+            url: "https://example.com/0/0/0.pbf".to_owned(),
+            kind: ResourceKind::Tile,
+            source: source.map(|source| source.to_owned()),
+            status: Some(200),
+            bytes,
+            duration: Duration::from_millis(millis),
+            cache,
+            failed: false,
+        }
+    }
+
+    #[test]
+    fn network_stats_per_source() {
+        let mut stats = NetworkStats::default();
+        stats.record(&event(Some("streets"), CacheStatus::Miss, 100, 30));
+        stats.record(&event(Some("streets"), CacheStatus::Hit, 100, 10));
+        stats.record(&event(Some("terrain"), CacheStatus::Miss, 50, 20));
+        stats.record(&event(None, CacheStatus::Revalidated, 10, 20));
+        stats.record(&event(Some("terrain"), CacheStatus::Stale, 50, 20));
+
+        assert_eq!(stats.total.requests, 5);
+        assert_eq!(stats.total.cache_hits, 1);
+        assert_eq!(stats.total.revalidations, 1);
+        assert_eq!(stats.total.stale, 1);
+        assert_eq!(stats.total.bytes, 310);
+        assert_eq!(stats.total.average_duration(), Duration::from_millis(20));
+        assert_eq!(stats.kinds[&ResourceKind::Tile].requests, 5);
+
+        let streets = &stats.sources["streets"];
+        assert_eq!(streets.requests, 2);
+        assert_eq!(streets.cache_hit_ratio(), 0.5);
+        assert_eq!(stats.sources["terrain"].cache_hits, 0);
+        assert_eq!(stats.sources["terrain"].stale, 1);
+        assert_eq!(stats.sources.len(), 2);
+        assert_eq!(
+            RequestStats::default().average_duration(),
+            Duration::default()
+        );
+    }
+}
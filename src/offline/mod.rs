@@ -0,0 +1,3 @@
+mod offline_region;
+
+pub use offline_region::{OfflineProgress, OfflineRegion};
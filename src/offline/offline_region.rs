@@ -0,0 +1,519 @@
+use crate::geo::{mercator_x_from_lng, mercator_y_from_lat, LngLatBounds};
+use crate::network::{NetworkError, NetworkManager, ResourceKind};
+use crate::source::{CanonicalTileId, TileSet, TileSetOptions};
+use crate::style_spec;
+use eyre::{eyre, Result};
+use futures::stream::{self, StreamExt};
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+const GLYPH_RANGE_SIZE: u32 = 256;
+const GLYPH_RANGE_COUNT: u32 = 256;
+const DEFAULT_FONT_STACK: &str = "Open Sans Regular,Arial Unicode MS Regular";
+const EXPRESSION_NAMES: [&str; 8] = [
+    "zoom",
+    "get",
+    "has",
+    "properties",
+    "geometry-type",
+    "id",
+    "feature-state",
+    "literal",
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct OfflineProgress {
+    pub completed_resources: usize,
+    pub required_resources: usize,
+    pub completed_bytes: u64,
+    pub failed_resources: usize,
+}
+
+impl OfflineProgress {
+    pub fn is_complete(&self) -> bool {
+        self.failed_resources == 0 && self.completed_resources == self.required_resources
+    }
+
+    fn complete(&mut self, bytes: usize) {
+        self.completed_resources += 1;
+        self.completed_bytes += bytes as u64;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OfflineRegion {
+    style: String,
+    bounds: LngLatBounds<f64>,
+    min_zoom: f32,
+    max_zoom: f32,
+    pixel_ratio: f32,
+}
+
+struct TiledSource<'a> {
+    options: TileSetOptions<'a>,
+    tile_size: f32,
+    raster: bool,
+}
+
+impl OfflineRegion {
+    pub fn new(style: &str, bounds: [f64; 4], min_zoom: f32, max_zoom: f32) -> Result<Self> {
+        if min_zoom.is_nan() || max_zoom.is_nan() || min_zoom > max_zoom {
+            return Err(eyre!(
+                "Invalid zoom range {}..{} for offline region",
+                min_zoom,
+                max_zoom
+            ));
+        }
+        Ok(Self {
+            style: style.to_owned(),
+            bounds: LngLatBounds::convert(&bounds),
+            min_zoom,
+            max_zoom,
+            pixel_ratio: 1.0,
+        })
+    }
+
+    pub fn style(&self) -> &str {
+        &self.style
+    }
+
+    pub fn min_zoom(&self) -> f32 {
+        self.min_zoom
+    }
+
+    pub fn max_zoom(&self) -> f32 {
+        self.max_zoom
+    }
+
+    pub fn pixel_ratio(&self) -> f32 {
+        self.pixel_ratio
+    }
+
+    pub fn set_pixel_ratio(&mut self, pixel_ratio: f32) {
+        self.pixel_ratio = pixel_ratio;
+    }
+
+    // Resources already stored by a previous, interrupted download are
+    // skipped, so calling this again resumes where it stopped.
+    pub(crate) async fn download<F>(
+        &self,
+        nm: &NetworkManager,
+        mut on_progress: F,
+    ) -> Result<OfflineProgress>
+    where
+        F: FnMut(&OfflineProgress),
+    {
+        let mut progress = OfflineProgress {
+            required_resources: 1,
+            ..Default::default()
+        };
+        let style_data = nm
+            .download(&nm.style_url(&self.style), ResourceKind::Style, None)
+            .await?;
+        progress.complete(style_data.len());
+        on_progress(&progress);
+
+        let style_json = serde_json::from_slice::<Value>(&style_data)?;
+        let style = serde_json::from_value::<style_spec::Style>(style_json.clone())?;
+
+        let mut urls = Vec::new();
+        for (name, source) in &style.sources {
+            let source = match tiled_source(source) {
+                Some(source) => source,
+                None => {
+                    if let style_spec::Source::GeoJSON(geojson) = source {
+                        if let Some(Value::String(url)) = &geojson.data {
+                            urls.push((url.clone(), ResourceKind::Source, Some(name.clone())));
+                        }
+                    }
+                    continue;
+                }
+            };
+            urls.extend(self.tile_urls(nm, name, &source, &mut progress).await?);
+            on_progress(&progress);
+        }
+
+        if let Some(sprite) = &style.sprite {
+            let ratio = if self.pixel_ratio > 1.0 { "@2x" } else { "" };
+            urls.push((
+                nm.sprite_url(sprite, ratio, "json"),
+                ResourceKind::SpriteJson,
+                None,
+            ));
+            urls.push((
+                nm.sprite_url(sprite, ratio, "png"),
+                ResourceKind::SpriteImage,
+                None,
+            ));
+        }
+
+        if let Some(glyphs) = &style.glyphs {
+            for font_stack in font_stacks(&style_json) {
+                for i in 0..GLYPH_RANGE_COUNT {
+                    let start = i * GLYPH_RANGE_SIZE;
+                    let range = format!("{}-{}", start, start + GLYPH_RANGE_SIZE - 1);
+                    urls.push((
+                        nm.glyphs_url(glyphs, &font_stack, &range),
+                        ResourceKind::Glyphs,
+                        None,
+                    ));
+                }
+            }
+        }
+
+        progress.required_resources += urls.len();
+        on_progress(&progress);
+
+        // As many downloads run at once as the network manager lets through
+        // to a host, most resources come from the same one.
+        let mut downloads = stream::iter(urls)
+            .map(|(url, kind, source)| async move {
+                if nm.is_downloaded(&url) {
+                    return Ok(Vec::new());
+                }
+                nm.download(&url, kind, source.as_deref()).await
+            })
+            .buffer_unordered(nm.max_requests_per_host());
+        while let Some(result) = downloads.next().await {
+            match result {
+                Ok(data) => progress.complete(data.len()),
+                // Missing tiles are stored by the network manager, other
+                // missing resources have nothing to store.
+                Err(e) if is_not_found(&e) => progress.complete(0),
+                // The request events have the details.
+                Err(_) => progress.failed_resources += 1,
+            }
+            on_progress(&progress);
+        }
+
+        if progress.failed_resources > 0 {
+            return Err(eyre!(
+                "{} of {} resources failed to download",
+                progress.failed_resources,
+                progress.required_resources
+            ));
+        }
+
+        Ok(progress)
+    }
+
+    async fn tile_urls(
+        &self,
+        nm: &NetworkManager,
+        name: &str,
+        source: &TiledSource<'_>,
+        progress: &mut OfflineProgress,
+    ) -> Result<Vec<(String, ResourceKind, Option<String>)>> {
+        let options = &source.options;
+        let tile_set = match (options.url, options.tiles) {
+            (Some(url), _) => {
+                progress.required_resources += 1;
+                let tilejson_url = nm.tilejson_url(url);
+                let tile_set = match nm
+                    .download(&tilejson_url, ResourceKind::Source, Some(name))
+                    .await
+                {
+                    Ok(data) => TileSet::from_tilejson(&data, options)
+                        .map(|tile_set| (tile_set, data.len())),
+                    Err(e) => Err(e),
+                };
+                match tile_set {
+                    Ok((tile_set, size)) => {
+                        progress.complete(size);
+                        tile_set
+                    }
+                    // The other sources can still be downloaded.
+                    Err(_) => {
+                        progress.failed_resources += 1;
+                        return Ok(vec![]);
+                    }
+                }
+            }
+            (None, Some(tiles)) => TileSet::from_tiles(tiles, options),
+            (None, None) => return Ok(vec![]),
+        };
+
+        if tile_set.tiles.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let zoom_offset = (512.0 / source.tile_size).log2();
+        let min_z = (self.min_zoom + zoom_offset).floor().max(tile_set.min_zoom) as u32;
+        let max_z = (self.max_zoom + zoom_offset).ceil().min(tile_set.max_zoom) as u32;
+
+        let mut urls = Vec::new();
+        for z in min_z..=max_z {
+            for tile_id in tile_cover(&self.bounds, z)
+                .into_iter()
+                .filter(|tile_id| tile_set.contains(tile_id))
+            {
+                let url = tile_id.url(&tile_set.tiles, Some(tile_set.scheme.clone()));
+                // The same URLs the sources request at runtime.
+                let url = if source.raster {
+                    nm.raster_tile_url(&url, source.tile_size as u32)
+                } else {
+                    nm.tile_url(&url)
+                };
+                urls.push((url, ResourceKind::Tile, Some(name.to_owned())));
+            }
+        }
+        Ok(urls)
+    }
+}
+
+fn is_not_found(e: &eyre::Report) -> bool {
+    matches!(
+        e.downcast_ref::<NetworkError>(),
+        Some(NetworkError::NotFound { .. })
+    )
+}
+
+fn tiled_source(source: &style_spec::Source) -> Option<TiledSource<'_>> {
+    match source {
+        style_spec::Source::Vector(vector) => Some(TiledSource {
+            options: TileSetOptions::from(vector),
+            tile_size: 512.0,
+            raster: false,
+        }),
+        style_spec::Source::Raster(raster) => Some(TiledSource {
+            options: TileSetOptions::from(raster),
+            tile_size: raster.tile_size,
+            raster: true,
+        }),
+        style_spec::Source::RasterDEM(raster_dem) => Some(TiledSource {
+            options: TileSetOptions::from(raster_dem),
+            tile_size: raster_dem.tile_size,
+            raster: true,
+        }),
+        _ => None,
+    }
+}
+
+fn tile_cover(bounds: &LngLatBounds<f64>, z: u32) -> Vec<CanonicalTileId> {
+    let tiles = 2u32.pow(z);
+    let to_tile = |v: f64| ((v * tiles as f64).floor().max(0.0) as u32).min(tiles - 1);
+
+    // A west bound greater than the east one, or an east bound past 180,
+    // crosses the antimeridian and covers both ends of the world.
+    let mut width = bounds.get_east() - bounds.get_west();
+    if width < 0.0 {
+        width += 360.0;
+    }
+    let west = (bounds.get_west() + 180.0).rem_euclid(360.0) - 180.0;
+    let east = west + width;
+    let x_ranges = if width >= 360.0 {
+        vec![(0, tiles - 1)]
+    } else if east <= 180.0 {
+        vec![(
+            to_tile(mercator_x_from_lng(west)),
+            to_tile(mercator_x_from_lng(east)),
+        )]
+    } else {
+        vec![
+            (to_tile(mercator_x_from_lng(west)), tiles - 1),
+            (0, to_tile(mercator_x_from_lng(east - 360.0))),
+        ]
+    };
+    let min_y = to_tile(mercator_y_from_lat(bounds.get_north()));
+    let max_y = to_tile(mercator_y_from_lat(bounds.get_south()));
+
+    let mut result = Vec::new();
+    for (min_x, max_x) in x_ranges {
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                result.push(CanonicalTileId::new(z, x, y));
+            }
+        }
+    }
+    result
+}
+
+fn font_stacks(style: &Value) -> BTreeSet<String> {
+    let mut stacks = BTreeSet::new();
+    let layers = match style.get("layers").and_then(Value::as_array) {
+        Some(layers) => layers,
+        None => return stacks,
+    };
+
+    for layer in layers {
+        if layer.get("type").and_then(Value::as_str) != Some("symbol") {
+            continue;
+        }
+        let layout = layer.get("layout");
+        match layout.and_then(|layout| layout.get("text-font")) {
+            Some(text_font) => collect_font_stacks(text_font, &mut stacks),
+            None => {
+                if layout.and_then(|layout| layout.get("text-field")).is_some() {
+                    stacks.insert(DEFAULT_FONT_STACK.to_owned());
+                }
+            }
+        }
+    }
+    stacks
+}
+
+fn collect_font_stacks(value: &Value, stacks: &mut BTreeSet<String>) {
+    match value {
+        Value::Array(values) => {
+            let fonts = values.iter().map(Value::as_str).collect::<Option<Vec<_>>>();
+            match fonts {
+                Some(fonts) if !fonts.is_empty() && !EXPRESSION_NAMES.contains(&fonts[0]) => {
+                    stacks.insert(fonts.join(","));
+                }
+                _ => values
+                    .iter()
+                    .for_each(|value| collect_font_stacks(value, stacks)),
+            }
+        }
+        Value::Object(map) => map
+            .values()
+            .for_each(|value| collect_font_stacks(value, stacks)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn tile_cover_world() {
+        let bounds = LngLatBounds::convert(&[-180.0, -85.051129, 180.0, 85.051129]);
+        assert_eq!(tile_cover(&bounds, 0), vec![CanonicalTileId::new(0, 0, 0)]);
+        assert_eq!(tile_cover(&bounds, 2).len(), 16);
+    }
+
+    #[test]
+    fn tile_cover_region() {
+        // Central Berlin.
+        let bounds = LngLatBounds::convert(&[13.3, 52.47, 13.5, 52.55]);
+        assert_eq!(
+            tile_cover(&bounds, 5),
+            vec![CanonicalTileId::new(5, 17, 10)]
+        );
+        assert_eq!(
+            tile_cover(&bounds, 10),
+            vec![
+                CanonicalTileId::new(10, 549, 335),
+                CanonicalTileId::new(10, 549, 336),
+                CanonicalTileId::new(10, 550, 335),
+                CanonicalTileId::new(10, 550, 336),
+            ]
+        );
+    }
+
+    #[test]
+    fn tile_cover_across_antimeridian() {
+        // Fiji, given either with west > east or with east past 180.
+        for bounds in &[[176.0, -21.0, -178.0, -12.0], [176.0, -21.0, 182.0, -12.0]] {
+            let bounds = LngLatBounds::convert(bounds);
+            assert_eq!(
+                tile_cover(&bounds, 4),
+                vec![
+                    CanonicalTileId::new(4, 15, 8),
+                    CanonicalTileId::new(4, 0, 8),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn offline_region_zoom_range() {
+        assert!(OfflineRegion::new("style", [0.0, 0.0, 1.0, 1.0], 5.0, 4.0).is_err());
+        assert!(OfflineRegion::new("style", [0.0, 0.0, 1.0, 1.0], f32::NAN, 4.0).is_err());
+        assert!(OfflineRegion::new("style", [0.0, 0.0, 1.0, 1.0], 4.0, 4.0).is_ok());
+    }
+
+    #[test]
+    fn tile_urls_failed_tilejson() {
+        let source = serde_json::from_value::<style_spec::Source>(json!({
+            "type": "vector",
+            "url": "mapbox://mapbox.mapbox-streets-v8"
+        }))
+        .unwrap();
+        // Without a cache every download fails.
+        let nm = NetworkManager::new("token", None, 6, None, None).unwrap();
+        let region = OfflineRegion::new("style", [13.3, 52.47, 13.5, 52.55], 5.0, 5.0).unwrap();
+        let mut progress = OfflineProgress::default();
+        let urls = futures::executor::block_on(region.tile_urls(
+            &nm,
+            "streets",
+            &tiled_source(&source).unwrap(),
+            &mut progress,
+        ))
+        .unwrap();
+        assert!(urls.is_empty());
+        assert_eq!(progress.required_resources, 1);
+        assert_eq!(progress.failed_resources, 1);
+    }
+
+    #[test]
+    fn tile_urls_high_resolution_raster() {
+        let source = serde_json::from_value::<style_spec::Source>(json!({
+            "type": "raster",
+            "tiles": ["https://api.mapbox.com/v4/mapbox.satellite/{z}/{x}/{y}.webp"],
+            "tileSize": 512
+        }))
+        .unwrap();
+        let nm = NetworkManager::new("token", None, 6, None, None).unwrap();
+        let region = OfflineRegion::new("style", [13.3, 52.47, 13.5, 52.55], 5.0, 5.0).unwrap();
+        let urls = futures::executor::block_on(region.tile_urls(
+            &nm,
+            "satellite",
+            &tiled_source(&source).unwrap(),
+            &mut OfflineProgress::default(),
+        ))
+        .unwrap();
+        assert_eq!(urls.len(), 1);
+        assert_eq!(
+            urls[0].0,
+            "https://api.mapbox.com/v4/mapbox.satellite/5/17/10@2x.webp"
+        );
+    }
+
+    #[test]
+    fn font_stacks_from_layers() {
+        let style = json!({
+            "layers": [
+                {
+                    "id": "road-label",
+                    "type": "symbol",
+                    "layout": {
+                        "text-field": ["get", "name"],
+                        "text-font": ["DIN Pro Medium", "Arial Unicode MS Regular"]
+                    }
+                },
+                {
+                    "id": "place-label",
+                    "type": "symbol",
+                    "layout": {
+                        "text-font": [
+                            "step",
+                            ["zoom"],
+                            ["literal", ["DIN Pro Regular"]],
+                            8,
+                            ["literal", ["DIN Pro Medium", "Arial Unicode MS Regular"]]
+                        ]
+                    }
+                },
+                {
+                    "id": "poi-label",
+                    "type": "symbol",
+                    "layout": { "text-field": "{name}" }
+                },
+                { "id": "water", "type": "fill" }
+            ]
+        });
+
+        let stacks = font_stacks(&style).into_iter().collect::<Vec<_>>();
+        assert_eq!(
+            stacks,
+            vec![
+                "DIN Pro Medium,Arial Unicode MS Regular",
+                "DIN Pro Regular",
+                DEFAULT_FONT_STACK,
+            ]
+        );
+    }
+}
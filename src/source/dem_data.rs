@@ -0,0 +1,158 @@
+use crate::style_spec::Encoding;
+use eyre::{eyre, Result};
+use image::RgbaImage;
+
+// Elevations of a DEM tile in meters. The tile is surrounded by a 1 pixel
+// border that is filled from the neighbouring tiles, so slopes can be
+// computed right up to the tile edges.
+#[derive(Debug, Clone)]
+pub(crate) struct DEMData {
+    dim: i32,
+    stride: i32,
+    data: Vec<f32>,
+}
+
+impl DEMData {
+    pub fn new(image: &RgbaImage, encoding: &Encoding) -> Result<Self> {
+        let (width, height) = image.dimensions();
+        if width != height || width == 0 {
+            return Err(eyre!("DEM tiles must be square, got {}x{}", width, height));
+        }
+
+        let dim = width as i32;
+        let stride = dim + 2;
+        let mut dem = Self {
+            dim,
+            stride,
+            data: vec![0.0; (stride * stride) as usize],
+        };
+        for (i, pixel) in image.as_raw().chunks_exact(4).enumerate() {
+            let (x, y) = (i as i32 % dim, i as i32 / dim);
+            dem.set(x, y, unpack(encoding, pixel[0], pixel[1], pixel[2]));
+        }
+
+        // Until the neighbours are loaded the border repeats the edge pixels.
+        let last = dim - 1;
+        for i in 0..dim {
+            dem.set(-1, i, dem.get(0, i));
+            dem.set(dim, i, dem.get(last, i));
+            dem.set(i, -1, dem.get(i, 0));
+            dem.set(i, dim, dem.get(i, last));
+        }
+        dem.set(-1, -1, dem.get(0, 0));
+        dem.set(dim, -1, dem.get(last, 0));
+        dem.set(-1, dim, dem.get(0, last));
+        dem.set(dim, dim, dem.get(last, last));
+        Ok(dem)
+    }
+
+    pub fn memory_size(&self) -> usize {
+        self.data.len() * std::mem::size_of::<f32>()
+    }
+
+    pub fn dim(&self) -> u32 {
+        self.dim as u32
+    }
+
+    // Both coordinates range from -1 to `dim`, including the border.
+    pub fn get(&self, x: i32, y: i32) -> f32 {
+        self.data[self.idx(x, y)]
+    }
+
+    // Copies the edge of the neighbouring tile at `dx`, `dy` (each -1, 0 or
+    // 1) into the border.
+    pub fn backfill_border(&mut self, border: &DEMData, dx: i32, dy: i32) {
+        if self.dim != border.dim {
+            return;
+        }
+
+        let dim = self.dim;
+        let (mut x_min, mut x_max) = (dx * dim, dx * dim + dim);
+        let (mut y_min, mut y_max) = (dy * dim, dy * dim + dim);
+        match dx {
+            -1 => x_min = x_max - 1,
+            1 => x_max = x_min + 1,
+            _ => {}
+        }
+        match dy {
+            -1 => y_min = y_max - 1,
+            1 => y_max = y_min + 1,
+            _ => {}
+        }
+
+        let (ox, oy) = (-dx * dim, -dy * dim);
+        for y in y_min..y_max {
+            for x in x_min..x_max {
+                self.set(x, y, border.get(x + ox, y + oy));
+            }
+        }
+    }
+
+    fn set(&mut self, x: i32, y: i32, elevation: f32) {
+        let idx = self.idx(x, y);
+        self.data[idx] = elevation;
+    }
+
+    fn idx(&self, x: i32, y: i32) -> usize {
+        debug_assert!(x >= -1 && x <= self.dim && y >= -1 && y <= self.dim);
+        ((y + 1) * self.stride + x + 1) as usize
+    }
+}
+
+fn unpack(encoding: &Encoding, r: u8, g: u8, b: u8) -> f32 {
+    let (r, g, b) = (f64::from(r), f64::from(g), f64::from(b));
+    let elevation = match encoding {
+        Encoding::Mapbox => (r * 256.0 * 256.0 + g * 256.0 + b) * 0.1 - 10000.0,
+        Encoding::Terrarium => r * 256.0 + g + b / 256.0 - 32768.0,
+    };
+    elevation as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn image(dim: u32, pixel: [u8; 4]) -> RgbaImage {
+        let mut image = RgbaImage::new(dim, dim);
+        for y in 0..dim {
+            for x in 0..dim {
+                image.put_pixel(x, y, Rgba(pixel));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn dem_data_unpack() {
+        assert_eq!(unpack(&Encoding::Mapbox, 1, 134, 160), 0.0);
+        assert_eq!(unpack(&Encoding::Mapbox, 1, 138, 136), 100.0);
+        assert_eq!(unpack(&Encoding::Terrarium, 128, 0, 0), 0.0);
+        assert_eq!(unpack(&Encoding::Terrarium, 128, 100, 128), 100.5);
+
+        let dem = DEMData::new(&image(4, [128, 100, 0, 255]), &Encoding::Terrarium).unwrap();
+        assert_eq!(dem.dim(), 4);
+        assert_eq!(dem.get(-1, -1), 100.0);
+        assert_eq!(dem.get(4, 2), 100.0);
+        assert!(DEMData::new(&RgbaImage::new(4, 2), &Encoding::Mapbox).is_err());
+    }
+
+    #[test]
+    fn dem_data_backfill_border() {
+        let mut dem = DEMData::new(&image(4, [128, 0, 0, 255]), &Encoding::Terrarium).unwrap();
+        let mut right = image(4, [128, 10, 0, 255]);
+        right.put_pixel(0, 3, Rgba([128, 20, 0, 255]));
+        let right = DEMData::new(&right, &Encoding::Terrarium).unwrap();
+
+        dem.backfill_border(&right, 1, 0);
+        assert_eq!(dem.get(4, 0), 10.0);
+        assert_eq!(dem.get(4, 3), 20.0);
+        // Only the right border changes.
+        assert_eq!(dem.get(3, 0), 0.0);
+        assert_eq!(dem.get(4, -1), 0.0);
+        assert_eq!(dem.get(-1, 0), 0.0);
+
+        dem.backfill_border(&right, 1, -1);
+        assert_eq!(dem.get(4, -1), 20.0);
+    }
+}
@@ -0,0 +1,269 @@
+use super::convert::{Feature, Geometry, Point, Ring};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Axis {
+    X,
+    Y,
+}
+
+impl Axis {
+    fn coord(self, point: &Point) -> f64 {
+        match self {
+            Axis::X => point.x,
+            Axis::Y => point.y,
+        }
+    }
+
+    fn bounds(self, feature: &Feature) -> (f64, f64) {
+        match self {
+            Axis::X => (feature.min_x, feature.max_x),
+            Axis::Y => (feature.min_y, feature.max_y),
+        }
+    }
+
+    // Also returns how far along the segment the intersection is.
+    fn intersect(self, a: &Point, b: &Point, k: f64) -> (Point, f64) {
+        match self {
+            Axis::X => {
+                let t = (k - a.x) / (b.x - a.x);
+                (Point::new(k, a.y + (b.y - a.y) * t, 1.0), t)
+            }
+            Axis::Y => {
+                let t = (k - a.y) / (b.y - a.y);
+                (Point::new(a.x + (b.x - a.x) * t, k, 1.0), t)
+            }
+        }
+    }
+}
+
+// Keeps the parts of the features between `k1` and `k2` along the axis, both
+// given in tile units of the `scale` zoom level.
+pub(crate) fn clip(
+    features: &[Feature],
+    scale: f64,
+    k1: f64,
+    k2: f64,
+    axis: Axis,
+    min_all: f64,
+    max_all: f64,
+) -> Vec<Feature> {
+    let (k1, k2) = (k1 / scale, k2 / scale);
+
+    if min_all >= k1 && max_all < k2 {
+        return features.to_vec();
+    } else if max_all < k1 || min_all >= k2 {
+        return Vec::new();
+    }
+
+    let mut clipped = Vec::new();
+    for feature in features {
+        let (min, max) = axis.bounds(feature);
+        if min >= k1 && max < k2 {
+            clipped.push(feature.clone());
+            continue;
+        } else if max < k1 || min >= k2 {
+            continue;
+        }
+
+        let geometry = match &feature.geometry {
+            Geometry::Points(points) => {
+                let points = points
+                    .iter()
+                    .filter(|point| {
+                        let k = axis.coord(point);
+                        k >= k1 && k <= k2
+                    })
+                    .copied()
+                    .collect::<Vec<_>>();
+                if points.is_empty() {
+                    continue;
+                }
+                Geometry::Points(points)
+            }
+            Geometry::Lines(lines) => {
+                let lines = lines
+                    .iter()
+                    .flat_map(|ring| clip_ring(ring, k1, k2, axis, false))
+                    .collect::<Vec<_>>();
+                if lines.is_empty() {
+                    continue;
+                }
+                Geometry::Lines(lines)
+            }
+            Geometry::Polygons(polygons) => {
+                let polygons = polygons
+                    .iter()
+                    .map(|polygon| {
+                        polygon
+                            .iter()
+                            .flat_map(|ring| clip_ring(ring, k1, k2, axis, true))
+                            .collect::<Vec<_>>()
+                    })
+                    .filter(|polygon| !polygon.is_empty())
+                    .collect::<Vec<_>>();
+                if polygons.is_empty() {
+                    continue;
+                }
+                Geometry::Polygons(polygons)
+            }
+        };
+        clipped.push(Feature::new(
+            feature.id.clone(),
+            geometry,
+            feature.properties.clone(),
+        ));
+    }
+    clipped
+}
+
+// Lines can be cut into several slices, polygon rings are closed along the
+// clip edge and always stay a single ring.
+fn clip_ring(ring: &Ring, k1: f64, k2: f64, axis: Axis, is_polygon: bool) -> Vec<Ring> {
+    let points = &ring.points;
+    let mut slices = Vec::new();
+    let mut slice = Vec::new();
+    let mut start = ring.start;
+    let mut len = ring.start;
+
+    for segment in points.windows(2) {
+        let (a, b) = (&segment[0], &segment[1]);
+        let (ak, bk) = (axis.coord(a), axis.coord(b));
+        let segment_len = ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt();
+        let mut exited = None;
+
+        if ak < k1 {
+            if bk > k1 {
+                let (point, t) = axis.intersect(a, b, k1);
+                slice.push(point);
+                start = len + segment_len * t;
+            }
+        } else if ak > k2 {
+            if bk < k2 {
+                let (point, t) = axis.intersect(a, b, k2);
+                slice.push(point);
+                start = len + segment_len * t;
+            }
+        } else {
+            slice.push(*a);
+        }
+        if bk < k1 && ak >= k1 {
+            let (point, t) = axis.intersect(a, b, k1);
+            slice.push(point);
+            exited = Some(t);
+        }
+        if bk > k2 && ak <= k2 {
+            let (point, t) = axis.intersect(a, b, k2);
+            slice.push(point);
+            exited = Some(t);
+        }
+
+        if let (false, Some(t)) = (is_polygon, exited) {
+            slices.push(Ring {
+                points: std::mem::take(&mut slice),
+                size: ring.size,
+                start,
+                end: len + segment_len * t,
+            });
+            start = ring.start;
+        }
+        len += segment_len;
+    }
+
+    if let Some(last) = points.last() {
+        let k = axis.coord(last);
+        if k >= k1 && k <= k2 {
+            slice.push(*last);
+        }
+    }
+
+    if is_polygon && slice.len() >= 2 {
+        let (first, last) = (slice[0], slice[slice.len() - 1]);
+        if first.x != last.x || first.y != last.y {
+            slice.push(first);
+        }
+    }
+
+    if !slice.is_empty() {
+        slices.push(Ring {
+            points: slice,
+            size: ring.size,
+            start,
+            end: ring.end,
+        });
+    }
+    slices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Map;
+
+    fn points(coords: &[(f64, f64)]) -> Vec<Point> {
+        coords.iter().map(|&(x, y)| Point::new(x, y, 0.0)).collect()
+    }
+
+    fn coords(ring: &Ring) -> Vec<(f64, f64)> {
+        ring.points.iter().map(|point| (point.x, point.y)).collect()
+    }
+
+    #[test]
+    fn clip_line_into_slices() {
+        let ring = Ring {
+            points: points(&[(0.0, 0.0), (50.0, 0.0), (50.0, 10.0), (0.0, 10.0)]),
+            size: 110.0,
+            start: 0.0,
+            end: 110.0,
+        };
+        let slices = clip_ring(&ring, 10.0, 40.0, Axis::X, false);
+        assert_eq!(slices.len(), 2);
+        assert_eq!(coords(&slices[0]), vec![(10.0, 0.0), (40.0, 0.0)]);
+        assert_eq!(coords(&slices[1]), vec![(40.0, 10.0), (10.0, 10.0)]);
+        // Slices keep their distance along the whole line.
+        assert_eq!((slices[0].start, slices[0].end), (10.0, 40.0));
+        assert_eq!((slices[1].start, slices[1].end), (70.0, 100.0));
+        assert_eq!(slices[1].size, 110.0);
+    }
+
+    #[test]
+    fn clip_polygon_closes_ring() {
+        let ring = Ring {
+            points: points(&[
+                (0.0, 0.0),
+                (50.0, 0.0),
+                (50.0, 10.0),
+                (0.0, 10.0),
+                (0.0, 0.0),
+            ]),
+            ..Default::default()
+        };
+        let slices = clip_ring(&ring, 10.0, 40.0, Axis::X, true);
+        assert_eq!(slices.len(), 1);
+        assert_eq!(
+            coords(&slices[0]),
+            vec![
+                (10.0, 0.0),
+                (40.0, 0.0),
+                (40.0, 10.0),
+                (10.0, 10.0),
+                (10.0, 0.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn clip_features() {
+        let features = vec![
+            Feature::new(
+                None,
+                Geometry::Points(points(&[(0.1, 0.1), (0.6, 0.1)])),
+                Map::new(),
+            ),
+            Feature::new(None, Geometry::Points(points(&[(0.9, 0.9)])), Map::new()),
+        ];
+        let clipped = clip(&features, 2.0, 0.0, 1.0, Axis::X, 0.1, 0.9);
+        assert_eq!(clipped.len(), 1);
+        assert_eq!(clipped[0].geometry, Geometry::Points(points(&[(0.1, 0.1)])));
+        assert_eq!(clipped[0].max_x, 0.1);
+    }
+}
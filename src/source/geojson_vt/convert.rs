@@ -0,0 +1,323 @@
+use super::simplify::simplify;
+use super::Bounds;
+use eyre::{eyre, Result};
+use serde_json::{Map, Value};
+use std::f64::consts::PI;
+
+// Projected coordinates are in 0..1 world units, `z` holds the
+// simplification importance of the point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Point {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Point {
+    pub fn new(x: f64, y: f64, z: f64) -> Self {
+        Self { x, y, z }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Ring {
+    pub points: Vec<Point>,
+    // Length for lines, area for polygon rings.
+    pub size: f64,
+    // Distance along the original line at the first and last point, so
+    // slices of a line know which part of it they are.
+    pub start: f64,
+    pub end: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Geometry {
+    Points(Vec<Point>),
+    Lines(Vec<Ring>),
+    Polygons(Vec<Vec<Ring>>),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Feature {
+    pub id: Option<Value>,
+    pub geometry: Geometry,
+    pub properties: Map<String, Value>,
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+impl Feature {
+    pub fn new(id: Option<Value>, geometry: Geometry, properties: Map<String, Value>) -> Self {
+        let mut feature = Self {
+            id,
+            geometry,
+            properties,
+            min_x: f64::INFINITY,
+            min_y: f64::INFINITY,
+            max_x: f64::NEG_INFINITY,
+            max_y: f64::NEG_INFINITY,
+        };
+        feature.update_bbox();
+        feature
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        Bounds {
+            min_x: self.min_x,
+            min_y: self.min_y,
+            max_x: self.max_x,
+            max_y: self.max_y,
+        }
+    }
+
+    pub fn shift_x(&mut self, offset: f64) {
+        let shift = |points: &mut Vec<Point>| {
+            for point in points {
+                point.x += offset;
+            }
+        };
+        match &mut self.geometry {
+            Geometry::Points(points) => shift(points),
+            Geometry::Lines(lines) => lines.iter_mut().for_each(|ring| shift(&mut ring.points)),
+            Geometry::Polygons(polygons) => polygons
+                .iter_mut()
+                .flatten()
+                .for_each(|ring| shift(&mut ring.points)),
+        }
+        self.min_x += offset;
+        self.max_x += offset;
+    }
+
+    fn update_bbox(&mut self) {
+        let points: Vec<&Point> = match &self.geometry {
+            Geometry::Points(points) => points.iter().collect(),
+            Geometry::Lines(lines) => lines.iter().flat_map(|ring| &ring.points).collect(),
+            // Holes are always inside of the outer ring.
+            Geometry::Polygons(polygons) => polygons
+                .iter()
+                .filter_map(|polygon| polygon.first())
+                .flat_map(|ring| &ring.points)
+                .collect(),
+        };
+        for point in points {
+            self.min_x = self.min_x.min(point.x);
+            self.min_y = self.min_y.min(point.y);
+            self.max_x = self.max_x.max(point.x);
+            self.max_y = self.max_y.max(point.y);
+        }
+    }
+}
+
+pub(crate) fn convert(data: &Value, tolerance: f64) -> Result<Vec<Feature>> {
+    let mut features = Vec::new();
+    match data.get("type").and_then(Value::as_str) {
+        Some("FeatureCollection") => {
+            let items = data
+                .get("features")
+                .and_then(Value::as_array)
+                .ok_or_else(|| eyre!("FeatureCollection has no features"))?;
+            for feature in items {
+                convert_feature(&mut features, feature, tolerance)?;
+            }
+        }
+        Some("Feature") => convert_feature(&mut features, data, tolerance)?,
+        Some(_) => convert_geometry(&mut features, None, data, &Map::new(), tolerance)?,
+        None => return Err(eyre!("GeoJSON object has no type")),
+    }
+    Ok(features)
+}
+
+fn convert_feature(features: &mut Vec<Feature>, feature: &Value, tolerance: f64) -> Result<()> {
+    let geometry = match feature.get("geometry") {
+        Some(Value::Null) | None => return Ok(()),
+        Some(geometry) => geometry,
+    };
+    let properties = match feature.get("properties") {
+        Some(Value::Object(properties)) => properties.clone(),
+        _ => Map::new(),
+    };
+    let id = feature.get("id").cloned();
+    convert_geometry(features, id, geometry, &properties, tolerance)
+}
+
+fn convert_geometry(
+    features: &mut Vec<Feature>,
+    id: Option<Value>,
+    geometry: &Value,
+    properties: &Map<String, Value>,
+    tolerance: f64,
+) -> Result<()> {
+    let kind = geometry
+        .get("type")
+        .and_then(Value::as_str)
+        .ok_or_else(|| eyre!("Geometry has no type"))?;
+    if kind == "GeometryCollection" {
+        let geometries = geometry
+            .get("geometries")
+            .and_then(Value::as_array)
+            .ok_or_else(|| eyre!("GeometryCollection has no geometries"))?;
+        for geometry in geometries {
+            convert_geometry(features, id.clone(), geometry, properties, tolerance)?;
+        }
+        return Ok(());
+    }
+
+    let coords = geometry
+        .get("coordinates")
+        .ok_or_else(|| eyre!("Geometry \"{}\" has no coordinates", kind))?;
+    let sq_tolerance = tolerance * tolerance;
+    let geometry = match kind {
+        "Point" => Geometry::Points(vec![project_point(coords)?]),
+        "MultiPoint" => Geometry::Points(
+            as_array(coords)?
+                .iter()
+                .map(project_point)
+                .collect::<Result<_>>()?,
+        ),
+        "LineString" => Geometry::Lines(vec![convert_ring(coords, sq_tolerance, false)?]),
+        "MultiLineString" => Geometry::Lines(
+            as_array(coords)?
+                .iter()
+                .map(|line| convert_ring(line, sq_tolerance, false))
+                .collect::<Result<_>>()?,
+        ),
+        "Polygon" => Geometry::Polygons(vec![convert_polygon(coords, sq_tolerance)?]),
+        "MultiPolygon" => Geometry::Polygons(
+            as_array(coords)?
+                .iter()
+                .map(|polygon| convert_polygon(polygon, sq_tolerance))
+                .collect::<Result<_>>()?,
+        ),
+        _ => return Err(eyre!("Unknown geometry type \"{}\"", kind)),
+    };
+    features.push(Feature::new(id, geometry, properties.clone()));
+    Ok(())
+}
+
+fn convert_polygon(coords: &Value, sq_tolerance: f64) -> Result<Vec<Ring>> {
+    as_array(coords)?
+        .iter()
+        .map(|ring| convert_ring(ring, sq_tolerance, true))
+        .collect()
+}
+
+fn convert_ring(coords: &Value, sq_tolerance: f64, is_polygon: bool) -> Result<Ring> {
+    let coords = as_array(coords)?;
+    let mut points: Vec<Point> = Vec::with_capacity(coords.len());
+    let mut size = 0.0;
+    for coord in coords {
+        let point = project_point(coord)?;
+        if let Some(prev) = points.last() {
+            size += if is_polygon {
+                (prev.x * point.y - point.x * prev.y) / 2.0
+            } else {
+                ((point.x - prev.x).powi(2) + (point.y - prev.y).powi(2)).sqrt()
+            };
+        }
+        points.push(point);
+    }
+
+    if let Some(last) = points.len().checked_sub(1) {
+        points[0].z = 1.0;
+        points[last].z = 1.0;
+        simplify(&mut points, 0, last, sq_tolerance);
+    }
+    let size = size.abs();
+    Ok(Ring {
+        points,
+        size,
+        start: 0.0,
+        end: size,
+    })
+}
+
+fn project_point(coord: &Value) -> Result<Point> {
+    let coord = as_array(coord)?;
+    match (
+        coord.first().and_then(Value::as_f64),
+        coord.get(1).and_then(Value::as_f64),
+    ) {
+        (Some(lng), Some(lat)) => Ok(Point::new(project_x(lng), project_y(lat), 0.0)),
+        _ => Err(eyre!("Invalid position {:?}", coord)),
+    }
+}
+
+fn as_array(value: &Value) -> Result<&Vec<Value>> {
+    value
+        .as_array()
+        .ok_or_else(|| eyre!("Expected an array of coordinates, found {}", value))
+}
+
+pub(crate) fn project_x(lng: f64) -> f64 {
+    lng / 360.0 + 0.5
+}
+
+pub(crate) fn project_y(lat: f64) -> f64 {
+    let sin = (lat * PI / 180.0).sin();
+    let y = 0.5 - 0.25 * ((1.0 + sin) / (1.0 - sin)).ln() / PI;
+    y.clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use serde_json::json;
+
+    #[test]
+    fn convert_feature_collection() {
+        let data = json!({
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "id": 7,
+                    "properties": { "name": "a" },
+                    "geometry": { "type": "Point", "coordinates": [0.0, 0.0] }
+                },
+                {
+                    "type": "Feature",
+                    "properties": null,
+                    "geometry": {
+                        "type": "GeometryCollection",
+                        "geometries": [
+                            { "type": "LineString", "coordinates": [[-180.0, 0.0], [180.0, 0.0]] },
+                            { "type": "MultiPoint", "coordinates": [[90.0, 0.0]] }
+                        ]
+                    }
+                },
+                { "type": "Feature", "properties": {}, "geometry": null }
+            ]
+        });
+        let features = convert(&data, 0.0).unwrap();
+        assert_eq!(features.len(), 3);
+        assert_eq!(features[0].id, Some(json!(7)));
+        assert_eq!(
+            features[0].geometry,
+            Geometry::Points(vec![Point::new(0.5, 0.5, 0.0)])
+        );
+
+        match &features[1].geometry {
+            Geometry::Lines(lines) => assert_approx_eq!(lines[0].size, 1.0),
+            geometry => panic!("Unexpected geometry {:?}", geometry),
+        }
+        assert_approx_eq!(features[2].min_x, 0.75);
+    }
+
+    #[test]
+    fn convert_invalid() {
+        assert!(convert(&json!({ "type": "Point", "coordinates": [0.0] }), 0.0).is_err());
+        assert!(convert(&json!({ "type": "Circle", "coordinates": [0.0, 0.0] }), 0.0).is_err());
+        assert!(convert(&json!({ "features": [] }), 0.0).is_err());
+    }
+
+    #[test]
+    fn project_clamps_poles() {
+        assert_approx_eq!(project_y(0.0), 0.5);
+        assert_approx_eq!(project_y(90.0), 0.0);
+        assert_approx_eq!(project_y(-90.0), 1.0);
+        assert_approx_eq!(project_x(-180.0), 0.0);
+    }
+}
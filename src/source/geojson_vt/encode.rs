@@ -0,0 +1,191 @@
+use super::tile::{FeatureType, TileFeature, VectorTile};
+use crate::source::promote_id::feature_id;
+use crate::util::{Writer, WIRE_FIXED64};
+use serde_json::Value;
+use std::collections::HashMap;
+
+pub(crate) const LAYER_NAME: &str = "_geojsonTileLayer";
+
+const MOVE_TO: u32 = 1;
+const LINE_TO: u32 = 2;
+const CLOSE_PATH: u32 = 7;
+
+// Serializes the tile as a Mapbox Vector Tile with a single layer, so sliced
+// GeoJSON goes through the same decoding as tiles from the network.
+pub(crate) fn encode(tile: &VectorTile, extent: u32) -> Vec<u8> {
+    let mut layer = Writer::default();
+    layer.varint_field(15, 2);
+    layer.bytes_field(1, LAYER_NAME.as_bytes());
+
+    let mut keys = Vec::new();
+    let mut key_indices = HashMap::new();
+    let mut values = Vec::new();
+    let mut value_indices = HashMap::new();
+    for feature in &tile.features {
+        let mut tags = Vec::new();
+        for (key, value) in &feature.properties {
+            let value = match encode_value(value) {
+                Some(value) => value,
+                None => continue,
+            };
+            let key_index = *key_indices.entry(key.clone()).or_insert_with(|| {
+                keys.push(key.clone());
+                keys.len() as u32 - 1
+            });
+            let value_index = *value_indices.entry(value.clone()).or_insert_with(|| {
+                values.push(value);
+                values.len() as u32 - 1
+            });
+            tags.push(key_index);
+            tags.push(value_index);
+        }
+        layer.bytes_field(2, &encode_feature(feature, &tags));
+    }
+
+    for key in &keys {
+        layer.bytes_field(3, key.as_bytes());
+    }
+    for value in &values {
+        layer.bytes_field(4, value);
+    }
+    layer.varint_field(5, u64::from(extent));
+
+    let mut writer = Writer::default();
+    writer.bytes_field(3, &layer.data);
+    writer.data
+}
+
+fn encode_feature(feature: &TileFeature, tags: &[u32]) -> Vec<u8> {
+    let mut writer = Writer::default();
+    if let Some(id) = feature.id.as_ref().and_then(feature_id) {
+        writer.varint_field(1, id);
+    }
+    writer.packed_field(2, tags);
+    writer.varint_field(3, feature.kind as u64);
+    writer.packed_field(4, &encode_geometry(feature.kind, &feature.geometry));
+    writer.data
+}
+
+pub(crate) fn encode_geometry(kind: FeatureType, rings: &[Vec<[i32; 2]>]) -> Vec<u32> {
+    let mut commands = Vec::new();
+    let mut cursor = [0, 0];
+    let mut push_point = |commands: &mut Vec<u32>, point: &[i32; 2]| {
+        commands.push(zigzag(point[0] - cursor[0]));
+        commands.push(zigzag(point[1] - cursor[1]));
+        cursor = *point;
+    };
+
+    for ring in rings {
+        let points = match kind {
+            // The closing point is implied by ClosePath.
+            FeatureType::Polygon if ring.len() > 1 && ring.first() == ring.last() => {
+                &ring[..ring.len() - 1]
+            }
+            _ => &ring[..],
+        };
+        if points.is_empty() {
+            continue;
+        }
+
+        if kind == FeatureType::Point {
+            commands.push(command(MOVE_TO, points.len()));
+            for point in points {
+                push_point(&mut commands, point);
+            }
+            continue;
+        }
+
+        commands.push(command(MOVE_TO, 1));
+        push_point(&mut commands, &points[0]);
+        commands.push(command(LINE_TO, points.len() - 1));
+        for point in &points[1..] {
+            push_point(&mut commands, point);
+        }
+        if kind == FeatureType::Polygon {
+            commands.push(command(CLOSE_PATH, 1));
+        }
+    }
+    commands
+}
+
+// Values are kept encoded so equal values share one entry in the layer.
+pub(crate) fn encode_value(value: &Value) -> Option<Vec<u8>> {
+    let mut writer = Writer::default();
+    match value {
+        Value::Null => return None,
+        Value::String(string) => writer.bytes_field(1, string.as_bytes()),
+        Value::Bool(boolean) => writer.varint_field(7, *boolean as u64),
+        Value::Number(number) => {
+            if let Some(uint) = number.as_u64() {
+                writer.varint_field(5, uint);
+            } else if let Some(int) = number.as_i64() {
+                writer.varint_field(6, ((int << 1) ^ (int >> 63)) as u64);
+            } else {
+                writer.tag(3, WIRE_FIXED64);
+                let double = number.as_f64().unwrap_or_default();
+                writer.data.extend_from_slice(&double.to_le_bytes());
+            }
+        }
+        // Nested values can't be represented in a vector tile.
+        Value::Array(_) | Value::Object(_) => writer.bytes_field(1, value.to_string().as_bytes()),
+    }
+    Some(writer.data)
+}
+
+fn command(id: u32, count: usize) -> u32 {
+    (id & 0x7) | ((count as u32) << 3)
+}
+
+fn zigzag(n: i32) -> u32 {
+    ((n << 1) ^ (n >> 31)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Map};
+
+    #[test]
+    fn encode_geometry_commands() {
+        let line = encode_geometry(FeatureType::LineString, &[vec![[2, 2], [2, 10], [10, 10]]]);
+        assert_eq!(line, vec![9, 4, 4, 18, 0, 16, 16, 0]);
+
+        let polygon = encode_geometry(
+            FeatureType::Polygon,
+            &[vec![[3, 6], [8, 12], [20, 34], [3, 6]]],
+        );
+        assert_eq!(polygon, vec![9, 6, 12, 18, 10, 12, 24, 44, 15]);
+
+        let points = encode_geometry(FeatureType::Point, &[vec![[5, 7], [3, 2]]]);
+        assert_eq!(points, vec![17, 10, 14, 3, 9]);
+    }
+
+    #[test]
+    fn encode_shares_keys_and_values() {
+        let feature = |value: Value| {
+            let mut properties = Map::new();
+            properties.insert("kind".to_owned(), value);
+            properties.insert("empty".to_owned(), Value::Null);
+            TileFeature {
+                id: Some(json!(1)),
+                kind: FeatureType::Point,
+                geometry: vec![vec![[0, 0]]],
+                properties,
+            }
+        };
+        let tile = VectorTile::new(vec![
+            feature(json!("a")),
+            feature(json!("a")),
+            feature(json!(-1)),
+        ]);
+        let data = encode(&tile, 4096);
+
+        let count = |needle: &[u8]| data.windows(needle.len()).filter(|w| *w == needle).count();
+        // Key "kind" and value "a" are stored once, the null property is skipped.
+        assert_eq!(count(b"\x1a\x04kind"), 1);
+        assert_eq!(count(b"\x22\x03\x0a\x01a"), 1);
+        assert_eq!(count(b"empty"), 0);
+        // sint value -1 zigzag encodes to 1.
+        assert_eq!(count(b"\x22\x02\x30\x01"), 1);
+    }
+}
@@ -0,0 +1,482 @@
+mod clip;
+mod convert;
+mod encode;
+mod simplify;
+mod tile;
+mod wrap;
+
+use clip::{clip, Axis};
+use convert::{convert, Feature};
+use eyre::Result;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use tile::create_tile;
+use wrap::wrap;
+
+pub(crate) use convert::{project_x, project_y};
+pub(crate) use encode::{encode, encode_value, LAYER_NAME};
+pub(crate) use tile::{FeatureType, TileFeature, VectorTile};
+
+const MAX_TILE_ZOOM: u32 = 24;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Options {
+    // Geometry is simplified on every zoom level but this one.
+    pub max_zoom: u32,
+    // Tiles are sliced eagerly up to this zoom, or until they have fewer
+    // than `index_max_points` points.
+    pub index_max_zoom: u32,
+    pub index_max_points: usize,
+    pub tolerance: f64,
+    pub extent: u32,
+    pub buffer: f64,
+    // Tag line features with the part of the line they cover, for
+    // `line-progress`.
+    pub line_metrics: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max_zoom: 14,
+            index_max_zoom: 5,
+            index_max_points: 100_000,
+            tolerance: 3.0,
+            extent: 4096,
+            buffer: 64.0,
+            line_metrics: false,
+        }
+    }
+}
+
+// Bounding box in projected 0..1 world units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Bounds {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+impl Bounds {
+    pub fn world() -> Self {
+        Self {
+            min_x: f64::NEG_INFINITY,
+            min_y: f64::NEG_INFINITY,
+            max_x: f64::INFINITY,
+            max_y: f64::INFINITY,
+        }
+    }
+
+    // `buffer` is a fraction of the tile size.
+    pub fn intersects_tile(&self, z: u32, x: u32, y: u32, buffer: f64) -> bool {
+        let z2 = f64::from(1u32 << z);
+        let (x, y) = (f64::from(x), f64::from(y));
+        self.max_x >= (x - buffer) / z2
+            && self.min_x <= (x + 1.0 + buffer) / z2
+            && self.max_y >= (y - buffer) / z2
+            && self.min_y <= (y + 1.0 + buffer) / z2
+    }
+}
+
+// On-the-fly GeoJSON tiling ported from geojson-vt.
+#[derive(Debug)]
+pub(crate) struct GeoJSONVT {
+    options: Options,
+    tiles: HashMap<u64, VectorTile>,
+    // Features with an id, so the ones replaced or removed by updates are
+    // found without the original data. Wrapped features have several parts.
+    features: HashMap<String, Vec<Feature>>,
+}
+
+impl GeoJSONVT {
+    pub fn new(data: &Value, options: Options) -> Result<Self> {
+        let mut index = Self {
+            options,
+            tiles: HashMap::new(),
+            features: HashMap::new(),
+        };
+        let features = index.convert(data)?;
+        index.insert(&features);
+        if !features.is_empty() {
+            index.split_tile(features, 0, 0, 0, None);
+        }
+        Ok(index)
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn contains(&self, id: &Value) -> bool {
+        self.features.contains_key(&id.to_string())
+    }
+
+    fn insert(&mut self, features: &[Feature]) {
+        for feature in features {
+            if let Some(id) = &feature.id {
+                self.features
+                    .entry(id.to_string())
+                    .or_default()
+                    .push(feature.clone());
+            }
+        }
+    }
+
+    // Removes the features with the given ids and adds the ones in `data`.
+    // Only the changed features are sliced, into the tiles they touch, and
+    // their bounds are returned.
+    pub fn update(&mut self, removed: &[Value], data: &Value) -> Result<Vec<Bounds>> {
+        let added = self.convert(data)?;
+        let removed_ids = removed.iter().map(Value::to_string).collect::<HashSet<_>>();
+        let removed_bounds = removed_ids
+            .iter()
+            .filter_map(|id| self.features.remove(id))
+            .flatten()
+            .map(|feature| feature.bounds())
+            .collect::<Vec<_>>();
+        let buffer = self.options.buffer / f64::from(self.options.extent);
+        for (id, tile) in &mut self.tiles {
+            let (z, x, y) = from_id(*id);
+            if !removed_bounds
+                .iter()
+                .any(|bounds| bounds.intersects_tile(z, x, y, buffer))
+            {
+                continue;
+            }
+            tile.features
+                .retain(|feature| !is_removed(&feature.id, &removed_ids));
+            if let Some(source) = &mut tile.source {
+                source.retain(|feature| !is_removed(&feature.id, &removed_ids));
+            }
+        }
+
+        let mut changed = removed_bounds;
+        changed.extend(added.iter().map(Feature::bounds));
+        self.insert(&added);
+        if !added.is_empty() {
+            self.add_features(added);
+        }
+        Ok(changed)
+    }
+
+    // Slices new features into the tiles already there. Below tiles that
+    // weren't split yet they are only added to the source sliced on demand.
+    fn add_features(&mut self, features: Vec<Feature>) {
+        let mut stack = vec![(features, 0, 0, 0)];
+        while let Some((features, z, x, y)) = stack.pop() {
+            let tile = match self.tiles.get_mut(&to_id(z, x, y)) {
+                Some(tile) => tile,
+                // Nothing was in this part of the world before.
+                None => {
+                    self.split_tile(features, z, x, y, None);
+                    continue;
+                }
+            };
+
+            let added = create_tile(&features, z, x, y, &self.options);
+            tile.features.extend(added.features);
+            tile.num_points += added.num_points;
+            tile.num_simplified += added.num_simplified;
+            tile.min_x = tile.min_x.min(added.min_x);
+            tile.min_y = tile.min_y.min(added.min_y);
+            tile.max_x = tile.max_x.max(added.max_x);
+            tile.max_y = tile.max_y.max(added.max_y);
+            if let Some(source) = &mut tile.source {
+                source.extend(features);
+                continue;
+            }
+            if features.is_empty() {
+                continue;
+            }
+            stack.extend(split_children(
+                &features,
+                z,
+                x,
+                y,
+                [added.min_x, added.min_y, added.max_x, added.max_y],
+                &self.options,
+            ));
+        }
+    }
+
+    fn convert(&self, data: &Value) -> Result<Vec<Feature>> {
+        let extent = f64::from(self.options.extent);
+        let tolerance =
+            self.options.tolerance / (f64::from(1u32 << self.options.max_zoom) * extent);
+        Ok(wrap(
+            convert(data, tolerance)?,
+            self.options.buffer / extent,
+        ))
+    }
+
+    // Returns `None` when there is nothing in the tile.
+    pub fn get_tile(&mut self, z: u32, x: u32, y: u32) -> Option<&VectorTile> {
+        if z > MAX_TILE_ZOOM {
+            return None;
+        }
+
+        let id = to_id(z, x, y);
+        if !self.tiles.contains_key(&id) {
+            let (mut z0, mut x0, mut y0) = (z, x, y);
+            let mut parent = None;
+            while parent.is_none() && z0 > 0 {
+                z0 -= 1;
+                x0 >>= 1;
+                y0 >>= 1;
+                parent = self.tiles.get_mut(&to_id(z0, x0, y0));
+            }
+
+            let source = parent?.source.take()?;
+            self.split_tile(source, z0, x0, y0, Some((z, x, y)));
+        }
+        self.tiles.get(&id)
+    }
+
+    // Slices the features into the tile and its descendants, either up to the
+    // index limits or, with a target, down the path to the target tile.
+    fn split_tile(
+        &mut self,
+        features: Vec<Feature>,
+        z: u32,
+        x: u32,
+        y: u32,
+        target: Option<(u32, u32, u32)>,
+    ) {
+        let options = &self.options;
+        let mut stack = vec![(features, z, x, y)];
+
+        while let Some((features, z, x, y)) = stack.pop() {
+            let tile = self
+                .tiles
+                .entry(to_id(z, x, y))
+                .or_insert_with(|| create_tile(&features, z, x, y, options));
+
+            let stop = match target {
+                None => z == options.index_max_zoom || tile.num_points <= options.index_max_points,
+                Some((cz, cx, cy)) => {
+                    z == options.max_zoom || z == cz || x != cx >> (cz - z) || y != cy >> (cz - z)
+                }
+            };
+            if stop {
+                tile.source = Some(features);
+                continue;
+            }
+            tile.source = None;
+            if features.is_empty() {
+                continue;
+            }
+
+            let bounds = [tile.min_x, tile.min_y, tile.max_x, tile.max_y];
+            stack.extend(split_children(&features, z, x, y, bounds, options));
+        }
+    }
+}
+
+// Clips the features of a tile, within `bounds`, into its four children.
+fn split_children(
+    features: &[Feature],
+    z: u32,
+    x: u32,
+    y: u32,
+    bounds: [f64; 4],
+    options: &Options,
+) -> Vec<(Vec<Feature>, u32, u32, u32)> {
+    let z2 = f64::from(1u32 << z);
+    let (fx, fy) = (f64::from(x), f64::from(y));
+    let k1 = 0.5 * options.buffer / f64::from(options.extent);
+    let (k2, k3, k4) = (0.5 - k1, 0.5 + k1, 1.0 + k1);
+    let [min_x, min_y, max_x, max_y] = bounds;
+
+    let left = clip(features, z2, fx - k1, fx + k3, Axis::X, min_x, max_x);
+    let right = clip(features, z2, fx + k2, fx + k4, Axis::X, min_x, max_x);
+
+    let (x, y) = (x * 2, y * 2);
+    let mut children = Vec::with_capacity(4);
+    for (features, x) in [(left, x), (right, x + 1)] {
+        let top = clip(&features, z2, fy - k1, fy + k3, Axis::Y, min_y, max_y);
+        let bottom = clip(&features, z2, fy + k2, fy + k4, Axis::Y, min_y, max_y);
+        children.push((top, z + 1, x, y));
+        children.push((bottom, z + 1, x, y + 1));
+    }
+    children
+}
+
+fn is_removed(id: &Option<Value>, removed: &HashSet<String>) -> bool {
+    match id {
+        Some(id) => removed.contains(&id.to_string()),
+        None => false,
+    }
+}
+
+fn to_id(z: u32, x: u32, y: u32) -> u64 {
+    ((1u64 << z) * u64::from(y) + u64::from(x)) * 32 + u64::from(z)
+}
+
+fn from_id(id: u64) -> (u32, u32, u32) {
+    let z = (id % 32) as u32;
+    let xy = id / 32;
+    (z, (xy % (1u64 << z)) as u32, (xy >> z) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tile::FeatureType;
+
+    fn line_data() -> Value {
+        json!({
+            "type": "Feature",
+            "id": 3,
+            "properties": { "name": "parallel" },
+            "geometry": {
+                "type": "LineString",
+                "coordinates": [[-170.0, 1.0], [170.0, 1.0]]
+            }
+        })
+    }
+
+    #[test]
+    fn geojson_vt_slices_on_demand() {
+        let mut index = GeoJSONVT::new(&line_data(), Options::default()).unwrap();
+        let buffer = index.options().buffer as i32;
+        assert_eq!(index.tiles.len(), 1);
+
+        let tile = index.get_tile(0, 0, 0).unwrap();
+        assert_eq!(tile.features.len(), 1);
+        assert_eq!(tile.features[0].kind, FeatureType::LineString);
+        assert_eq!(tile.features[0].id, Some(json!(3)));
+
+        let tile = index.get_tile(3, 2, 3).unwrap();
+        assert_eq!(tile.features.len(), 1);
+        let line = &tile.features[0].geometry[0];
+        assert_eq!(line[0][0], -buffer);
+        assert_eq!(line[line.len() - 1][0], 4096 + buffer);
+
+        // The line is north of the equator, beyond the buffer of the row below it.
+        assert!(index.get_tile(3, 2, 4).unwrap().features.is_empty());
+        assert!(index.get_tile(25, 0, 0).is_none());
+    }
+
+    #[test]
+    fn geojson_vt_indexes_up_to_limits() {
+        let options = Options {
+            index_max_points: 0,
+            index_max_zoom: 2,
+            ..Default::default()
+        };
+        let index = GeoJSONVT::new(&line_data(), options).unwrap();
+        assert!(index.tiles.contains_key(&to_id(2, 0, 1)));
+        assert!(!index.tiles.contains_key(&to_id(3, 0, 3)));
+        assert!(index.tiles[&to_id(2, 0, 1)].source.is_some());
+        assert!(index.tiles[&to_id(1, 0, 0)].source.is_none());
+    }
+
+    #[test]
+    fn geojson_vt_updates_affected_tiles() {
+        let point = |id: u64, lng: f64, lat: f64| {
+            json!({
+                "type": "Feature",
+                "id": id,
+                "properties": {},
+                "geometry": { "type": "Point", "coordinates": [lng, lat] }
+            })
+        };
+        let data = json!({
+            "type": "FeatureCollection",
+            "features": [point(1, -100.0, 40.0), point(2, 100.0, -40.0)]
+        });
+        let mut index = GeoJSONVT::new(&data, Options::default()).unwrap();
+        assert_eq!(from_id(to_id(5, 7, 30)), (5, 7, 30));
+        assert!(index.get_tile(4, 3, 6).is_some());
+        assert!(index.get_tile(4, 12, 9).is_some());
+
+        let other = index.get_tile(4, 12, 9).unwrap().features.clone();
+        let tiles = index.tiles.len();
+
+        let changed = index.update(&[json!(1)], &point(1, -101.0, 41.0)).unwrap();
+        assert_eq!(changed.len(), 2);
+        assert!(index.contains(&json!(1)));
+        // The moved point is sliced into the tiles already there, tiles
+        // around the other point are left alone.
+        assert_eq!(index.tiles.len(), tiles);
+        assert_eq!(index.get_tile(4, 12, 9).unwrap().features, other);
+
+        let tile = index.get_tile(4, 3, 6).unwrap();
+        assert_eq!(tile.features.len(), 1);
+        assert_eq!(tile.features[0].id, Some(json!(1)));
+        let moved = tile.features.clone();
+        let mut rebuilt = GeoJSONVT::new(
+            &json!({
+                "type": "FeatureCollection",
+                "features": [point(2, 100.0, -40.0), point(1, -101.0, 41.0)]
+            }),
+            Options::default(),
+        )
+        .unwrap();
+        assert_eq!(rebuilt.get_tile(4, 3, 6).unwrap().features, moved);
+
+        index
+            .update(
+                &[json!(2)],
+                &json!({ "type": "FeatureCollection", "features": [] }),
+            )
+            .unwrap();
+        assert!(!index.contains(&json!(2)));
+        assert!(index.get_tile(4, 12, 9).unwrap().features.is_empty());
+        assert_eq!(index.get_tile(0, 0, 0).unwrap().features.len(), 1);
+    }
+
+    #[test]
+    fn geojson_vt_update_matches_rebuild() {
+        let line = |id: u64, lat: f64| {
+            json!({
+                "type": "Feature",
+                "id": id,
+                "properties": {},
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": [[-120.0, lat], [-60.0, lat + 10.0], [30.0, lat - 5.0]]
+                }
+            })
+        };
+        let options = || Options {
+            index_max_points: 0,
+            index_max_zoom: 3,
+            ..Default::default()
+        };
+        let collection =
+            |features: Vec<Value>| json!({ "type": "FeatureCollection", "features": features });
+
+        let mut index =
+            GeoJSONVT::new(&collection(vec![line(1, 10.0), line(2, 40.0)]), options()).unwrap();
+        let tile_ids = [(2, 1, 1), (4, 5, 6), (6, 21, 24), (8, 90, 100)];
+        for &(z, x, y) in &tile_ids {
+            index.get_tile(z, x, y);
+        }
+        index
+            .update(&[json!(1)], &collection(vec![line(3, -20.0)]))
+            .unwrap();
+
+        let mut rebuilt =
+            GeoJSONVT::new(&collection(vec![line(2, 40.0), line(3, -20.0)]), options()).unwrap();
+        // Tiles emptied by the update are kept, they encode the same as
+        // missing ones.
+        let features = |index: &mut GeoJSONVT, z, x, y| {
+            index
+                .get_tile(z, x, y)
+                .map_or_else(Vec::new, |tile| tile.features.clone())
+        };
+        for &(z, x, y) in &tile_ids {
+            assert_eq!(
+                features(&mut index, z, x, y),
+                features(&mut rebuilt, z, x, y),
+                "tile {}/{}/{}",
+                z,
+                x,
+                y
+            );
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use super::convert::Point;
+
+// Douglas-Peucker which, instead of dropping points, records the squared
+// distance a point would be dropped at, so every zoom level can filter the
+// same geometry with its own tolerance.
+pub(crate) fn simplify(points: &mut [Point], first: usize, last: usize, sq_tolerance: f64) {
+    let mut max_sq_dist = sq_tolerance;
+    let mid = first + (last - first) / 2;
+    let mut min_pos_to_mid = last - first;
+    let mut index = None;
+
+    let (a, b) = (points[first], points[last]);
+    for (i, point) in points.iter().enumerate().take(last).skip(first + 1) {
+        let d = sq_seg_dist(point, &a, &b);
+        if d > max_sq_dist {
+            index = Some(i);
+            max_sq_dist = d;
+        } else if (d - max_sq_dist).abs() < f64::EPSILON {
+            // Prefer the point closest to the middle to get balanced splits.
+            let pos_to_mid = i.abs_diff(mid);
+            if pos_to_mid < min_pos_to_mid {
+                index = Some(i);
+                min_pos_to_mid = pos_to_mid;
+            }
+        }
+    }
+
+    if let Some(index) = index {
+        if max_sq_dist > sq_tolerance {
+            if index - first > 1 {
+                simplify(points, first, index, sq_tolerance);
+            }
+            points[index].z = max_sq_dist;
+            if last - index > 1 {
+                simplify(points, index, last, sq_tolerance);
+            }
+        }
+    }
+}
+
+fn sq_seg_dist(p: &Point, a: &Point, b: &Point) -> f64 {
+    let (mut x, mut y) = (a.x, a.y);
+    let (dx, dy) = (b.x - x, b.y - y);
+
+    if dx != 0.0 || dy != 0.0 {
+        let t = ((p.x - x) * dx + (p.y - y) * dy) / (dx * dx + dy * dy);
+        if t > 1.0 {
+            x = b.x;
+            y = b.y;
+        } else if t > 0.0 {
+            x += dx * t;
+            y += dy * t;
+        }
+    }
+
+    (p.x - x).powi(2) + (p.y - y).powi(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simplify_marks_importance() {
+        let mut points = vec![
+            Point::new(0.0, 0.0, 1.0),
+            Point::new(1.0, 0.95, 0.0),
+            Point::new(2.0, 2.0, 0.0),
+            Point::new(4.0, 0.0, 1.0),
+        ];
+        simplify(&mut points, 0, 3, 0.1);
+        let kept = points
+            .iter()
+            .filter(|point| point.z > 0.1)
+            .map(|point| point.x)
+            .collect::<Vec<_>>();
+        assert_eq!(kept, vec![0.0, 2.0, 4.0]);
+        assert!((points[2].z - 4.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,281 @@
+use super::convert::{Feature, Geometry, Point, Ring};
+use super::Options;
+use serde_json::{Map, Value};
+
+pub(crate) const CLIP_START: &str = "mapbox_clip_start";
+pub(crate) const CLIP_END: &str = "mapbox_clip_end";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FeatureType {
+    Point = 1,
+    LineString = 2,
+    Polygon = 3,
+}
+
+// Geometry is in tile coordinates, points are stored as a single ring.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TileFeature {
+    pub id: Option<Value>,
+    pub kind: FeatureType,
+    pub geometry: Vec<Vec<[i32; 2]>>,
+    pub properties: Map<String, Value>,
+}
+
+#[derive(Debug)]
+pub(crate) struct VectorTile {
+    pub features: Vec<TileFeature>,
+    pub num_points: usize,
+    pub num_simplified: usize,
+    // Kept for tiles that were not split further, so they can be sliced on
+    // demand.
+    pub source: Option<Vec<Feature>>,
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+impl VectorTile {
+    pub fn new(features: Vec<TileFeature>) -> Self {
+        Self {
+            features,
+            num_points: 0,
+            num_simplified: 0,
+            source: None,
+            min_x: 2.0,
+            min_y: 1.0,
+            max_x: -1.0,
+            max_y: 0.0,
+        }
+    }
+}
+
+pub(crate) fn create_tile(
+    features: &[Feature],
+    z: u32,
+    x: u32,
+    y: u32,
+    options: &Options,
+) -> VectorTile {
+    let z2 = f64::from(1u32 << z);
+    let extent = f64::from(options.extent);
+    let tolerance = if z == options.max_zoom {
+        0.0
+    } else {
+        options.tolerance / (z2 * extent)
+    };
+
+    let mut tile = VectorTile::new(Vec::new());
+    let transform = |point: &Point| {
+        [
+            (extent * (point.x * z2 - f64::from(x))).round() as i32,
+            (extent * (point.y * z2 - f64::from(y))).round() as i32,
+        ]
+    };
+
+    for feature in features {
+        tile.min_x = tile.min_x.min(feature.min_x);
+        tile.min_y = tile.min_y.min(feature.min_y);
+        tile.max_x = tile.max_x.max(feature.max_x);
+        tile.max_y = tile.max_y.max(feature.max_y);
+
+        let (kind, rings) = match &feature.geometry {
+            Geometry::Points(points) => {
+                tile.num_points += points.len();
+                tile.num_simplified += points.len();
+                (FeatureType::Point, vec![points.clone()])
+            }
+            // Every line gets its own feature so it can carry its metrics.
+            Geometry::Lines(lines) if options.line_metrics => {
+                for line in lines {
+                    let ring = match simplify_ring(&mut tile, line, tolerance, false) {
+                        Some(ring) => ring,
+                        None => continue,
+                    };
+                    let mut properties = feature.properties.clone();
+                    if line.size > 0.0 {
+                        properties
+                            .insert(CLIP_START.to_owned(), Value::from(line.start / line.size));
+                        properties.insert(CLIP_END.to_owned(), Value::from(line.end / line.size));
+                    }
+                    tile.features.push(TileFeature {
+                        id: feature.id.clone(),
+                        kind: FeatureType::LineString,
+                        geometry: vec![ring.iter().map(transform).collect()],
+                        properties,
+                    });
+                }
+                continue;
+            }
+            Geometry::Lines(lines) => {
+                let rings = lines
+                    .iter()
+                    .filter_map(|ring| simplify_ring(&mut tile, ring, tolerance, false))
+                    .collect();
+                (FeatureType::LineString, rings)
+            }
+            Geometry::Polygons(polygons) => {
+                let mut rings = Vec::new();
+                for polygon in polygons {
+                    for (i, ring) in polygon.iter().enumerate() {
+                        if let Some(mut ring) = simplify_ring(&mut tile, ring, tolerance, true) {
+                            rewind(&mut ring, i == 0);
+                            rings.push(ring);
+                        }
+                    }
+                }
+                (FeatureType::Polygon, rings)
+            }
+        };
+
+        if rings.is_empty() {
+            continue;
+        }
+        tile.features.push(TileFeature {
+            id: feature.id.clone(),
+            kind,
+            geometry: rings
+                .iter()
+                .map(|ring| ring.iter().map(transform).collect())
+                .collect(),
+            properties: feature.properties.clone(),
+        });
+    }
+    tile
+}
+
+fn simplify_ring(
+    tile: &mut VectorTile,
+    ring: &Ring,
+    tolerance: f64,
+    is_polygon: bool,
+) -> Option<Vec<Point>> {
+    let sq_tolerance = tolerance * tolerance;
+    tile.num_points += ring.points.len();
+
+    // Rings too small to be seen at this zoom are dropped entirely.
+    if tolerance > 0.0 && ring.size < if is_polygon { sq_tolerance } else { tolerance } {
+        return None;
+    }
+
+    let points = ring
+        .points
+        .iter()
+        .filter(|point| tolerance == 0.0 || point.z > sq_tolerance)
+        .copied()
+        .collect::<Vec<_>>();
+    tile.num_simplified += points.len();
+    if points.is_empty() {
+        None
+    } else {
+        Some(points)
+    }
+}
+
+// Outer rings are clockwise and holes counter-clockwise in tile coordinates.
+fn rewind(ring: &mut [Point], clockwise: bool) {
+    let mut area = 0.0;
+    let mut j = ring.len().wrapping_sub(1);
+    for (i, point) in ring.iter().enumerate() {
+        let prev = &ring[j];
+        area += (point.x - prev.x) * (point.y + prev.y);
+        j = i;
+    }
+    if (area > 0.0) == clockwise {
+        ring.reverse();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(min: f64, max: f64, clockwise: bool) -> Ring {
+        let mut points = vec![
+            Point::new(min, min, 1.0),
+            Point::new(max, min, 1.0),
+            Point::new(max, max, 1.0),
+            Point::new(min, max, 1.0),
+            Point::new(min, min, 1.0),
+        ];
+        if !clockwise {
+            points.reverse();
+        }
+        Ring {
+            points,
+            size: (max - min).powi(2),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn create_tile_transforms_and_rewinds() {
+        let options = Options {
+            extent: 4096,
+            ..Default::default()
+        };
+        let features = vec![Feature::new(
+            Some(Value::from(1)),
+            Geometry::Polygons(vec![vec![
+                square(0.5, 0.75, false),
+                square(0.55, 0.6, false),
+            ]]),
+            Map::new(),
+        )];
+
+        let tile = create_tile(&features, 1, 1, 1, &options);
+        assert_eq!(tile.features.len(), 1);
+        let feature = &tile.features[0];
+        assert_eq!(feature.kind, FeatureType::Polygon);
+        assert_eq!(
+            feature.geometry[0],
+            vec![[0, 0], [2048, 0], [2048, 2048], [0, 2048], [0, 0]]
+        );
+        assert_eq!(
+            feature.geometry[1],
+            vec![[410, 410], [410, 819], [819, 819], [819, 410], [410, 410]]
+        );
+        assert_eq!(tile.num_points, 10);
+    }
+
+    #[test]
+    fn create_tile_line_metrics() {
+        let line = |start: f64, end: f64| Ring {
+            points: vec![Point::new(0.5, 0.5, 1.0), Point::new(0.75, 0.5, 1.0)],
+            size: 1.0,
+            start,
+            end,
+        };
+        let features = vec![Feature::new(
+            None,
+            Geometry::Lines(vec![line(0.25, 0.5), line(0.5, 0.75)]),
+            Map::new(),
+        )];
+        let options = Options {
+            line_metrics: true,
+            ..Default::default()
+        };
+
+        let tile = create_tile(&features, 1, 1, 1, &options);
+        assert_eq!(tile.features.len(), 2);
+        assert_eq!(tile.features[0].properties[CLIP_START], 0.25);
+        assert_eq!(tile.features[0].properties[CLIP_END], 0.5);
+        assert_eq!(tile.features[1].properties[CLIP_START], 0.5);
+
+        let tile = create_tile(&features, 1, 1, 1, &Options::default());
+        assert_eq!(tile.features.len(), 1);
+        assert!(tile.features[0].properties.is_empty());
+    }
+
+    #[test]
+    fn create_tile_drops_tiny_rings() {
+        let features = vec![Feature::new(
+            None,
+            Geometry::Polygons(vec![vec![square(0.5, 0.500001, true)]]),
+            Map::new(),
+        )];
+        let tile = create_tile(&features, 0, 0, 0, &Options::default());
+        assert!(tile.features.is_empty());
+        assert_eq!(tile.num_points, 5);
+    }
+}
@@ -0,0 +1,33 @@
+use super::clip::{clip, Axis};
+use super::convert::Feature;
+
+// Features crossing the antimeridian are copied into the neighbouring worlds
+// so tiles on both sides of it get their part.
+pub(crate) fn wrap(features: Vec<Feature>, buffer: f64) -> Vec<Feature> {
+    let left = clip(&features, 1.0, -1.0 - buffer, buffer, Axis::X, -1.0, 2.0);
+    let right = clip(
+        &features,
+        1.0,
+        1.0 - buffer,
+        2.0 + buffer,
+        Axis::X,
+        -1.0,
+        2.0,
+    );
+
+    if left.is_empty() && right.is_empty() {
+        return features;
+    }
+
+    let mut merged = clip(&features, 1.0, -buffer, 1.0 + buffer, Axis::X, -1.0, 2.0);
+    merged.extend(shift_features(left, 1.0));
+    merged.extend(shift_features(right, -1.0));
+    merged
+}
+
+fn shift_features(mut features: Vec<Feature>, offset: f64) -> Vec<Feature> {
+    for feature in &mut features {
+        feature.shift_x(offset);
+    }
+    features
+}
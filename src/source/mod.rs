@@ -1,9 +1,24 @@
+mod dem_data;
+mod geojson_vt;
+mod promote_id;
 mod source_cache;
 mod sources;
+mod supercluster;
 mod tile;
 mod tile_bounds;
 mod tile_cache;
 mod tile_id;
 
+pub(crate) use dem_data::DEMData;
+pub(crate) use geojson_vt::Bounds;
+pub(crate) use promote_id::{promote_ids, PromoteId};
 pub(crate) use source_cache::SourceCache;
+pub(crate) use sources::{
+    encode_features, geojson_tile, validate_custom_source, GeoJSON, GeoJSONIndex, TileSet,
+    TileSetOptions, Video,
+};
+pub use sources::{CustomSource, CustomTile, TileCoordinates};
+pub(crate) use tile::vector_data_size;
+pub(crate) use tile_cache::TileCache;
+pub use tile_cache::TileCacheStats;
 pub(crate) use tile_id::*;
@@ -0,0 +1,294 @@
+use crate::util::{fnv1a, Field, Reader, Writer};
+use eyre::{eyre, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+
+// The property used as the feature id, either for every source layer or per
+// source layer.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum PromoteId {
+    Property(String),
+    SourceLayers(HashMap<String, String>),
+}
+
+impl PromoteId {
+    pub fn parse(value: &Value) -> Result<Self> {
+        match value {
+            Value::String(property) => Ok(PromoteId::Property(property.clone())),
+            Value::Object(layers) => layers
+                .iter()
+                .map(|(layer, property)| match property {
+                    Value::String(property) => Ok((layer.clone(), property.clone())),
+                    _ => Err(eyre!("promoteId of \"{}\" is not a string", layer)),
+                })
+                .collect::<Result<_>>()
+                .map(PromoteId::SourceLayers),
+            _ => Err(eyre!("promoteId must be a string or an object")),
+        }
+    }
+
+    pub fn property(&self, source_layer: &str) -> Option<&str> {
+        match self {
+            PromoteId::Property(property) => Some(property),
+            PromoteId::SourceLayers(layers) => layers.get(source_layer).map(String::as_str),
+        }
+    }
+}
+
+// Vector tiles only have unsigned integer ids, other values are hashed into
+// one. The hash doesn't change between tiles or runs, so the ids are stable,
+// but two values can end up with the same id. The promoted property is kept
+// in the feature properties, so its original value is still available.
+pub(crate) fn feature_id(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(number) => match number.as_u64() {
+            Some(id) => Some(id),
+            None => Some(hash(b'n', number.to_string().as_bytes())),
+        },
+        Value::String(string) => Some(hash(b's', string.as_bytes())),
+        Value::Bool(boolean) => Some(hash(b'b', &[*boolean as u8])),
+        _ => None,
+    }
+}
+
+// The value is prefixed by its type, "1.5" and 1.5 get different ids.
+fn hash(kind: u8, data: &[u8]) -> u64 {
+    fnv1a(std::iter::once(&kind).chain(data))
+}
+
+// Sets the ids of the features in an encoded vector tile to the promoted
+// property. Features without the property keep their id.
+pub(crate) fn promote_ids(data: &[u8], promote_id: &PromoteId) -> Result<Vec<u8>> {
+    let mut tile = Writer::default();
+    let mut reader = Reader::new(data);
+    while let Some((field, value, raw)) = reader.field()? {
+        match (field, value) {
+            (3, Field::Bytes(layer)) => tile.bytes_field(3, &promote_layer_ids(layer, promote_id)?),
+            _ => tile.data.extend_from_slice(raw),
+        }
+    }
+    Ok(tile.data)
+}
+
+fn promote_layer_ids(data: &[u8], promote_id: &PromoteId) -> Result<Vec<u8>> {
+    let mut name = "";
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    let mut reader = Reader::new(data);
+    while let Some((field, value, _)) = reader.field()? {
+        match (field, value) {
+            (1, Field::Bytes(bytes)) => name = std::str::from_utf8(bytes)?,
+            (3, Field::Bytes(bytes)) => keys.push(bytes),
+            (4, Field::Bytes(bytes)) => values.push(bytes),
+            _ => {}
+        }
+    }
+
+    let key_index = match promote_id.property(name) {
+        Some(property) => keys.iter().position(|key| *key == property.as_bytes()),
+        None => None,
+    };
+    let key_index = match key_index {
+        Some(key_index) => key_index as u64,
+        None => return Ok(data.to_vec()),
+    };
+
+    let mut layer = Writer::default();
+    let mut reader = Reader::new(data);
+    while let Some((field, value, raw)) = reader.field()? {
+        match (field, value) {
+            (2, Field::Bytes(feature)) => {
+                layer.bytes_field(2, &promote_feature_id(feature, key_index, &values)?)
+            }
+            _ => layer.data.extend_from_slice(raw),
+        }
+    }
+    Ok(layer.data)
+}
+
+fn promote_feature_id(data: &[u8], key_index: u64, values: &[&[u8]]) -> Result<Vec<u8>> {
+    let mut id = None;
+    let mut reader = Reader::new(data);
+    while let Some((field, value, _)) = reader.field()? {
+        if let (2, Field::Bytes(tags)) = (field, value) {
+            let mut tags = Reader::new(tags);
+            while !tags.is_empty() {
+                let (key, value) = (tags.varint()?, tags.varint()?);
+                if key == key_index {
+                    let value = values
+                        .get(value as usize)
+                        .ok_or_else(|| eyre!("Feature tag refers to a missing value"))?;
+                    id = decode_value(value)?.as_ref().and_then(feature_id);
+                }
+            }
+        }
+    }
+    let id = match id {
+        Some(id) => id,
+        None => return Ok(data.to_vec()),
+    };
+
+    let mut feature = Writer::default();
+    feature.varint_field(1, id);
+    let mut reader = Reader::new(data);
+    while let Some((field, _, raw)) = reader.field()? {
+        if field != 1 {
+            feature.data.extend_from_slice(raw);
+        }
+    }
+    Ok(feature.data)
+}
+
+fn decode_value(data: &[u8]) -> Result<Option<Value>> {
+    let mut result = None;
+    let mut reader = Reader::new(data);
+    while let Some((field, value, _)) = reader.field()? {
+        result = match (field, value) {
+            (1, Field::Bytes(bytes)) => Some(Value::from(std::str::from_utf8(bytes)?)),
+            (2, Field::Fixed32(bits)) => Some(Value::from(f32::from_bits(bits))),
+            (3, Field::Fixed64(bits)) => Some(Value::from(f64::from_bits(bits))),
+            (4, Field::Varint(int)) => Some(Value::from(int as i64)),
+            (5, Field::Varint(uint)) => Some(Value::from(uint)),
+            (6, Field::Varint(sint)) => {
+                Some(Value::from((sint >> 1) as i64 ^ -((sint & 1) as i64)))
+            }
+            (7, Field::Varint(boolean)) => Some(Value::from(boolean != 0)),
+            _ => result,
+        };
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::geojson_vt::{encode, FeatureType, TileFeature, VectorTile, LAYER_NAME};
+    use serde_json::json;
+
+    fn feature_ids(data: &[u8]) -> Vec<Option<u64>> {
+        let mut ids = Vec::new();
+        let mut tile = Reader::new(data);
+        while let Some((_, layer, _)) = tile.field().unwrap() {
+            let mut layer = match layer {
+                Field::Bytes(layer) => Reader::new(layer),
+                _ => continue,
+            };
+            while let Some((field, feature, _)) = layer.field().unwrap() {
+                let mut feature = match (field, feature) {
+                    (2, Field::Bytes(feature)) => Reader::new(feature),
+                    _ => continue,
+                };
+                let mut id = None;
+                while let Some((field, value, _)) = feature.field().unwrap() {
+                    if let (1, Field::Varint(value)) = (field, value) {
+                        id = Some(value);
+                    }
+                }
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    fn tile(properties: &[Value]) -> Vec<u8> {
+        let features = properties
+            .iter()
+            .map(|properties| TileFeature {
+                id: None,
+                kind: FeatureType::Point,
+                geometry: vec![vec![[10, 10]]],
+                properties: properties.as_object().unwrap().clone(),
+            })
+            .collect();
+        encode(&VectorTile::new(features), 4096)
+    }
+
+    #[test]
+    fn promote_id_parse() {
+        assert_eq!(
+            PromoteId::parse(&json!("code")).unwrap().property("roads"),
+            Some("code")
+        );
+        let promote_id = PromoteId::parse(&json!({ "roads": "road_id" })).unwrap();
+        assert_eq!(promote_id.property("roads"), Some("road_id"));
+        assert_eq!(promote_id.property("water"), None);
+        assert!(PromoteId::parse(&json!(1)).is_err());
+        assert!(PromoteId::parse(&json!({ "roads": 1 })).is_err());
+    }
+
+    #[test]
+    fn promote_ids_in_vector_tile() {
+        let data = tile(&[
+            json!({ "code": 12, "name": "a" }),
+            json!({ "code": "FR", "name": "b" }),
+            json!({ "name": "c" }),
+        ]);
+        assert_eq!(feature_ids(&data), vec![None, None, None]);
+
+        let promoted = promote_ids(&data, &PromoteId::Property("code".to_owned())).unwrap();
+        assert_eq!(
+            feature_ids(&promoted),
+            vec![Some(12), feature_id(&json!("FR")), None]
+        );
+
+        // Other source layers are left alone.
+        let mut layers = HashMap::new();
+        layers.insert("roads".to_owned(), "code".to_owned());
+        let unchanged = promote_ids(&data, &PromoteId::SourceLayers(layers.clone())).unwrap();
+        assert_eq!(unchanged, data);
+        layers.insert(LAYER_NAME.to_owned(), "name".to_owned());
+        let promoted = promote_ids(&data, &PromoteId::SourceLayers(layers)).unwrap();
+        assert_eq!(feature_ids(&promoted)[2], feature_id(&json!("c")));
+    }
+
+    #[test]
+    fn feature_id_is_stable() {
+        assert_eq!(feature_id(&json!(7)), Some(7));
+        assert_eq!(feature_id(&json!("a")), feature_id(&json!("a")));
+        assert_ne!(feature_id(&json!("a")), feature_id(&json!("b")));
+        assert_ne!(feature_id(&json!(-1)), None);
+        assert_eq!(feature_id(&Value::Null), None);
+    }
+
+    #[test]
+    fn feature_id_of_different_types() {
+        let values = [
+            json!(12),
+            json!("12"),
+            json!(1.5),
+            json!("1.5"),
+            json!(-1),
+            json!("-1"),
+            json!(true),
+            json!("true"),
+            json!(false),
+        ];
+        let ids = values.iter().map(feature_id).collect::<Vec<_>>();
+        for (i, id) in ids.iter().enumerate() {
+            assert!(id.is_some());
+            assert!(!ids[i + 1..].contains(id), "{} collides", values[i]);
+        }
+    }
+
+    #[test]
+    fn promote_ids_keep_property() {
+        let data = tile(&[json!({ "code": "FR" }), json!({ "code": 1.5 })]);
+        let promoted = promote_ids(&data, &PromoteId::Property("code".to_owned())).unwrap();
+        assert_eq!(
+            feature_ids(&promoted),
+            vec![feature_id(&json!("FR")), feature_id(&json!(1.5))]
+        );
+        // Promoting again gives the same ids, the values are still there.
+        let again = promote_ids(&promoted, &PromoteId::Property("code".to_owned())).unwrap();
+        assert_eq!(again, promoted);
+    }
+
+    #[test]
+    fn promote_ids_invalid_length() {
+        // A layer claiming to be longer than the address space.
+        let mut data = vec![0x1a];
+        data.extend_from_slice(&[0xff; 9]);
+        data.push(0x01);
+        assert!(promote_ids(&data, &PromoteId::Property("code".to_owned())).is_err());
+    }
+}
@@ -1,31 +1,133 @@
 use super::sources::SourceControl;
+use super::sources::{CustomSource, GeoJSON, Image, Source, Video};
+use super::tile::{Tile, TileState};
 use super::tile_cache::TileCache;
-use super::{sources::Source, tile::Tile, OverscaledTileId};
+use super::{Bounds, OverscaledTileId, TileCoordinates};
+use crate::event::{MapEvent, RedrawCallback};
 use crate::geo::Transform;
-use crate::network::NetworkManager;
+use crate::network::{NetworkError, NetworkManager};
 use crate::style_spec;
+use crate::worker::WorkerPool;
 use eyre::Result;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::future::{abortable, AbortHandle};
+use futures::{FutureExt, StreamExt};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+type LoadResult = (Tile, Result<()>);
+
+const COMMON_ZOOM_RANGE: f64 = 5.0;
+const REFRESH_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+struct TileRequest {
+    tile_id: OverscaledTileId,
+    uid: usize,
+    handle: AbortHandle,
+}
 
 pub(crate) struct SourceCache {
-    source: Source,
-    tile_cache: TileCache,
+    name: String,
+    source: Arc<Source>,
+    tiles: HashMap<String, Tile>,
+    loading: HashMap<String, TileRequest>,
+    sender: UnboundedSender<LoadResult>,
+    receiver: UnboundedReceiver<LoadResult>,
+    events: Vec<MapEvent>,
+    redraw_callback: Option<RedrawCallback>,
 }
 
 impl SourceCache {
     pub async fn new(
         nm: Arc<NetworkManager>,
+        workers: Arc<WorkerPool>,
         name: &str,
         source: &style_spec::Source,
+        redraw_callback: Option<RedrawCallback>,
     ) -> Result<Self> {
-        let mut source = Source::new(nm, name, source);
-        let tile_cache = TileCache::new(0);
+        let mut source = Source::new(nm, workers, name, source, redraw_callback.clone());
+        source.load().await?;
+        Ok(Self::with_source(name, source, redraw_callback))
+    }
 
+    pub async fn custom(
+        workers: Arc<WorkerPool>,
+        name: &str,
+        source: Arc<dyn CustomSource>,
+        redraw_callback: Option<RedrawCallback>,
+    ) -> Result<Self> {
+        let mut source = Source::custom(workers, name, source);
         source.load().await?;
-        Ok(Self { source, tile_cache })
+        Ok(Self::with_source(name, source, redraw_callback))
+    }
+
+    fn with_source(name: &str, source: Source, redraw_callback: Option<RedrawCallback>) -> Self {
+        let (sender, receiver) = unbounded();
+        Self {
+            name: name.to_owned(),
+            source: Arc::new(source),
+            tiles: HashMap::new(),
+            loading: HashMap::new(),
+            sender,
+            receiver,
+            events: Vec::new(),
+            redraw_callback,
+        }
+    }
+
+    pub fn geojson(&self) -> Option<&GeoJSON> {
+        match &*self.source {
+            Source::GeoJSON(geojson) => Some(geojson),
+            _ => None,
+        }
+    }
+
+    pub fn image(&self) -> Option<&Image> {
+        match &*self.source {
+            Source::Image(image) => Some(image),
+            _ => None,
+        }
+    }
+
+    pub fn video(&self) -> Option<&Video> {
+        match &*self.source {
+            Source::Video(video) => Some(video),
+            _ => None,
+        }
+    }
+
+    // Drops cached tiles overlapping any of the bounds and reloads the ones
+    // in use. `buffer` is a fraction of the tile size.
+    pub fn invalidate(&mut self, bounds: &[Bounds], buffer: f64, tile_cache: &mut TileCache) {
+        let affected = |tile_id: &OverscaledTileId| {
+            let canonical = tile_id.canonical();
+            bounds
+                .iter()
+                .any(|bounds| bounds.intersects_tile(canonical.z, canonical.x, canonical.y, buffer))
+        };
+        tile_cache.retain(&self.name, |tile| !affected(tile.tile_id()));
+
+        // Outdated tiles stay in use until their replacement is loaded.
+        let reload = self
+            .tiles
+            .values()
+            .map(Tile::tile_id)
+            .filter(|tile_id| affected(tile_id))
+            .cloned()
+            .collect::<Vec<_>>();
+        for tile_id in reload {
+            self.reload_tile(&tile_id);
+        }
     }
 
-    pub async fn update(&mut self, transform: &Transform) -> Result<()> {
+    pub async fn update(
+        &mut self,
+        transform: &Transform,
+        tile_cache: &mut TileCache,
+    ) -> Result<()> {
+        self.collect_loaded_tiles()?;
+
         let ideal_tile_ids = transform.covering_tiles(
             self.source.tile_size(),
             Some(self.source.min_zoom()),
@@ -34,16 +136,834 @@ impl SourceCache {
             self.source.reparse_overscaled(),
             self.source.render_world_copies(),
         );
-        for tile_id in ideal_tile_ids {
-            self.add_tile(tile_id.clone()).await?;
+        // Tiles outside of the source's data are never requested.
+        let ideal_tile_ids = ideal_tile_ids
+            .into_iter()
+            .filter(|tile_id| self.source.has_tile(tile_id))
+            .collect::<Vec<_>>();
+        let loaded = self
+            .tiles
+            .values()
+            .filter(|tile| tile.has_data())
+            .map(Tile::tile_id)
+            .collect::<Vec<_>>();
+        let retained = retained_tile_ids(
+            &ideal_tile_ids,
+            &loaded,
+            |tile_id| tile_cache.has(&self.name, tile_id),
+            self.source.min_zoom() as u32,
+            self.source.max_zoom() as u32,
+        );
+        let retained_keys = retained
+            .iter()
+            .map(OverscaledTileId::key)
+            .collect::<HashSet<_>>();
+
+        let removed = self
+            .tiles
+            .keys()
+            .filter(|key| !retained_keys.contains(*key))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in removed {
+            self.remove_tile(&key, tile_cache);
+        }
+
+        // Tiles come sorted by distance from the center, so the closest ones
+        // are requested first. Retained parents are taken from the cache.
+        for tile_id in retained {
+            self.add_tile(tile_id, tile_cache);
+        }
+
+        self.refresh_expired_tiles(SystemTime::now());
+
+        if let Some((frame, _)) = self.video().and_then(Video::current_frame) {
+            for tile in self.tiles.values_mut() {
+                tile.set_image_frame(frame.clone());
+            }
+        }
+        if let Some(error) = self.video().and_then(Video::take_error) {
+            self.events.push(MapEvent::SourceErrored {
+                source: self.name.clone(),
+                error,
+            });
         }
         Ok(())
     }
 
-    async fn add_tile(&mut self, tile_id: OverscaledTileId) -> Result<()> {
+    // Expired tiles are refetched in the background and drawn with their old
+    // data until the new one arrives. Overscaled tiles expire with their max
+    // zoom tile, which is only fetched once.
+    fn refresh_expired_tiles(&mut self, now: SystemTime) {
+        let mut expired = HashMap::new();
+        for tile in self.tiles.values_mut() {
+            tile.check_expiry(now);
+            if tile.is_expired() {
+                let tile_id = tile.tile_id();
+                let max_zoom_id = tile_id.scaled_to(tile_id.canonical().z);
+                expired.insert(max_zoom_id.key(), max_zoom_id);
+            }
+        }
+        for tile_id in expired.values() {
+            self.reload_tile(tile_id);
+        }
+    }
+
+    // Whether no tile is waiting for its data.
+    pub fn is_loaded(&self) -> bool {
+        self.loading.is_empty()
+    }
+
+    pub fn take_events(&mut self) -> Vec<MapEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn tile_event<F>(&mut self, tile_id: &OverscaledTileId, event: F)
+    where
+        F: FnOnce(String, TileCoordinates) -> MapEvent,
+    {
+        let tile = TileCoordinates::from(tile_id.canonical());
+        self.events.push(event(self.name.clone(), tile));
+    }
+
+    pub fn tile(&self, tile_id: &OverscaledTileId) -> Option<&Tile> {
+        self.tiles.get(&tile_id.key())
+    }
+
+    // Cached tiles of this source worth keeping, about five zoom levels for
+    // the viewport.
+    pub fn cache_size(&self, transform: &Transform) -> usize {
+        let tile_size = f64::from(self.source.tile_size());
+        let size = transform.size();
+        let width_in_tiles = (size.x / tile_size).ceil() + 1.0;
+        let height_in_tiles = (size.y / tile_size).ceil() + 1.0;
+        (width_in_tiles * height_in_tiles * COMMON_ZOOM_RANGE).floor() as usize
+    }
+
+    // Reuses a retained or cached tile before loading it.
+    fn add_tile(&mut self, tile_id: OverscaledTileId, tile_cache: &mut TileCache) {
+        let key = tile_id.key();
+        if self.tiles.contains_key(&key) {
+            return;
+        }
+        if let Some(tile) = tile_cache.take(&self.name, &tile_id) {
+            self.tiles.insert(key, tile);
+            return;
+        }
+
         let size = (self.source.tile_size() * tile_id.overscaled_factor()) as usize;
-        let mut tile = Tile::new(tile_id, size);
-        self.source.load_tile(&mut tile).await?;
-        Ok(())
+        let overscaled = self
+            .overscaled_source(&tile_id, tile_cache)
+            .map(|tile| tile.overscaled(tile_id.clone(), size));
+        match overscaled {
+            Some(mut tile) => {
+                if self.source.reparse_overscaled() {
+                    self.request(tile.overscaled(tile_id, size), true);
+                    tile.set_reloading();
+                }
+                self.tiles.insert(key, tile);
+            }
+            None => {
+                self.tiles.insert(key, Tile::new(tile_id.clone(), size));
+                self.request(Tile::new(tile_id, size), false);
+            }
+        }
+    }
+
+    // Past the source's max zoom, tiles share the data of the max zoom tile
+    // or a less overscaled one instead of loading it again.
+    fn overscaled_source<'a>(
+        &'a self,
+        tile_id: &OverscaledTileId,
+        tile_cache: &'a TileCache,
+    ) -> Option<&'a Tile> {
+        (tile_id.canonical().z..tile_id.overscaled_z())
+            .rev()
+            .map(|z| tile_id.scaled_to(z))
+            .filter_map(|parent| {
+                self.tiles
+                    .get(&parent.key())
+                    .or_else(|| tile_cache.get(&self.name, &parent))
+            })
+            .find(|tile| tile.has_data())
+    }
+
+    // Loaded tiles go to the cache, the rest is unloaded.
+    fn remove_tile(&mut self, key: &str, tile_cache: &mut TileCache) {
+        self.abort_request(key);
+        let mut tile = match self.tiles.remove(key) {
+            Some(tile) => tile,
+            None => return,
+        };
+        if tile.state() == TileState::Reloading {
+            tile.cancel_reload();
+        }
+        if tile.has_data() {
+            tile_cache.add(&self.name, tile);
+        } else {
+            tile.unload();
+        }
+    }
+
+    // Overscaled tiles share the data of their max zoom tile, so that one is
+    // loaded again for all of them.
+    fn reload_tile(&mut self, tile_id: &OverscaledTileId) {
+        let max_zoom_id = tile_id.scaled_to(tile_id.canonical().z);
+        self.abort_request(&tile_id.key());
+        self.abort_request(&max_zoom_id.key());
+
+        let mut reloading = false;
+        for tile in self.tiles.values_mut() {
+            if shares_data(tile.tile_id(), &max_zoom_id) {
+                tile.set_reloading();
+                reloading = true;
+            }
+        }
+        if reloading {
+            let size = self.source.tile_size() as usize;
+            self.request(Tile::new(max_zoom_id, size), false);
+        }
+    }
+
+    fn abort_request(&mut self, key: &str) {
+        if let Some(request) = self.loading.remove(key) {
+            request.handle.abort();
+            self.tile_event(&request.tile_id, |source, tile| MapEvent::TileAborted {
+                source,
+                tile,
+            });
+        }
+    }
+
+    fn request(&mut self, mut tile: Tile, reparse: bool) {
+        let tile_id = tile.tile_id().clone();
+        let key = tile_id.key();
+        let uid = tile.uid();
+
+        let source = self.source.clone();
+        let sender = self.sender.clone();
+        let redraw_callback = self.redraw_callback.clone();
+        let (task, handle) = abortable(async move {
+            let result = if reparse {
+                source.reparse_tile(&mut tile).await
+            } else {
+                source.load_tile(&mut tile).await
+            };
+            // The tile is picked up on the next render.
+            if sender.unbounded_send((tile, result)).is_ok() {
+                if let Some(redraw_callback) = redraw_callback {
+                    redraw_callback.notify();
+                }
+            }
+        });
+        tokio::spawn(task);
+
+        self.loading.insert(
+            key,
+            TileRequest {
+                tile_id,
+                uid,
+                handle,
+            },
+        );
+    }
+
+    // Every finished tile is taken in, a fatal error is reported once they
+    // are.
+    fn collect_loaded_tiles(&mut self) -> Result<()> {
+        let mut fatal = None;
+        while let Some(Some((mut tile, result))) = self.receiver.next().now_or_never() {
+            let key = tile.tile_id().key();
+            match self.loading.get(&key) {
+                Some(request) if request.uid == tile.uid() => {
+                    self.loading.remove(&key);
+                }
+                // The request was cancelled after it had already finished.
+                _ => continue,
+            }
+
+            if let Err(e) = result {
+                let error = e.to_string();
+                self.tile_event(tile.tile_id(), |source, tile| MapEvent::TileErrored {
+                    source,
+                    tile,
+                    error,
+                });
+                let is_fatal = match e.downcast_ref::<NetworkError>() {
+                    Some(error) => error.is_fatal(),
+                    None => false,
+                };
+                if is_fatal && fatal.is_none() {
+                    fatal = Some(e);
+                }
+
+                // Tiles that fail to reload keep their previous data.
+                let mut reloaded = false;
+                for current in self.tiles.values_mut() {
+                    let same_data = current.tile_id() == tile.tile_id()
+                        || shares_data(current.tile_id(), tile.tile_id());
+                    if current.state() == TileState::Reloading && same_data {
+                        current.cancel_reload();
+                        // Expired tiles are retried after a while rather
+                        // than on every frame.
+                        if current.is_expired() {
+                            current.set_expires(Some(SystemTime::now() + REFRESH_RETRY_DELAY));
+                        }
+                        reloaded = true;
+                    }
+                }
+                if reloaded {
+                    continue;
+                }
+                tile.set_errored();
+            } else {
+                // Stale data served while offline keeps its past expiry, it is
+                // refreshed after a while rather than on every frame.
+                if tile.is_expired() {
+                    tile.set_expires(Some(SystemTime::now() + REFRESH_RETRY_DELAY));
+                }
+                self.tile_event(tile.tile_id(), |source, tile| MapEvent::TileLoaded {
+                    source,
+                    tile,
+                });
+                self.share_reloaded_data(&tile);
+            }
+            self.backfill_dem(&mut tile);
+            self.tiles.insert(key, tile);
+        }
+        match fatal {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    // Overscaled tiles drawing the previous data of a reloaded max zoom tile
+    // get the new one.
+    fn share_reloaded_data(&mut self, tile: &Tile) {
+        if tile.tile_id().overscaled_factor() != 1 {
+            return;
+        }
+        let tile_size = self.source.tile_size();
+        for current in self.tiles.values_mut() {
+            let tile_id = current.tile_id().clone();
+            if tile_id != *tile.tile_id() && shares_data(&tile_id, tile.tile_id()) {
+                let size = (tile_size * tile_id.overscaled_factor()) as usize;
+                *current = tile.overscaled(tile_id, size);
+            }
+        }
+    }
+
+    // Neighbouring DEM tiles fill in each other's borders, so there are no
+    // seams between them.
+    fn backfill_dem(&mut self, tile: &mut Tile) {
+        let mut neighbors = Vec::new();
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+                if let Some(neighbor) = tile.tile_id().neighbor(dx, dy) {
+                    neighbors.push((neighbor.key(), dx, dy));
+                }
+            }
+        }
+
+        let dem = match tile.dem_data_mut() {
+            Some(dem) => dem,
+            None => return,
+        };
+        for (key, dx, dy) in neighbors {
+            if let Some(border) = self.tiles.get_mut(&key).and_then(Tile::dem_data_mut) {
+                dem.backfill_border(border, dx, dy);
+                border.backfill_border(dem, -dx, -dy);
+            }
+        }
+    }
+}
+
+// Whether the tile is the max zoom tile or one overscaling it.
+fn shares_data(tile_id: &OverscaledTileId, max_zoom_id: &OverscaledTileId) -> bool {
+    tile_id.scaled_to(tile_id.canonical().z) == *max_zoom_id
+}
+
+// The ideal tiles plus loaded tiles covering the ones without data yet: their
+// children when zooming out and otherwise the closest parent, so there are no
+// empty gaps while loading.
+fn retained_tile_ids<F>(
+    ideal_tile_ids: &[OverscaledTileId],
+    loaded: &[&OverscaledTileId],
+    cached: F,
+    min_zoom: u32,
+    max_zoom: u32,
+) -> Vec<OverscaledTileId>
+where
+    F: Fn(&OverscaledTileId) -> bool,
+{
+    let loaded_keys = loaded
+        .iter()
+        .map(|tile_id| tile_id.key())
+        .collect::<HashSet<_>>();
+    let mut retained = ideal_tile_ids.to_vec();
+    let mut retained_keys = ideal_tile_ids
+        .iter()
+        .map(OverscaledTileId::key)
+        .collect::<HashSet<_>>();
+    let mut retain = |tile_id: OverscaledTileId, retained: &mut Vec<OverscaledTileId>| {
+        if retained_keys.insert(tile_id.key()) {
+            retained.push(tile_id);
+        }
+    };
+
+    for tile_id in ideal_tile_ids {
+        if loaded_keys.contains(&tile_id.key()) {
+            continue;
+        }
+
+        for &child in loaded {
+            if child.is_descendant_of(tile_id) {
+                retain(child.clone(), &mut retained);
+            }
+        }
+        let covered = tile_id
+            .children(max_zoom)
+            .iter()
+            .all(|child| loaded_keys.contains(&child.key()));
+        if covered {
+            continue;
+        }
+
+        for z in (min_zoom..tile_id.overscaled_z()).rev() {
+            let parent = tile_id.scaled_to(z);
+            if loaded_keys.contains(&parent.key()) || cached(&parent) {
+                retain(parent, &mut retained);
+                break;
+            }
+        }
+    }
+    retained
+}
+
+impl Drop for SourceCache {
+    fn drop(&mut self) {
+        for request in self.loading.values() {
+            request.handle.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::IdleTracker;
+    use crate::geo::LngLat;
+    use crate::source::{CustomTile, TileCoordinates};
+    use async_trait::async_trait;
+    use eyre::eyre;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::time::delay_for;
+
+    // Empty tiles, counting how many were loaded.
+    #[derive(Default)]
+    struct CountingSource {
+        loads: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl CustomSource for CountingSource {
+        async fn load_tile(&self, _tile: TileCoordinates) -> Result<CustomTile> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            Ok(CustomTile::Empty)
+        }
+    }
+
+    struct FailingSource;
+
+    #[async_trait]
+    impl CustomSource for FailingSource {
+        async fn load_tile(&self, _tile: TileCoordinates) -> Result<CustomTile> {
+            Err(eyre!("unavailable"))
+        }
+    }
+
+    // The tile at x 0 is refused, the others are empty.
+    struct UnauthorizedSource;
+
+    #[async_trait]
+    impl CustomSource for UnauthorizedSource {
+        async fn load_tile(&self, tile: TileCoordinates) -> Result<CustomTile> {
+            if tile.x != 0 {
+                return Ok(CustomTile::Empty);
+            }
+            Err(NetworkError::Unauthorized {
+                url: "https://a.com/1/0/0.pbf".to_owned(),
+                status: 401,
+            }
+            .into())
+        }
+    }
+
+    // Tiles that never finish loading.
+    struct PendingSource;
+
+    #[async_trait]
+    impl CustomSource for PendingSource {
+        async fn load_tile(&self, _tile: TileCoordinates) -> Result<CustomTile> {
+            futures::future::pending().await
+        }
+    }
+
+    async fn custom_cache(
+        source: Arc<dyn CustomSource>,
+        redraw_callback: Option<RedrawCallback>,
+    ) -> SourceCache {
+        let workers = Arc::new(WorkerPool::new(1).unwrap());
+        SourceCache::custom(workers, "custom", source, redraw_callback)
+            .await
+            .unwrap()
+    }
+
+    async fn wait_until_loaded(cache: &mut SourceCache) {
+        loop {
+            cache.collect_loaded_tiles().unwrap();
+            if cache.is_loaded() {
+                break;
+            }
+            delay_for(Duration::from_millis(1)).await;
+        }
+    }
+
+    fn viewport(zoom: f64, lng: f64) -> Transform {
+        let mut transform = Transform::new(0.0, 22.0, 0.0, 60.0, true);
+        transform.resize(512.0, 512.0);
+        transform.set_zoom(zoom);
+        transform.set_center(LngLat::new(lng, 0.0));
+        transform
+    }
+
+    // Updates the cache like a rendered frame and returns its events, with
+    // `Idle` added like `Map::render` does.
+    async fn frame(
+        cache: &mut SourceCache,
+        transform: &Transform,
+        tile_cache: &mut TileCache,
+        idle: &mut IdleTracker,
+    ) -> Vec<MapEvent> {
+        cache.update(transform, tile_cache).await.unwrap();
+        let mut events = cache.take_events();
+        if idle.update(cache.is_loaded()) {
+            events.push(MapEvent::Idle);
+        }
+        events
+    }
+
+    // Renders frames until the cache is loaded.
+    async fn frames_until_loaded(
+        cache: &mut SourceCache,
+        transform: &Transform,
+        tile_cache: &mut TileCache,
+        idle: &mut IdleTracker,
+    ) -> Vec<MapEvent> {
+        let mut events = Vec::new();
+        loop {
+            events.extend(frame(cache, transform, tile_cache, idle).await);
+            if cache.is_loaded() {
+                return events;
+            }
+            delay_for(Duration::from_millis(1)).await;
+        }
+    }
+
+    fn retained_keys(retained: &[OverscaledTileId]) -> HashSet<String> {
+        retained.iter().map(OverscaledTileId::key).collect()
+    }
+
+    #[test]
+    fn retain_parent_when_zooming_in() {
+        let parent = OverscaledTileId::new(2, 0, 2, 1, 1);
+        let grandparent = OverscaledTileId::new(1, 0, 1, 0, 0);
+        let ideal = parent.children(22);
+
+        let retained = retained_tile_ids(&ideal, &[&parent, &grandparent], |_| false, 0, 22);
+        assert_eq!(retained.len(), 5);
+        assert_eq!(&retained[4], &parent);
+
+        // The closest parent is taken from the cache when it is not retained.
+        let cached = OverscaledTileId::new(1, 0, 1, 0, 0);
+        let retained = retained_tile_ids(&ideal, &[], |tile_id| *tile_id == cached, 0, 22);
+        assert_eq!(&retained[4], &cached);
+
+        let retained = retained_tile_ids(&ideal, &[], |_| false, 0, 22);
+        assert_eq!(retained, ideal);
+    }
+
+    #[test]
+    fn retain_overscaled_parent() {
+        let max_zoom = OverscaledTileId::new(14, 0, 14, 8800, 5373);
+        let overscaled = OverscaledTileId::new(15, 0, 14, 8800, 5373);
+        let ideal = vec![OverscaledTileId::new(16, 0, 14, 8800, 5373)];
+
+        let retained = retained_tile_ids(&ideal, &[&max_zoom, &overscaled], |_| false, 0, 14);
+        assert_eq!(retained.len(), 2);
+        assert_eq!(&retained[1], &overscaled);
+        assert!(ideal[0].is_descendant_of(&max_zoom));
+    }
+
+    #[test]
+    fn retain_children_when_zooming_out() {
+        let ideal = vec![OverscaledTileId::new(1, 0, 1, 0, 0)];
+        let children = ideal[0].children(22);
+        let loaded = children.iter().collect::<Vec<_>>();
+        let parent = OverscaledTileId::new(0, 0, 0, 0, 0);
+
+        // Loaded children cover the whole tile, no parent is needed.
+        let retained = retained_tile_ids(&ideal, &loaded, |_| true, 0, 22);
+        assert_eq!(retained.len(), 5);
+        assert!(!retained_keys(&retained).contains(&parent.key()));
+
+        // Partially covered, the parent fills the rest.
+        let retained = retained_tile_ids(&ideal, &loaded[..2], |_| true, 0, 22);
+        let keys = retained_keys(&retained);
+        assert_eq!(retained.len(), 4);
+        assert!(keys.contains(&children[1].key()));
+        assert!(keys.contains(&parent.key()));
+
+        // Tiles of another world copy don't count.
+        let other_world = OverscaledTileId::new(2, 1, 2, 0, 0);
+        let retained = retained_tile_ids(&ideal, &[&other_world], |_| false, 0, 22);
+        assert_eq!(retained, ideal);
+    }
+
+    #[tokio::test]
+    async fn refresh_overscaled_tiles_once() {
+        let source = Arc::new(CountingSource::default());
+        let mut cache = custom_cache(source.clone(), None).await;
+
+        // Only the overscaled tiles are in use, sharing expired data.
+        let mut tile = Tile::new(OverscaledTileId::new(10, 0, 10, 1, 1), 512);
+        tile.set_vector_data(Default::default(), 0);
+        tile.set_expires(Some(SystemTime::now() - Duration::from_secs(1)));
+        let overscaled = (11..13)
+            .map(|z| OverscaledTileId::new(z, 0, 10, 1, 1))
+            .collect::<Vec<_>>();
+        for tile_id in &overscaled {
+            let size = 512 * tile_id.overscaled_factor() as usize;
+            cache
+                .tiles
+                .insert(tile_id.key(), tile.overscaled(tile_id.clone(), size));
+        }
+
+        cache.refresh_expired_tiles(SystemTime::now());
+        assert_eq!(cache.loading.len(), 1);
+        // Still expired on the next frame, but already being refreshed.
+        cache.refresh_expired_tiles(SystemTime::now());
+        wait_until_loaded(&mut cache).await;
+        assert_eq!(source.loads.load(Ordering::SeqCst), 1);
+
+        for tile_id in &overscaled {
+            let tile = cache.tile(tile_id).unwrap();
+            assert_eq!(tile.state(), TileState::Loaded);
+            assert_eq!(tile.expires(), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn redraw_when_tile_arrives() {
+        let redraws = Arc::new(AtomicUsize::new(0));
+        let redraw_callback = {
+            let redraws = redraws.clone();
+            RedrawCallback::new(move || {
+                redraws.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+        let source = Arc::new(CountingSource::default());
+        let mut cache = custom_cache(source, Some(redraw_callback)).await;
+
+        let size = cache.source.tile_size() as usize;
+        for x in 0..2 {
+            cache.request(Tile::new(OverscaledTileId::new(1, 0, 1, x, 0), size), false);
+        }
+        // Nothing needs to render for the tiles to be loaded.
+        while redraws.load(Ordering::SeqCst) < 2 {
+            delay_for(Duration::from_millis(1)).await;
+        }
+        cache.collect_loaded_tiles().unwrap();
+        assert!(cache.is_loaded());
+        assert_eq!(cache.tiles.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn tile_loaded_events_and_idle() {
+        let source = Arc::new(CountingSource::default());
+        let mut cache = custom_cache(source.clone(), None).await;
+        let mut tile_cache = TileCache::new(u64::MAX);
+        let mut idle = IdleTracker::default();
+
+        let transform = viewport(1.0, 0.0);
+        let events = frames_until_loaded(&mut cache, &transform, &mut tile_cache, &mut idle).await;
+        let loaded = events
+            .iter()
+            .filter(
+                |event| matches!(event, MapEvent::TileLoaded { source, .. } if source == "custom"),
+            )
+            .count();
+        assert!(loaded > 0);
+        assert_eq!(loaded, source.loads.load(Ordering::SeqCst));
+        // Idle comes once, after the last tile.
+        assert_eq!(events.last(), Some(&MapEvent::Idle));
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| **event == MapEvent::Idle)
+                .count(),
+            1
+        );
+        assert!(frame(&mut cache, &transform, &mut tile_cache, &mut idle)
+            .await
+            .is_empty());
+
+        // Moving the map loads other tiles, then it is idle again.
+        let transform = viewport(4.0, 100.0);
+        let events = frame(&mut cache, &transform, &mut tile_cache, &mut idle).await;
+        assert!(!events.contains(&MapEvent::Idle));
+        let events = frames_until_loaded(&mut cache, &transform, &mut tile_cache, &mut idle).await;
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, MapEvent::TileLoaded { tile, .. } if tile.z == 4)));
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| **event == MapEvent::Idle)
+                .count(),
+            1
+        );
+        assert_eq!(events.last(), Some(&MapEvent::Idle));
+    }
+
+    #[tokio::test]
+    async fn tile_errored_events() {
+        let mut cache = custom_cache(Arc::new(FailingSource), None).await;
+        let mut tile_cache = TileCache::new(u64::MAX);
+        let mut idle = IdleTracker::default();
+
+        let transform = viewport(1.0, 0.0);
+        let events = frames_until_loaded(&mut cache, &transform, &mut tile_cache, &mut idle).await;
+        let errors = events
+            .iter()
+            .filter_map(|event| match event {
+                MapEvent::TileErrored { error, .. } => Some(error.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert!(!errors.is_empty());
+        assert!(errors.iter().all(|error| *error == "unavailable"));
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, MapEvent::TileLoaded { .. })));
+        // Failed tiles aren't waited for.
+        assert_eq!(events.last(), Some(&MapEvent::Idle));
+    }
+
+    #[tokio::test]
+    async fn tile_aborted_events() {
+        let mut cache = custom_cache(Arc::new(PendingSource), None).await;
+        let mut tile_cache = TileCache::new(u64::MAX);
+        let mut idle = IdleTracker::default();
+
+        let transform = viewport(2.0, 0.0);
+        assert!(frame(&mut cache, &transform, &mut tile_cache, &mut idle)
+            .await
+            .is_empty());
+        let requested = cache
+            .loading
+            .values()
+            .map(|request| TileCoordinates::from(request.tile_id.canonical()))
+            .collect::<HashSet<_>>();
+        assert!(!requested.is_empty());
+
+        // Tiles that left the viewport are aborted, the new ones are loading.
+        let transform = viewport(6.0, 100.0);
+        let events = frame(&mut cache, &transform, &mut tile_cache, &mut idle).await;
+        let aborted = events
+            .iter()
+            .filter_map(|event| match event {
+                MapEvent::TileAborted { source, tile } if source == "custom" => Some(*tile),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        assert_eq!(aborted, requested);
+        assert!(!events.contains(&MapEvent::Idle));
+        assert!(!cache.is_loaded());
+    }
+
+    #[tokio::test]
+    async fn stale_tiles_are_not_refreshed_every_frame() {
+        let source = Arc::new(CountingSource::default());
+        let mut cache = custom_cache(source.clone(), None).await;
+
+        // A reload answered with cached data that expired a while ago.
+        let tile_id = OverscaledTileId::new(1, 0, 1, 0, 0);
+        let mut tile = Tile::new(tile_id.clone(), 512);
+        tile.set_vector_data(Default::default(), 0);
+        tile.set_expires(Some(SystemTime::now() - Duration::from_secs(60)));
+        assert!(tile.is_expired());
+        let (handle, _) = AbortHandle::new_pair();
+        cache.loading.insert(
+            tile_id.key(),
+            TileRequest {
+                tile_id: tile_id.clone(),
+                uid: tile.uid(),
+                handle,
+            },
+        );
+        cache.sender.unbounded_send((tile, Ok(()))).unwrap();
+
+        cache.collect_loaded_tiles().unwrap();
+        let tile = cache.tile(&tile_id).unwrap();
+        assert_eq!(tile.state(), TileState::Loaded);
+        assert!(tile.expires().unwrap() > SystemTime::now());
+        cache.refresh_expired_tiles(SystemTime::now());
+        assert!(cache.is_loaded());
+        assert_eq!(source.loads.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn fatal_error_after_collecting_tiles() {
+        let redraws = Arc::new(AtomicUsize::new(0));
+        let redraw_callback = {
+            let redraws = redraws.clone();
+            RedrawCallback::new(move || {
+                redraws.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+        let mut cache = custom_cache(Arc::new(UnauthorizedSource), Some(redraw_callback)).await;
+        let tile_ids = (0..3)
+            .map(|x| OverscaledTileId::new(2, 0, 2, x, 0))
+            .collect::<Vec<_>>();
+        for tile_id in &tile_ids {
+            cache
+                .tiles
+                .insert(tile_id.key(), Tile::new(tile_id.clone(), 512));
+            cache.request(Tile::new(tile_id.clone(), 512), false);
+        }
+        while redraws.load(Ordering::SeqCst) < tile_ids.len() {
+            delay_for(Duration::from_millis(1)).await;
+        }
+
+        assert!(cache.collect_loaded_tiles().is_err());
+        // The refused tile isn't left loading and the others are taken in.
+        assert!(cache.is_loaded());
+        assert_eq!(
+            cache.tile(&tile_ids[0]).unwrap().state(),
+            TileState::Errored
+        );
+        for tile_id in &tile_ids[1..] {
+            assert_eq!(cache.tile(tile_id).unwrap().state(), TileState::Loaded);
+        }
+        let events = cache.take_events();
+        assert_eq!(events.len(), 3);
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, MapEvent::TileErrored { tile, .. } if tile.x == 0)));
+        assert!(cache.collect_loaded_tiles().is_ok());
     }
 }
@@ -0,0 +1,314 @@
+use super::SourceControl;
+use crate::source::geojson_vt::{self, GeoJSONVT};
+use crate::source::tile::Tile;
+use crate::source::tile_bounds::TileBounds;
+use crate::source::{CanonicalTileId, OverscaledTileId};
+use crate::worker::WorkerPool;
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use serde_json::{json, Value};
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
+const EXTENT: u32 = 8192;
+const MAX_ZOOM: f32 = 24.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileCoordinates {
+    pub z: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl From<&CanonicalTileId> for TileCoordinates {
+    fn from(tile_id: &CanonicalTileId) -> Self {
+        Self {
+            z: tile_id.z,
+            x: tile_id.x,
+            y: tile_id.y,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum CustomTile {
+    // GeoJSON features in longitude and latitude, only the part inside the
+    // tile is kept.
+    Features(Vec<Value>),
+    // An encoded Mapbox Vector Tile.
+    VectorTile(Vec<u8>),
+    // An encoded PNG, JPEG or WebP image.
+    Image(Vec<u8>),
+    Empty,
+}
+
+// Tiles of a source implemented by the application, styled like those of the
+// sources in the style.
+#[async_trait]
+pub trait CustomSource: Send + Sync {
+    async fn load_tile(&self, tile: TileCoordinates) -> Result<CustomTile>;
+
+    fn tile_size(&self) -> u32 {
+        512
+    }
+
+    fn min_zoom(&self) -> f32 {
+        0.0
+    }
+
+    fn max_zoom(&self) -> f32 {
+        22.0
+    }
+
+    // [west, south, east, north], no tiles are requested outside of them.
+    fn bounds(&self) -> Option<[f64; 4]> {
+        None
+    }
+}
+
+pub(crate) struct Custom {
+    workers: Arc<WorkerPool>,
+    name: String,
+    source: Arc<dyn CustomSource>,
+    tile_bounds: Option<TileBounds>,
+}
+
+impl Custom {
+    pub fn new(workers: Arc<WorkerPool>, name: &str, source: Arc<dyn CustomSource>) -> Self {
+        Self {
+            workers,
+            name: name.to_owned(),
+            tile_bounds: source.bounds().as_ref().map(TileBounds::new),
+            source,
+        }
+    }
+}
+
+// Checked when the source is added, the tiles depend on them.
+pub(crate) fn validate_custom_source(source: &dyn CustomSource) -> Result<()> {
+    let tile_size = source.tile_size();
+    if !tile_size.is_power_of_two() {
+        return Err(eyre!(
+            "Custom source tile size {} is not a power of two",
+            tile_size
+        ));
+    }
+    let (min_zoom, max_zoom) = (source.min_zoom(), source.max_zoom());
+    if !(0.0..=MAX_ZOOM).contains(&min_zoom)
+        || !(0.0..=MAX_ZOOM).contains(&max_zoom)
+        || min_zoom > max_zoom
+    {
+        return Err(eyre!(
+            "Invalid custom source zoom range {}..{}",
+            min_zoom,
+            max_zoom
+        ));
+    }
+    Ok(())
+}
+
+// Slices features in longitude and latitude into an encoded vector tile, run
+// on the workers.
+pub(crate) fn encode_features(
+    features: Vec<Value>,
+    tile_id: &CanonicalTileId,
+    tile_size: u32,
+    max_zoom: f32,
+) -> Result<Option<Vec<u8>>> {
+    let data = json!({ "type": "FeatureCollection", "features": features });
+    let scale = f64::from(EXTENT) / f64::from(tile_size);
+    let mut index = GeoJSONVT::new(
+        &data,
+        geojson_vt::Options {
+            max_zoom: max_zoom as u32,
+            index_max_zoom: 0,
+            extent: EXTENT,
+            tolerance: 3.0 * scale,
+            buffer: 64.0 * scale,
+            ..Default::default()
+        },
+    )?;
+    Ok(index
+        .get_tile(tile_id.z, tile_id.x, tile_id.y)
+        .map(|vector_tile| geojson_vt::encode(vector_tile, EXTENT)))
+}
+
+impl Debug for Custom {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Custom").field("name", &self.name).finish()
+    }
+}
+
+#[async_trait]
+impl SourceControl for Custom {
+    async fn load(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn has_tile(&self, tile_id: &OverscaledTileId) -> bool {
+        match &self.tile_bounds {
+            Some(tile_bounds) => tile_bounds.contains(tile_id.canonical()),
+            None => true,
+        }
+    }
+
+    async fn load_tile(&self, tile: &mut Tile) -> Result<()> {
+        let tile_id = tile.tile_id().canonical().clone();
+
+        match self
+            .source
+            .load_tile(TileCoordinates::from(&tile_id))
+            .await?
+        {
+            CustomTile::Features(features) => {
+                let (vector_tile, size) = self
+                    .workers
+                    .custom_features_tile(
+                        features,
+                        tile_id,
+                        self.source.tile_size(),
+                        self.source.max_zoom(),
+                    )
+                    .await?;
+                tile.set_vector_data(vector_tile, size);
+            }
+            CustomTile::VectorTile(data) => {
+                let (vector_tile, size) = self.workers.parse_vector_tile(data, None).await?;
+                tile.set_vector_data(vector_tile, size);
+            }
+            CustomTile::Image(data) => {
+                let image = self.workers.decode_raster_tile(data).await?;
+                tile.set_raster_data(Some(image));
+            }
+            CustomTile::Empty => tile.set_vector_data(Default::default(), 0),
+        }
+
+        Ok(())
+    }
+
+    fn tile_size(&self) -> u32 {
+        self.source.tile_size()
+    }
+
+    fn min_zoom(&self) -> f32 {
+        self.source.min_zoom()
+    }
+
+    fn max_zoom(&self) -> f32 {
+        self.source.max_zoom()
+    }
+
+    fn round_zoom(&self) -> bool {
+        false
+    }
+
+    fn reparse_overscaled(&self) -> bool {
+        false
+    }
+
+    fn render_world_copies(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Points;
+
+    #[async_trait]
+    impl CustomSource for Points {
+        async fn load_tile(&self, tile: TileCoordinates) -> Result<CustomTile> {
+            if tile.z == 0 {
+                return Ok(CustomTile::Empty);
+            }
+            Ok(CustomTile::Features(vec![json!({
+                "type": "Feature",
+                "properties": { "name": "origin" },
+                "geometry": { "type": "Point", "coordinates": [1.0, 1.0] }
+            })]))
+        }
+
+        fn max_zoom(&self) -> f32 {
+            10.0
+        }
+
+        fn bounds(&self) -> Option<[f64; 4]> {
+            Some([0.0, 0.0, 10.0, 10.0])
+        }
+    }
+
+    #[test]
+    fn custom_source_features() {
+        let workers = Arc::new(WorkerPool::new(1).unwrap());
+        let custom = Custom::new(workers, "custom", Arc::new(Points));
+        assert_eq!(custom.max_zoom(), 10.0);
+        assert!(custom.has_tile(&OverscaledTileId::new(1, 0, 1, 1, 0)));
+        assert!(!custom.has_tile(&OverscaledTileId::new(1, 0, 1, 0, 1)));
+
+        let mut tile = Tile::new(OverscaledTileId::new(1, 0, 1, 1, 0), 512);
+        futures::executor::block_on(custom.load_tile(&mut tile)).unwrap();
+        assert!(tile.has_data());
+        assert!(tile.memory_size() > 0);
+
+        let mut tile = Tile::new(OverscaledTileId::new(0, 0, 0, 0, 0), 512);
+        futures::executor::block_on(custom.load_tile(&mut tile)).unwrap();
+        assert!(tile.has_data());
+        assert_eq!(tile.memory_size(), 0);
+    }
+
+    struct Options(u32, f32, f32);
+
+    #[async_trait]
+    impl CustomSource for Options {
+        async fn load_tile(&self, _tile: TileCoordinates) -> Result<CustomTile> {
+            Ok(CustomTile::Empty)
+        }
+
+        fn tile_size(&self) -> u32 {
+            self.0
+        }
+
+        fn min_zoom(&self) -> f32 {
+            self.1
+        }
+
+        fn max_zoom(&self) -> f32 {
+            self.2
+        }
+    }
+
+    #[test]
+    fn custom_source_validation() {
+        assert!(validate_custom_source(&Points).is_ok());
+        assert!(validate_custom_source(&Options(256, 2.0, 2.0)).is_ok());
+        assert!(validate_custom_source(&Options(0, 0.0, 22.0)).is_err());
+        assert!(validate_custom_source(&Options(300, 0.0, 22.0)).is_err());
+        assert!(validate_custom_source(&Options(512, 10.0, 5.0)).is_err());
+        assert!(validate_custom_source(&Options(512, -1.0, 5.0)).is_err());
+        assert!(validate_custom_source(&Options(512, 0.0, f32::NAN)).is_err());
+    }
+
+    #[test]
+    fn encode_features_large_tiles() {
+        let features = vec![json!({
+            "type": "Feature",
+            "properties": {},
+            "geometry": { "type": "Point", "coordinates": [-0.1, 1.0] }
+        })];
+        // The point is just west of the tile, in its buffer. Tiles larger
+        // than the extent used to get no buffer at all.
+        let encode = |x, y| {
+            encode_features(
+                features.clone(),
+                &CanonicalTileId::new(1, x, y),
+                16384,
+                10.0,
+            )
+            .unwrap()
+            .unwrap()
+        };
+        assert!(encode(1, 0).len() > encode(1, 1).len());
+    }
+}
@@ -1,62 +1,444 @@
 use super::SourceControl;
+use crate::source::geojson_vt::{self, Bounds, GeoJSONVT, VectorTile, LAYER_NAME};
+use crate::source::supercluster::{self, ClusterProperties, Supercluster};
+use crate::source::tile::{vector_tile_size, Tile};
+use crate::source::{CanonicalTileId, PromoteId};
+use crate::style_spec;
+use crate::worker::WorkerPool;
 use crate::{network::NetworkManager, source::OverscaledTileId};
-use crate::{source::tile::Tile, style_spec};
 use async_trait::async_trait;
-use eyre::Result;
-use std::sync::Arc;
+use eyre::{eyre, Result};
+use mvt::FeatureWithCoordinates;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+const EXTENT: u32 = 8192;
+const TILE_SIZE: u32 = 512;
+
+// Clustered sources only keep their points.
+#[derive(Debug)]
+pub(crate) enum GeoJSONIndex {
+    Tiles(GeoJSONVT),
+    Clusters(Supercluster),
+}
+
+impl GeoJSONIndex {
+    fn contains(&self, id: &Value) -> bool {
+        match self {
+            GeoJSONIndex::Tiles(tiles) => tiles.contains(id),
+            GeoJSONIndex::Clusters(clusters) => clusters.point(id).is_some(),
+        }
+    }
+
+    // Slices a tile and copies its features out of the index.
+    fn tile(&mut self, tile_id: &CanonicalTileId) -> Option<VectorTile> {
+        match self {
+            GeoJSONIndex::Tiles(tiles) => tiles
+                .get_tile(tile_id.z, tile_id.x, tile_id.y)
+                .map(|vector_tile| VectorTile::new(vector_tile.features.clone())),
+            GeoJSONIndex::Clusters(clusters) => clusters.get_tile(tile_id.z, tile_id.x, tile_id.y),
+        }
+    }
+}
+
+// A tile of the index and the estimated size of its data, made on a worker
+// since it can take a while for large data. The index is only locked while
+// the tile is sliced, so updates don't wait for the conversion.
+pub(crate) fn geojson_tile(
+    index: &Mutex<GeoJSONIndex>,
+    tile_id: &CanonicalTileId,
+) -> Result<(mvt::Tile<FeatureWithCoordinates>, usize)> {
+    let tile = index.lock().unwrap().tile(tile_id);
+    let tile = match tile {
+        Some(tile) => tile,
+        None => return Ok((Default::default(), 0)),
+    };
+    // mvt tiles are only built by its decoder.
+    let vector_data = mvt::decode(&geojson_vt::encode(&tile, EXTENT))?;
+    Ok((vector_data, vector_tile_size(&tile)))
+}
 
 #[derive(Debug)]
 pub(crate) struct GeoJSON {
     nm: Arc<NetworkManager>,
+    workers: Arc<WorkerPool>,
     name: String,
     options: style_spec::GeoJSON,
+    promote_id: Option<PromoteId>,
+    // The next id handed out with `generateId`.
+    next_id: AtomicU64,
+    // Shared with the workers generating the tiles.
+    index: Option<Arc<Mutex<GeoJSONIndex>>>,
 }
 
 impl GeoJSON {
-    pub fn new(nm: Arc<NetworkManager>, name: &str, options: &style_spec::GeoJSON) -> Self {
+    pub fn new(
+        nm: Arc<NetworkManager>,
+        workers: Arc<WorkerPool>,
+        name: &str,
+        options: &style_spec::GeoJSON,
+    ) -> Self {
         Self {
             nm,
+            workers,
             name: name.to_owned(),
             options: options.clone(),
+            promote_id: None,
+            next_id: AtomicU64::new(0),
+            index: None,
+        }
+    }
+
+    // The tile buffer as a fraction of the tile size.
+    pub fn tile_buffer(&self) -> f64 {
+        f64::from(self.options.buffer) / f64::from(TILE_SIZE)
+    }
+
+    // Replaces all of the data, so every tile is affected.
+    pub async fn set_data(&self, data: Value) -> Result<Vec<Bounds>> {
+        let mut data = self.resolve_data(Some(&data)).await?;
+        self.next_id.store(0, Ordering::Relaxed);
+        self.assign_ids(&mut data, true);
+        let index = self.create_index(&data)?;
+        *self.index()?.lock().unwrap() = index;
+        Ok(vec![Bounds::world()])
+    }
+
+    // Features replace the ones with the same id.
+    pub fn add_features(&self, mut features: Vec<Value>) -> Result<Vec<Bounds>> {
+        for feature in &mut features {
+            self.assign_id(feature, true);
+        }
+        let ids = feature_ids(&features)?;
+        self.update(&ids, features, false)
+    }
+
+    pub fn update_features(&self, mut features: Vec<Value>) -> Result<Vec<Bounds>> {
+        for feature in &mut features {
+            self.assign_id(feature, false);
+        }
+        let ids = feature_ids(&features)?;
+        self.update(&ids, features, true)
+    }
+
+    pub fn remove_features(&self, ids: &[Value]) -> Result<Vec<Bounds>> {
+        self.update(ids, Vec::new(), false)
+    }
+
+    // Returns the bounds of the features that changed.
+    fn update(&self, ids: &[Value], features: Vec<Value>, existing: bool) -> Result<Vec<Bounds>> {
+        let mut index = self.index()?.lock().unwrap();
+        if existing {
+            if let Some(id) = ids.iter().find(|id| !index.contains(id)) {
+                return Err(eyre!(
+                    "Feature {} not found in source \"{}\"",
+                    id,
+                    self.name
+                ));
+            }
+        }
+
+        let points = match &mut *index {
+            GeoJSONIndex::Tiles(tiles) => return tiles.update(ids, &feature_collection(features)),
+            GeoJSONIndex::Clusters(clusters) => {
+                let ids = ids.iter().map(Value::to_string).collect::<HashSet<_>>();
+                let mut points = clusters
+                    .points()
+                    .iter()
+                    .filter(|point| match point.get("id") {
+                        Some(id) => !ids.contains(&id.to_string()),
+                        None => true,
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                points.extend(features);
+                points
+            }
+        };
+        // Any point can change clusters far away from it, so the clusters are
+        // built again.
+        *index = self.create_index(&feature_collection(points))?;
+        Ok(vec![Bounds::world()])
+    }
+
+    fn assign_ids(&self, data: &mut Value, generate: bool) {
+        match data.get("type").and_then(Value::as_str) {
+            Some("FeatureCollection") => {
+                if let Some(Value::Array(features)) = data.get_mut("features") {
+                    for feature in features {
+                        self.assign_id(feature, generate);
+                    }
+                }
+            }
+            Some("Feature") => self.assign_id(data, generate),
+            _ => {}
+        }
+    }
+
+    // Sets the id to the promoted property or, with `generateId`, to the
+    // next free one for new features.
+    fn assign_id(&self, feature: &mut Value, generate: bool) {
+        let id = if self.options.generate_id {
+            if !generate {
+                return;
+            }
+            Some(Value::from(self.next_id.fetch_add(1, Ordering::Relaxed)))
+        } else {
+            self.promote_id
+                .as_ref()
+                .and_then(|promote_id| promote_id.property(LAYER_NAME))
+                .and_then(|property| feature.get("properties")?.get(property))
+                .cloned()
+        };
+        if let (Some(id), Some(feature)) = (id, feature.as_object_mut()) {
+            feature.insert("id".to_owned(), id);
+        }
+    }
+
+    fn index(&self) -> Result<&Mutex<GeoJSONIndex>> {
+        self.index
+            .as_deref()
+            .ok_or_else(|| eyre!("Source \"{}\" is not loaded", self.name))
+    }
+
+    async fn resolve_data(&self, data: Option<&Value>) -> Result<Value> {
+        Ok(match data {
+            Some(Value::String(url)) => {
+                let data = self.nm.load_geojson(url, &self.name).await?;
+                serde_json::from_str::<Value>(&data)?
+            }
+            Some(data) => data.clone(),
+            None => feature_collection(Vec::new()),
+        })
+    }
+
+    pub fn get_cluster_expansion_zoom(&self, cluster_id: u64) -> Result<u32> {
+        self.with_clusters(|clusters| clusters.get_cluster_expansion_zoom(cluster_id as usize))
+    }
+
+    pub fn get_cluster_children(&self, cluster_id: u64) -> Result<Vec<Value>> {
+        self.with_clusters(|clusters| clusters.get_children(cluster_id as usize))
+    }
+
+    pub fn get_cluster_leaves(
+        &self,
+        cluster_id: u64,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<Value>> {
+        self.with_clusters(|clusters| clusters.get_leaves(cluster_id as usize, limit, offset))
+    }
+
+    fn with_clusters<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Supercluster) -> Result<T>,
+    {
+        let index = self.index()?.lock().unwrap();
+        match &*index {
+            GeoJSONIndex::Clusters(clusters) => f(clusters),
+            GeoJSONIndex::Tiles(_) => Err(eyre!("Source \"{}\" is not clustered", self.name)),
         }
     }
+
+    fn create_index(&self, data: &Value) -> Result<GeoJSONIndex> {
+        // `buffer`, `tolerance` and `clusterRadius` are given in pixels of a
+        // 512 tile.
+        let scale = f64::from(EXTENT / TILE_SIZE);
+        let max_zoom = self.options.maxzoom as u32;
+
+        if !self.options.cluster {
+            return Ok(GeoJSONIndex::Tiles(GeoJSONVT::new(
+                data,
+                geojson_vt::Options {
+                    max_zoom,
+                    tolerance: f64::from(self.options.tolerance) * scale,
+                    extent: EXTENT,
+                    buffer: f64::from(self.options.buffer) * scale,
+                    line_metrics: self.options.line_metrics,
+                    ..Default::default()
+                },
+            )?));
+        }
+
+        let cluster_max_zoom = max_zoom.saturating_sub(1);
+        let cluster_properties = match &self.options.cluster_properties {
+            Some(cluster_properties) => ClusterProperties::parse(cluster_properties)?,
+            None => ClusterProperties::default(),
+        };
+        Ok(GeoJSONIndex::Clusters(Supercluster::new(
+            data,
+            supercluster::Options {
+                max_zoom: match self.options.cluster_max_zoom {
+                    Some(zoom) => (zoom as u32).min(cluster_max_zoom),
+                    None => cluster_max_zoom,
+                },
+                min_points: self.options.cluster_min_points,
+                radius: f64::from(self.options.cluster_radius) * scale,
+                extent: EXTENT,
+                // Generated ids are assigned before indexing.
+                generate_id: false,
+                cluster_properties,
+                ..Default::default()
+            },
+        )?))
+    }
 }
 
 #[async_trait]
 impl SourceControl for GeoJSON {
     async fn load(&mut self) -> Result<()> {
-        todo!()
+        self.promote_id = match &self.options.promote_id {
+            Some(promote_id) => Some(PromoteId::parse(promote_id)?),
+            None => None,
+        };
+        let mut data = self.resolve_data(self.options.data.as_ref()).await?;
+        self.assign_ids(&mut data, true);
+        self.index = Some(Arc::new(Mutex::new(self.create_index(&data)?)));
+        Ok(())
     }
 
     fn has_tile(&self, _tile_id: &OverscaledTileId) -> bool {
-        todo!()
+        true
     }
 
-    async fn load_tile(&self, _tile: &mut Tile) -> Result<()> {
-        todo!()
+    async fn load_tile(&self, tile: &mut Tile) -> Result<()> {
+        let index = match &self.index {
+            Some(index) => index.clone(),
+            None => return Ok(()),
+        };
+        let tile_id = tile.tile_id().canonical().clone();
+        let (vector_tile, size) = self.workers.geojson_tile(index, tile_id).await?;
+        tile.set_vector_data(vector_tile, size);
+        Ok(())
     }
 
     fn tile_size(&self) -> u32 {
-        todo!()
+        TILE_SIZE
     }
 
     fn min_zoom(&self) -> f32 {
-        todo!()
+        0.0
     }
 
     fn max_zoom(&self) -> f32 {
-        todo!()
+        self.options.maxzoom
     }
 
     fn round_zoom(&self) -> bool {
-        todo!()
+        false
     }
 
+    // Tiles are sliced from the index at their canonical zoom, slicing
+    // overscaled ones again wouldn't add any precision.
     fn reparse_overscaled(&self) -> bool {
-        todo!()
+        false
     }
 
     fn render_world_copies(&self) -> bool {
-        todo!()
+        true
+    }
+}
+
+fn feature_collection(features: Vec<Value>) -> Value {
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+fn feature_ids(features: &[Value]) -> Result<Vec<Value>> {
+    features
+        .iter()
+        .map(|feature| match feature.get("id") {
+            Some(id) if !id.is_null() => Ok(id.clone()),
+            _ => Err(eyre!("Updated features need an id")),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn geojson(options: Value) -> GeoJSON {
+        let nm = Arc::new(NetworkManager::new("token", None, 6, None, None).unwrap());
+        let workers = Arc::new(WorkerPool::new(1).unwrap());
+        let options = serde_json::from_value::<style_spec::GeoJSON>(options).unwrap();
+        let mut geojson = GeoJSON::new(nm, workers, "geojson", &options);
+        futures::executor::block_on(geojson.load()).unwrap();
+        geojson
+    }
+
+    fn point(code: &str) -> Value {
+        json!({
+            "type": "Feature",
+            "properties": { "code": code },
+            "geometry": { "type": "Point", "coordinates": [0.0, 0.0] }
+        })
+    }
+
+    #[test]
+    fn geojson_promote_id() {
+        let source = geojson(json!({
+            "type": "geojson",
+            "promoteId": "code",
+            "data": feature_collection(vec![point("a")])
+        }));
+        assert!(source
+            .index()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .contains(&json!("a")));
+
+        // Updates find the feature by its promoted id.
+        source.update_features(vec![point("a")]).unwrap();
+        assert!(source.update_features(vec![point("b")]).is_err());
+        source.add_features(vec![point("b")]).unwrap();
+        assert!(source
+            .index()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .contains(&json!("b")));
+    }
+
+    #[test]
+    fn geojson_generate_id() {
+        let source = geojson(json!({
+            "type": "geojson",
+            "generateId": true,
+            "data": feature_collection(vec![point("a"), point("b")])
+        }));
+        source.add_features(vec![point("c")]).unwrap();
+        let index = source.index().unwrap().lock().unwrap();
+        for id in 0..3 {
+            assert!(index.contains(&json!(id)));
+        }
+    }
+
+    #[test]
+    fn geojson_tile_worker_panic() {
+        let source = geojson(json!({
+            "type": "geojson",
+            "data": feature_collection(vec![point("a")])
+        }));
+        let mut tile = Tile::new(OverscaledTileId::new(0, 0, 0, 0, 0), 512);
+        futures::executor::block_on(source.load_tile(&mut tile)).unwrap();
+        assert!(tile.has_data());
+
+        // Generating a tile from a poisoned index panics on the worker.
+        let index = source.index.clone().unwrap();
+        std::thread::spawn(move || {
+            let _index = index.lock().unwrap();
+            panic!("poisoned");
+        })
+        .join()
+        .ok();
+        let mut tile = Tile::new(OverscaledTileId::new(0, 0, 0, 0, 0), 512);
+        assert!(futures::executor::block_on(source.load_tile(&mut tile)).is_err());
+        // The worker is still there for the next request.
+        assert!(
+            futures::executor::block_on(source.workers.parse_vector_tile(Vec::new(), None)).is_ok()
+        );
     }
 }
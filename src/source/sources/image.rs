@@ -1,15 +1,119 @@
 use super::SourceControl;
-use crate::{network::NetworkManager, source::OverscaledTileId};
-use crate::{source::tile::Tile, style_spec};
+use crate::geo::{mercator_x_from_lng, mercator_y_from_lat};
+use crate::network::NetworkManager;
+use crate::source::geojson_vt::Bounds;
+use crate::source::{tile::Tile, CanonicalTileId, OverscaledTileId};
+use crate::style_spec;
 use async_trait::async_trait;
-use eyre::Result;
-use std::sync::Arc;
+use eyre::{eyre, Result};
+use image::RgbaImage;
+use std::sync::{Arc, Mutex};
+
+const EXTENT: f64 = 8192.0;
+const MAX_LATITUDE: f64 = 85.051129;
+
+// Texture coordinates of the top left, top right, bottom right and bottom
+// left corners, in the same order as the source coordinates.
+const TEX_COORDS: [[f32; 2]; 4] = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+
+// The image stretched over the corners, which are given in units of the
+// tile it is drawn into and can lie outside of it.
+#[derive(Debug, Clone)]
+pub(crate) struct ImageQuad {
+    pub image: Arc<RgbaImage>,
+    pub vertices: [[f32; 2]; 4],
+    pub tex_coords: [[f32; 2]; 4],
+}
+
+// Corners of an image or video, clockwise from the top left one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Corners {
+    coordinates: [[f64; 2]; 4],
+    // Projected to 0..1 world units.
+    points: [[f64; 2]; 4],
+}
+
+impl Corners {
+    pub fn new(coordinates: &[[f64; 2]; 4]) -> Result<Self> {
+        let mut points = [[0.0; 2]; 4];
+        for (point, &[lng, lat]) in points.iter_mut().zip(coordinates) {
+            if !lng.is_finite() || !lat.is_finite() || lat.abs() > MAX_LATITUDE {
+                return Err(eyre!("Invalid image coordinate [{}, {}]", lng, lat));
+            }
+            *point = [mercator_x_from_lng(lng), mercator_y_from_lat(lat)];
+        }
+        Ok(Self {
+            coordinates: *coordinates,
+            points,
+        })
+    }
+
+    // Coordinates as given in the style.
+    pub fn parse(coordinates: &[Vec<f32>]) -> Result<Self> {
+        if coordinates.len() != 4 {
+            return Err(eyre!("Image sources need exactly four coordinates"));
+        }
+        let mut corners = [[0.0; 2]; 4];
+        for (corner, coordinate) in corners.iter_mut().zip(coordinates) {
+            *corner = match coordinate.as_slice() {
+                [lng, lat] => [f64::from(*lng), f64::from(*lat)],
+                _ => return Err(eyre!("Image coordinates must be [longitude, latitude]")),
+            };
+        }
+        Self::new(&corners)
+    }
+
+    // As [longitude, latitude].
+    pub fn coordinates(&self) -> &[[f64; 2]; 4] {
+        &self.coordinates
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        let xs = self.points.iter().map(|point| point[0]);
+        let ys = self.points.iter().map(|point| point[1]);
+        Bounds {
+            min_x: xs.clone().fold(f64::INFINITY, f64::min),
+            min_y: ys.clone().fold(f64::INFINITY, f64::min),
+            max_x: xs.fold(f64::NEG_INFINITY, f64::max),
+            max_y: ys.fold(f64::NEG_INFINITY, f64::max),
+        }
+    }
+
+    pub fn intersects(&self, tile_id: &CanonicalTileId) -> bool {
+        self.bounds()
+            .intersects_tile(tile_id.z, tile_id.x, tile_id.y, 0.0)
+    }
+
+    pub fn quad(&self, image: Arc<RgbaImage>, tile_id: &CanonicalTileId) -> ImageQuad {
+        let z2 = f64::from(1u32 << tile_id.z);
+        let (x, y) = (f64::from(tile_id.x), f64::from(tile_id.y));
+        let mut vertices = [[0.0; 2]; 4];
+        for (vertex, point) in vertices.iter_mut().zip(&self.points) {
+            *vertex = [
+                ((point[0] * z2 - x) * EXTENT) as f32,
+                ((point[1] * z2 - y) * EXTENT) as f32,
+            ];
+        }
+        ImageQuad {
+            image,
+            vertices,
+            tex_coords: TEX_COORDS,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ImageState {
+    image: Arc<RgbaImage>,
+    corners: Corners,
+}
 
 #[derive(Debug)]
 pub(crate) struct Image {
     nm: Arc<NetworkManager>,
     name: String,
     options: style_spec::Image,
+    state: Mutex<Option<ImageState>>,
 }
 
 impl Image {
@@ -18,45 +122,112 @@ impl Image {
             nm,
             name: name.to_owned(),
             options: options.clone(),
+            state: Mutex::new(None),
         }
     }
+
+    // Returns the bounds of both the old and the new image, the tiles in
+    // them need to be reloaded.
+    pub async fn update_image(
+        &self,
+        url: &str,
+        coordinates: &[[f64; 2]; 4],
+    ) -> Result<Vec<Bounds>> {
+        let corners = Corners::new(coordinates)?;
+        let image = self.nm.load_image(url, &self.name).await?;
+
+        let state = ImageState {
+            image: Arc::new(image),
+            corners,
+        };
+        let mut bounds = vec![corners.bounds()];
+        if let Some(old) = self.state.lock().unwrap().replace(state) {
+            bounds.push(old.corners.bounds());
+        }
+        Ok(bounds)
+    }
 }
 
 #[async_trait]
 impl SourceControl for Image {
     async fn load(&mut self) -> Result<()> {
-        todo!()
+        let corners = Corners::parse(&self.options.coordinates)?;
+        let url = self.options.url.clone();
+        self.update_image(&url, corners.coordinates()).await?;
+        Ok(())
     }
 
-    fn has_tile(&self, _tile_id: &OverscaledTileId) -> bool {
-        todo!()
+    fn has_tile(&self, tile_id: &OverscaledTileId) -> bool {
+        match &*self.state.lock().unwrap() {
+            Some(state) => state.corners.intersects(tile_id.canonical()),
+            None => false,
+        }
     }
 
-    async fn load_tile(&self, _tile: &mut Tile) -> Result<()> {
-        todo!()
+    async fn load_tile(&self, tile: &mut Tile) -> Result<()> {
+        let canonical = tile.tile_id().canonical();
+        let quad = match &*self.state.lock().unwrap() {
+            Some(state) if state.corners.intersects(canonical) => {
+                Some(state.corners.quad(state.image.clone(), canonical))
+            }
+            _ => None,
+        };
+        tile.set_image_data(quad);
+        Ok(())
     }
 
     fn tile_size(&self) -> u32 {
-        todo!()
+        512
     }
 
     fn min_zoom(&self) -> f32 {
-        todo!()
+        0.0
     }
 
     fn max_zoom(&self) -> f32 {
-        todo!()
+        22.0
     }
 
     fn round_zoom(&self) -> bool {
-        todo!()
+        false
     }
 
     fn reparse_overscaled(&self) -> bool {
-        todo!()
+        false
     }
 
     fn render_world_copies(&self) -> bool {
-        todo!()
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_quad_in_tile_units() {
+        let corners = Corners::new(&[
+            [-90.0, 0.0],
+            [0.0, 0.0],
+            [0.0, -66.51326],
+            [-90.0, -66.51326],
+        ])
+        .unwrap();
+        assert!(corners.intersects(&CanonicalTileId::new(1, 0, 1)));
+        assert!(!corners.intersects(&CanonicalTileId::new(2, 3, 2)));
+        assert!(!corners.intersects(&CanonicalTileId::new(2, 1, 0)));
+
+        let image = Arc::new(RgbaImage::new(2, 2));
+        let quad = corners.quad(image, &CanonicalTileId::new(1, 0, 1));
+        assert_eq!(quad.vertices[0], [4096.0, 0.0]);
+        assert_eq!(quad.vertices[1][0], 8192.0);
+        assert!((quad.vertices[2][1] - 4096.0).abs() < 0.1);
+        assert_eq!(quad.tex_coords, TEX_COORDS);
+
+        let invalid = |corner: [f64; 2]| Corners::new(&[corner, [0.0; 2], [0.0; 2], [0.0; 2]]);
+        assert!(invalid([0.0, 90.0]).is_err());
+        assert!(invalid([f64::NAN, 0.0]).is_err());
+        assert!(Corners::parse(&[vec![0.0, 0.0]]).is_err());
     }
 }
@@ -1,23 +1,32 @@
+mod custom;
 mod geo_json;
 mod image;
 mod raster;
 mod raster_dem;
+mod tile_set;
 mod vector;
 mod video;
 
+use crate::event::RedrawCallback;
 use crate::network::NetworkManager;
 use crate::source::OverscaledTileId;
 use crate::style_spec;
+use crate::worker::WorkerPool;
 use async_trait::async_trait;
+use custom::Custom;
 use enum_dispatch::enum_dispatch;
 use eyre::Result;
-use geo_json::GeoJSON;
-use image::Image;
 use raster::Raster;
 use raster_dem::RasterDEM;
 use std::sync::Arc;
 use vector::Vector;
-use video::Video;
+
+pub(crate) use self::image::{Image, ImageQuad};
+pub(crate) use custom::{encode_features, validate_custom_source};
+pub use custom::{CustomSource, CustomTile, TileCoordinates};
+pub(crate) use geo_json::{geojson_tile, GeoJSON, GeoJSONIndex};
+pub(crate) use tile_set::{TileSet, TileSetOptions};
+pub(crate) use video::Video;
 
 use super::tile::Tile;
 
@@ -27,6 +36,11 @@ pub(crate) trait SourceControl {
     async fn load(&mut self) -> Result<()>;
     fn has_tile(&self, tile_id: &OverscaledTileId) -> bool;
     async fn load_tile(&self, tile: &mut Tile) -> Result<()>;
+    // Processes the data an overscaled tile shares with its max zoom tile
+    // again for the overscaled zoom. It must not load anything.
+    async fn reparse_tile(&self, _tile: &mut Tile) -> Result<()> {
+        Ok(())
+    }
     fn tile_size(&self) -> u32;
     fn min_zoom(&self) -> f32;
     fn max_zoom(&self) -> f32;
@@ -44,19 +58,38 @@ pub(crate) enum Source {
     GeoJSON(GeoJSON),
     Video(Video),
     Image(Image),
+    Custom(Custom),
 }
 
 impl Source {
-    pub fn new(nm: Arc<NetworkManager>, name: &str, source: &style_spec::Source) -> Source {
+    pub fn new(
+        nm: Arc<NetworkManager>,
+        workers: Arc<WorkerPool>,
+        name: &str,
+        source: &style_spec::Source,
+        redraw_callback: Option<RedrawCallback>,
+    ) -> Source {
         match source {
-            style_spec::Source::Vector(data) => Source::Vector(Vector::new(nm, name, data)),
-            style_spec::Source::Raster(data) => Source::Raster(Raster::new(nm, name, data)),
+            style_spec::Source::Vector(data) => {
+                Source::Vector(Vector::new(nm, workers, name, data))
+            }
+            style_spec::Source::Raster(data) => {
+                Source::Raster(Raster::new(nm, workers, name, data))
+            }
             style_spec::Source::RasterDEM(data) => {
-                Source::RasterDEM(RasterDEM::new(nm, name, data))
+                Source::RasterDEM(RasterDEM::new(nm, workers, name, data))
+            }
+            style_spec::Source::GeoJSON(data) => {
+                Source::GeoJSON(GeoJSON::new(nm, workers, name, data))
+            }
+            style_spec::Source::Video(data) => {
+                Source::Video(Video::new(redraw_callback, name, data))
             }
-            style_spec::Source::GeoJSON(data) => Source::GeoJSON(GeoJSON::new(nm, name, data)),
-            style_spec::Source::Video(data) => Source::Video(Video::new(nm, name, data)),
             style_spec::Source::Image(data) => Source::Image(Image::new(nm, name, data)),
         }
     }
+
+    pub fn custom(workers: Arc<WorkerPool>, name: &str, source: Arc<dyn CustomSource>) -> Source {
+        Source::Custom(Custom::new(workers, name, source))
+    }
 }
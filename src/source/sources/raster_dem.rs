@@ -1,22 +1,31 @@
+use super::tile_set::{TileSetOptions, TileSetSource};
 use super::SourceControl;
-use crate::{network::NetworkManager, source::OverscaledTileId};
-use crate::{source::tile::Tile, style_spec};
+use crate::network::NetworkManager;
+use crate::source::tile::Tile;
+use crate::source::OverscaledTileId;
+use crate::style_spec;
+use crate::worker::WorkerPool;
 use async_trait::async_trait;
 use eyre::Result;
 use std::sync::Arc;
 
 #[derive(Debug)]
 pub(crate) struct RasterDEM {
-    nm: Arc<NetworkManager>,
-    name: String,
+    workers: Arc<WorkerPool>,
+    tiles: TileSetSource,
     options: style_spec::RasterDEM,
 }
 
 impl RasterDEM {
-    pub fn new(nm: Arc<NetworkManager>, name: &str, options: &style_spec::RasterDEM) -> Self {
+    pub fn new(
+        nm: Arc<NetworkManager>,
+        workers: Arc<WorkerPool>,
+        name: &str,
+        options: &style_spec::RasterDEM,
+    ) -> Self {
         Self {
-            nm,
-            name: name.to_owned(),
+            workers,
+            tiles: TileSetSource::new(nm, name),
             options: options.clone(),
         }
     }
@@ -25,38 +34,52 @@ impl RasterDEM {
 #[async_trait]
 impl SourceControl for RasterDEM {
     async fn load(&mut self) -> Result<()> {
-        todo!()
+        self.tiles
+            .load(&TileSetOptions::from(&self.options))
+            .await?;
+        Ok(())
     }
 
-    fn has_tile(&self, _tile_id: &OverscaledTileId) -> bool {
-        todo!()
+    fn has_tile(&self, tile_id: &OverscaledTileId) -> bool {
+        self.tiles.has_tile(tile_id)
     }
 
-    async fn load_tile(&self, _tile: &mut Tile) -> Result<()> {
-        todo!()
+    async fn load_tile(&self, tile: &mut Tile) -> Result<()> {
+        let tile_id = tile.tile_id().canonical();
+        match self.tiles.download(tile_id, Some(self.tile_size())).await? {
+            Some(res) => {
+                let encoding = self.options.encoding.clone();
+                let dem = self.workers.decode_dem_tile(res.data, encoding).await?;
+                tile.set_dem_data(Some(dem));
+                tile.set_expires(res.expires);
+            }
+            None => tile.set_dem_data(None),
+        }
+
+        Ok(())
     }
 
     fn tile_size(&self) -> u32 {
-        todo!()
+        self.options.tile_size as u32
     }
 
     fn min_zoom(&self) -> f32 {
-        todo!()
+        self.tiles.min_zoom()
     }
 
     fn max_zoom(&self) -> f32 {
-        todo!()
+        self.tiles.max_zoom()
     }
 
     fn round_zoom(&self) -> bool {
-        todo!()
+        true
     }
 
     fn reparse_overscaled(&self) -> bool {
-        todo!()
+        false
     }
 
     fn render_world_copies(&self) -> bool {
-        todo!()
+        true
     }
 }
@@ -0,0 +1,309 @@
+use crate::network::{NetworkManager, Response};
+use crate::source::tile_bounds::TileBounds;
+use crate::source::{CanonicalTileId, OverscaledTileId};
+use crate::style_spec::{self, Scheme};
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use std::sync::Arc;
+use tilejson::TileJson;
+
+const DEFAULT_MIN_ZOOM: f32 = 0.0;
+const DEFAULT_MAX_ZOOM: f32 = 22.0;
+
+// Tiled source properties given in the style. When there is a TileJSON, the
+// ones that are set take precedence over its values.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TileSetOptions<'a> {
+    pub url: Option<&'a str>,
+    pub tiles: Option<&'a [String]>,
+    pub min_zoom: Option<f32>,
+    pub max_zoom: Option<f32>,
+    pub bounds: Option<&'a [f64]>,
+    pub scheme: Option<Scheme>,
+}
+
+impl<'a> From<&'a style_spec::Vector> for TileSetOptions<'a> {
+    fn from(options: &'a style_spec::Vector) -> Self {
+        Self {
+            url: options.url.as_deref(),
+            tiles: options.tiles.as_deref(),
+            min_zoom: options.minzoom,
+            max_zoom: options.maxzoom,
+            bounds: options.bounds.as_deref(),
+            scheme: options.scheme.clone(),
+        }
+    }
+}
+
+impl<'a> From<&'a style_spec::Raster> for TileSetOptions<'a> {
+    fn from(options: &'a style_spec::Raster) -> Self {
+        Self {
+            url: options.url.as_deref(),
+            tiles: options.tiles.as_deref(),
+            min_zoom: options.minzoom,
+            max_zoom: options.maxzoom,
+            bounds: options.bounds.as_deref(),
+            scheme: options.scheme.clone(),
+        }
+    }
+}
+
+impl<'a> From<&'a style_spec::RasterDEM> for TileSetOptions<'a> {
+    fn from(options: &'a style_spec::RasterDEM) -> Self {
+        Self {
+            url: options.url.as_deref(),
+            tiles: options.tiles.as_deref(),
+            min_zoom: options.minzoom,
+            max_zoom: options.maxzoom,
+            bounds: options.bounds.as_deref(),
+            scheme: None,
+        }
+    }
+}
+
+// The scheme is read separately, the style spec enum is used for it.
+#[derive(Deserialize)]
+struct TileJsonScheme {
+    scheme: Option<Scheme>,
+}
+
+// Tile URLs, zoom range and bounds of a tiled source, either from its
+// TileJSON or given inline in the style.
+#[derive(Debug)]
+pub(crate) struct TileSet {
+    pub tiles: Vec<String>,
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    pub scheme: Scheme,
+    tile_bounds: Option<TileBounds>,
+}
+
+impl TileSet {
+    pub async fn load(
+        nm: &NetworkManager,
+        name: &str,
+        options: &TileSetOptions<'_>,
+    ) -> Result<Self> {
+        match (options.url, options.tiles) {
+            (Some(url), _) => {
+                let tilejson = nm.load_tilejson(url, name).await?;
+                Self::from_tilejson(tilejson.as_bytes(), options)
+            }
+            (None, Some(tiles)) => Ok(Self::from_tiles(tiles, options)),
+            (None, None) => Err(eyre!(
+                "Source \"{}\" needs either \"url\" or \"tiles\"",
+                name
+            )),
+        }
+    }
+
+    pub fn from_tiles(tiles: &[String], options: &TileSetOptions<'_>) -> Self {
+        Self::new(
+            tiles.to_vec(),
+            options.min_zoom.unwrap_or(DEFAULT_MIN_ZOOM),
+            options.max_zoom.unwrap_or(DEFAULT_MAX_ZOOM),
+            options.bounds,
+            options.scheme.clone().unwrap_or(Scheme::XYZ),
+        )
+    }
+
+    pub fn from_tilejson(data: &[u8], options: &TileSetOptions<'_>) -> Result<Self> {
+        let tilejson = serde_json::from_slice::<TileJson>(data)?;
+        let scheme = match &options.scheme {
+            Some(scheme) => scheme.clone(),
+            None => serde_json::from_slice::<TileJsonScheme>(data)?
+                .scheme
+                .unwrap_or(Scheme::XYZ),
+        };
+        Ok(Self::new(
+            options.tiles.map_or(tilejson.tiles, |tiles| tiles.to_vec()),
+            options.min_zoom.unwrap_or(f32::from(tilejson.minzoom)),
+            options.max_zoom.unwrap_or(f32::from(tilejson.maxzoom)),
+            Some(options.bounds.unwrap_or(&tilejson.bounds)),
+            scheme,
+        ))
+    }
+
+    fn new(
+        tiles: Vec<String>,
+        min_zoom: f32,
+        max_zoom: f32,
+        bounds: Option<&[f64]>,
+        scheme: Scheme,
+    ) -> Self {
+        let tile_bounds = match bounds {
+            Some([west, south, east, north, ..]) => {
+                Some(TileBounds::new(&[*west, *south, *east, *north]))
+            }
+            _ => None,
+        };
+        Self {
+            tiles,
+            min_zoom,
+            max_zoom,
+            scheme,
+            tile_bounds,
+        }
+    }
+
+    // Tiles outside of the bounds have no data and are never requested.
+    pub fn contains(&self, tile_id: &CanonicalTileId) -> bool {
+        match &self.tile_bounds {
+            Some(tile_bounds) => tile_bounds.contains(tile_id),
+            None => true,
+        }
+    }
+}
+
+// What the sources tiled by a TileSet have in common: loading the TileJSON,
+// the zoom range and bounds, and downloading the tiles, which the sources
+// decode themselves.
+#[derive(Debug)]
+pub(crate) struct TileSetSource {
+    nm: Arc<NetworkManager>,
+    name: String,
+    tile_set: Option<TileSet>,
+}
+
+impl TileSetSource {
+    pub fn new(nm: Arc<NetworkManager>, name: &str) -> Self {
+        Self {
+            nm,
+            name: name.to_owned(),
+            tile_set: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub async fn load(&mut self, options: &TileSetOptions<'_>) -> Result<()> {
+        self.tile_set = Some(TileSet::load(&self.nm, &self.name, options).await?);
+        Ok(())
+    }
+
+    pub fn has_tile(&self, tile_id: &OverscaledTileId) -> bool {
+        match &self.tile_set {
+            Some(tile_set) => tile_set.contains(tile_id.canonical()),
+            None => false,
+        }
+    }
+
+    pub fn min_zoom(&self) -> f32 {
+        self.tile_set
+            .as_ref()
+            .map_or(DEFAULT_MIN_ZOOM, |tile_set| tile_set.min_zoom)
+    }
+
+    pub fn max_zoom(&self) -> f32 {
+        self.tile_set
+            .as_ref()
+            .map_or(DEFAULT_MAX_ZOOM, |tile_set| tile_set.max_zoom)
+    }
+
+    // Raster tiles are requested at their size, vector tiles with `None`.
+    // Returns `None` for tiles without data.
+    pub async fn download(
+        &self,
+        tile_id: &CanonicalTileId,
+        raster_size: Option<u32>,
+    ) -> Result<Option<Response>> {
+        let tile_set = match &self.tile_set {
+            Some(tile_set) => tile_set,
+            None => return Ok(None),
+        };
+        let url = tile_id.url(&tile_set.tiles, Some(tile_set.scheme.clone()));
+        match raster_size {
+            Some(tile_size) => self.nm.load_raster_tile(&url, tile_size, &self.name).await,
+            None => self.nm.load_vector_tile(&url, &self.name).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TILEJSON: &str = r#"{
+        "tilejson": "2.2.0",
+        "tiles": ["https://example.com/{z}/{x}/{y}.pbf"],
+        "minzoom": 2,
+        "maxzoom": 14,
+        "bounds": [-10.0, -10.0, 10.0, 10.0],
+        "scheme": "tms",
+        "center": [0.0, 0.0, 2.0]
+    }"#;
+
+    #[test]
+    fn tile_set_style_overrides_tilejson() {
+        let tile_set =
+            TileSet::from_tilejson(TILEJSON.as_bytes(), &TileSetOptions::default()).unwrap();
+        assert_eq!(tile_set.tiles, vec!["https://example.com/{z}/{x}/{y}.pbf"]);
+        assert_eq!((tile_set.min_zoom, tile_set.max_zoom), (2.0, 14.0));
+        assert_eq!(tile_set.scheme, Scheme::TMS);
+
+        let options = TileSetOptions {
+            max_zoom: Some(10.0),
+            scheme: Some(Scheme::XYZ),
+            ..TileSetOptions::default()
+        };
+        let tile_set = TileSet::from_tilejson(TILEJSON.as_bytes(), &options).unwrap();
+        assert_eq!((tile_set.min_zoom, tile_set.max_zoom), (2.0, 10.0));
+        assert_eq!(tile_set.scheme, Scheme::XYZ);
+        assert!(tile_set.contains(&CanonicalTileId::new(4, 8, 7)));
+        assert!(!tile_set.contains(&CanonicalTileId::new(4, 4, 7)));
+
+        let bounds = [170.0, -10.0, -170.0, 10.0];
+        let options = TileSetOptions {
+            bounds: Some(&bounds),
+            ..TileSetOptions::default()
+        };
+        let tile_set = TileSet::from_tilejson(TILEJSON.as_bytes(), &options).unwrap();
+        assert!(tile_set.contains(&CanonicalTileId::new(4, 0, 7)));
+        assert!(!tile_set.contains(&CanonicalTileId::new(4, 8, 7)));
+    }
+
+    #[test]
+    fn tile_set_from_inline_tiles() {
+        let tiles = vec!["https://example.com/{z}/{x}/{y}.pbf".to_owned()];
+        let options = TileSetOptions {
+            tiles: Some(&tiles),
+            min_zoom: Some(4.0),
+            ..TileSetOptions::default()
+        };
+        let nm = NetworkManager::new("token", None, 6, None, None).unwrap();
+        let tile_set = futures::executor::block_on(TileSet::load(&nm, "inline", &options)).unwrap();
+        assert_eq!(tile_set.tiles, tiles);
+        assert_eq!((tile_set.min_zoom, tile_set.max_zoom), (4.0, 22.0));
+        assert_eq!(tile_set.scheme, Scheme::XYZ);
+        assert!(tile_set.contains(&CanonicalTileId::new(5, 31, 31)));
+
+        let missing = TileSetOptions::default();
+        assert!(futures::executor::block_on(TileSet::load(&nm, "missing", &missing)).is_err());
+    }
+
+    #[test]
+    fn tile_set_source_zoom_range_and_bounds() {
+        let nm = Arc::new(NetworkManager::new("token", None, 6, None, None).unwrap());
+        let mut source = TileSetSource::new(nm, "inline");
+        let tile_id = OverscaledTileId::new(5, 0, 5, 31, 31);
+        assert!(!source.has_tile(&tile_id));
+        assert_eq!((source.min_zoom(), source.max_zoom()), (0.0, 22.0));
+        let download = source.download(tile_id.canonical(), None);
+        assert!(futures::executor::block_on(download).unwrap().is_none());
+
+        let tiles = vec!["https://example.com/{z}/{x}/{y}.png".to_owned()];
+        let bounds = [-10.0, -10.0, 10.0, 10.0];
+        let options = TileSetOptions {
+            tiles: Some(&tiles),
+            max_zoom: Some(12.0),
+            bounds: Some(&bounds),
+            ..TileSetOptions::default()
+        };
+        futures::executor::block_on(source.load(&options)).unwrap();
+        assert_eq!((source.min_zoom(), source.max_zoom()), (0.0, 12.0));
+        assert!(!source.has_tile(&tile_id));
+        assert!(source.has_tile(&OverscaledTileId::new(4, 0, 4, 8, 7)));
+    }
+}
@@ -1,31 +1,33 @@
+use super::tile_set::{TileSetOptions, TileSetSource};
 use super::SourceControl;
 use crate::network::NetworkManager;
 use crate::source::tile::Tile;
-use crate::source::tile_bounds::TileBounds;
-use crate::source::OverscaledTileId;
+use crate::source::{OverscaledTileId, PromoteId};
 use crate::style_spec;
+use crate::worker::WorkerPool;
 use async_trait::async_trait;
 use eyre::Result;
-use std::convert::TryFrom;
 use std::sync::Arc;
-use tilejson::TileJson;
 
 #[derive(Debug)]
 pub(crate) struct Vector {
-    nm: Arc<NetworkManager>,
-    name: String,
-    tilejson: Option<TileJson>,
-    tile_bounds: Option<TileBounds>,
+    workers: Arc<WorkerPool>,
+    tiles: TileSetSource,
+    promote_id: Option<PromoteId>,
     options: style_spec::Vector,
 }
 
 impl Vector {
-    pub fn new(nm: Arc<NetworkManager>, name: &str, options: &style_spec::Vector) -> Self {
+    pub fn new(
+        nm: Arc<NetworkManager>,
+        workers: Arc<WorkerPool>,
+        name: &str,
+        options: &style_spec::Vector,
+    ) -> Self {
         Self {
-            nm,
-            name: name.to_owned(),
-            tilejson: None,
-            tile_bounds: None,
+            workers,
+            tiles: TileSetSource::new(nm, name),
+            promote_id: None,
             options: options.clone(),
         }
     }
@@ -34,42 +36,33 @@ impl Vector {
 #[async_trait]
 impl SourceControl for Vector {
     async fn load(&mut self) -> Result<()> {
-        self.tilejson = match &self.options.url {
-            Some(url) => {
-                let tilejson = self.nm.load_tilejson(&url).await?;
-                let tilejson = serde_json::from_str::<TileJson>(&tilejson)?;
-                self.tile_bounds = Some(TileBounds::new(
-                    <&[f64; 4]>::try_from(&tilejson.bounds[0..4]).unwrap(),
-                ));
-                Some(tilejson)
-            }
+        self.tiles
+            .load(&TileSetOptions::from(&self.options))
+            .await?;
+        self.promote_id = match &self.options.promote_id {
+            Some(promote_id) => Some(PromoteId::parse(promote_id)?),
             None => None,
         };
-        println!("Source \"{}\" loaded", self.name);
         Ok(())
     }
 
     fn has_tile(&self, tile_id: &OverscaledTileId) -> bool {
-        let tile_bounds = match &self.tile_bounds {
-            Some(tile_bounds) => tile_bounds,
-            None => return false,
-        };
-
-        tile_bounds.contains(tile_id.canonical())
+        self.tiles.has_tile(tile_id)
     }
 
     async fn load_tile(&self, tile: &mut Tile) -> Result<()> {
-        let tilejson = match &self.tilejson {
-            Some(t) => t,
-            None => return Ok(()),
-        };
-        let url = tile
-            .tile_id()
-            .canonical()
-            .url(&tilejson.tiles, Some(self.options.scheme.clone()));
-
-        let vector_tile = self.nm.load_vector_tile(&url).await?;
-        tile.set_vector_data(vector_tile);
+        let tile_id = tile.tile_id().canonical();
+        match self.tiles.download(tile_id, None).await? {
+            Some(res) => {
+                let (vector_tile, size) = self
+                    .workers
+                    .parse_vector_tile(res.data, self.promote_id.clone())
+                    .await?;
+                tile.set_vector_data(vector_tile, size);
+                tile.set_expires(res.expires);
+            }
+            None => tile.set_vector_data(Default::default(), 0),
+        }
 
         Ok(())
     }
@@ -79,17 +72,11 @@ impl SourceControl for Vector {
     }
 
     fn min_zoom(&self) -> f32 {
-        match &self.tilejson {
-            Some(t) => t.minzoom as f32,
-            None => 22.0,
-        }
+        self.tiles.min_zoom()
     }
 
     fn max_zoom(&self) -> f32 {
-        match &self.tilejson {
-            Some(t) => t.maxzoom as f32,
-            None => 22.0,
-        }
+        self.tiles.max_zoom()
     }
 
     fn round_zoom(&self) -> bool {
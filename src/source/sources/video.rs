@@ -1,62 +1,627 @@
+use super::image::Corners;
 use super::SourceControl;
-use crate::{network::NetworkManager, source::OverscaledTileId};
-use crate::{source::tile::Tile, style_spec};
+use crate::event::RedrawCallback;
+use crate::source::{tile::Tile, OverscaledTileId};
+use crate::style_spec;
 use async_trait::async_trait;
-use eyre::Result;
-use std::sync::Arc;
+use eyre::{eyre, Result};
+use image::RgbaImage;
+#[cfg(feature = "ffmpeg")]
+use std::collections::HashMap;
+use std::fs;
+#[cfg(feature = "ffmpeg")]
+use std::io::Read;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "ffmpeg")]
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::spawn_blocking;
+use tokio::time::delay_for;
 
+// Frame rate of image sequences, encoded videos have their own.
+const FRAME_RATE: f64 = 30.0;
+const FRAME_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "webp"];
+// Frames read and thrown away before restarting the decoder at the wanted
+// position is cheaper.
+#[cfg(feature = "ffmpeg")]
+const MAX_SKIPPED_FRAMES: usize = 30;
+
+// Playback position, advancing with the clock while playing.
+#[derive(Debug, Default)]
+struct Playback {
+    started: Option<Instant>,
+    offset: Duration,
+}
+
+impl Playback {
+    fn position(&self, now: Instant) -> Duration {
+        match self.started {
+            Some(started) => self.offset + now.saturating_duration_since(started),
+            None => self.offset,
+        }
+    }
+
+    fn play(&mut self, now: Instant) {
+        if self.started.is_none() {
+            self.started = Some(now);
+        }
+    }
+
+    fn pause(&mut self, now: Instant) {
+        self.offset = self.position(now);
+        self.started = None;
+    }
+
+    fn seek(&mut self, now: Instant, position: Duration) {
+        self.offset = position;
+        if self.started.is_some() {
+            self.started = Some(now);
+        }
+    }
+}
+
+// Frames of a video. Opening and decoding them blocks, on an encoded video
+// for as long as ffmpeg takes, so it runs on the blocking threads instead of
+// the tile workers.
+#[derive(Debug)]
+enum VideoFrames {
+    // Image files ordered by name, one per frame.
+    Sequence(Vec<PathBuf>),
+    #[cfg(feature = "ffmpeg")]
+    Encoded(EncodedVideo),
+}
+
+impl VideoFrames {
+    // A directory is an image sequence ordered by file name, a single image is
+    // a video of one frame. Anything else is decoded with ffmpeg when the
+    // feature is enabled.
+    fn open(url: &str) -> Result<Self> {
+        let path = Path::new(url.strip_prefix("file://").unwrap_or(url));
+        let frames = if path.is_dir() {
+            let mut paths = fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            paths.retain(|path| is_frame(path));
+            paths.sort();
+            VideoFrames::Sequence(paths)
+        } else if is_frame(path) {
+            VideoFrames::Sequence(vec![path.to_owned()])
+        } else {
+            Self::open_encoded(path)?
+        };
+
+        if frames.frame_count() == 0 {
+            return Err(eyre!("no frames found"));
+        }
+        Ok(frames)
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    fn open_encoded(path: &Path) -> Result<Self> {
+        Ok(VideoFrames::Encoded(EncodedVideo::open(path)?))
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    fn open_encoded(_path: &Path) -> Result<Self> {
+        Err(eyre!(
+            "not an image sequence, encoded videos need the ffmpeg feature"
+        ))
+    }
+
+    fn frame_count(&self) -> usize {
+        match self {
+            VideoFrames::Sequence(paths) => paths.len(),
+            #[cfg(feature = "ffmpeg")]
+            VideoFrames::Encoded(video) => video.frame_count,
+        }
+    }
+
+    fn frame_rate(&self) -> f64 {
+        match self {
+            VideoFrames::Sequence(_) => FRAME_RATE,
+            #[cfg(feature = "ffmpeg")]
+            VideoFrames::Encoded(video) => video.frame_rate,
+        }
+    }
+
+    fn decode(&mut self, index: usize) -> Result<RgbaImage> {
+        match self {
+            VideoFrames::Sequence(paths) => {
+                let path = paths
+                    .get(index)
+                    .ok_or_else(|| eyre!("Frame {} is out of range", index))?;
+                Ok(image::open(path)?.to_rgba8())
+            }
+            #[cfg(feature = "ffmpeg")]
+            VideoFrames::Encoded(video) => video.decode(index),
+        }
+    }
+}
+
+// A video file decoded by an ffmpeg process, which keeps running while the
+// frames are played in order.
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug)]
+struct EncodedVideo {
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    frame_rate: f64,
+    frame_count: usize,
+    // The process and the index of the next frame it outputs.
+    decoder: Option<(Child, usize)>,
+}
+
+#[cfg(feature = "ffmpeg")]
+impl EncodedVideo {
+    // Reads the stream headers only, containers that don't store the frame
+    // count get it estimated from the duration.
+    fn open(path: &Path) -> Result<Self> {
+        let output = Command::new("ffprobe")
+            .args(["-v", "error", "-select_streams", "v:0"])
+            .args([
+                "-show_entries",
+                "stream=width,height,avg_frame_rate,nb_frames:format=duration",
+            ])
+            .args(["-of", "default=noprint_wrappers=1"])
+            .arg(path)
+            .output()
+            .map_err(|e| eyre!("ffprobe is needed to play encoded videos: {}", e))?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "not a video: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        let (width, height, frame_rate, frame_count) =
+            parse_probe(&String::from_utf8_lossy(&output.stdout))?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            width,
+            height,
+            frame_rate,
+            frame_count,
+            decoder: None,
+        })
+    }
+
+    fn decode(&mut self, index: usize) -> Result<RgbaImage> {
+        let reuse = match &self.decoder {
+            Some((_, next)) => (*next..=*next + MAX_SKIPPED_FRAMES).contains(&index),
+            None => false,
+        };
+        if !reuse {
+            self.start(index)?;
+        }
+
+        let mut frame = vec![0; self.width as usize * self.height as usize * 4];
+        let result = match &mut self.decoder {
+            Some((child, next)) => match &mut child.stdout {
+                Some(stdout) => (*next..=index).try_for_each(|_| {
+                    stdout.read_exact(&mut frame)?;
+                    *next += 1;
+                    Ok(())
+                }),
+                None => Err(eyre!("ffmpeg has no output")),
+            },
+            None => Err(eyre!("ffmpeg is not running")),
+        };
+        if let Err(e) = result {
+            self.stop();
+            return Err(e);
+        }
+        RgbaImage::from_raw(self.width, self.height, frame)
+            .ok_or_else(|| eyre!("Invalid frame size"))
+    }
+
+    fn start(&mut self, index: usize) -> Result<()> {
+        self.stop();
+        let position = index as f64 / self.frame_rate;
+        let child = Command::new("ffmpeg")
+            .args(["-v", "error", "-ss", &format!("{:.3}", position), "-i"])
+            .arg(&self.path)
+            .args(["-an", "-f", "rawvideo", "-pix_fmt", "rgba", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| eyre!("ffmpeg is needed to play encoded videos: {}", e))?;
+        self.decoder = Some((child, index));
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some((mut child, _)) = self.decoder.take() {
+            child.kill().ok();
+            child.wait().ok();
+        }
+    }
+}
+
+#[cfg(feature = "ffmpeg")]
+impl Drop for EncodedVideo {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// The last decoded frame and the one being decoded, shared with the task
+// decoding it. A failure is kept until it is reported.
+#[derive(Debug, Default)]
+struct FrameState {
+    decoded: Option<(usize, Arc<RgbaImage>)>,
+    decoding: Option<usize>,
+    error: Option<String>,
+}
+
+// Plays a video or a sequence of frames from local files, looping at the end.
+// Frames are decoded on the blocking threads when they are due, until then the last
+// one is shown. While playing, a redraw is asked for whenever a frame is
+// decoded or the next one is due.
 #[derive(Debug)]
 pub(crate) struct Video {
-    nm: Arc<NetworkManager>,
+    redraw_callback: Option<RedrawCallback>,
     name: String,
     options: style_spec::Video,
+    frames: Option<Arc<Mutex<VideoFrames>>>,
+    frame_rate: f64,
+    frame_count: usize,
+    corners: Option<Corners>,
+    playback: Arc<Mutex<Playback>>,
+    state: Arc<Mutex<FrameState>>,
 }
 
 impl Video {
-    pub fn new(nm: Arc<NetworkManager>, name: &str, options: &style_spec::Video) -> Self {
+    pub fn new(
+        redraw_callback: Option<RedrawCallback>,
+        name: &str,
+        options: &style_spec::Video,
+    ) -> Self {
         Self {
-            nm,
+            redraw_callback,
             name: name.to_owned(),
             options: options.clone(),
+            frames: None,
+            frame_rate: FRAME_RATE,
+            frame_count: 0,
+            corners: None,
+            playback: Arc::new(Mutex::new(Playback::default())),
+            state: Arc::new(Mutex::new(FrameState::default())),
+        }
+    }
+
+    pub fn play(&self) {
+        self.playback.lock().unwrap().play(Instant::now());
+        self.redraw();
+    }
+
+    pub fn pause(&self) {
+        self.playback.lock().unwrap().pause(Instant::now());
+    }
+
+    pub fn seek(&self, position: Duration) {
+        self.playback.lock().unwrap().seek(Instant::now(), position);
+        self.redraw();
+    }
+
+    fn redraw(&self) {
+        if let Some(redraw_callback) = &self.redraw_callback {
+            redraw_callback.notify();
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playback.lock().unwrap().started.is_some()
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frame_count as f64 / self.frame_rate)
+    }
+
+    // The frame to draw now and the corners it is stretched over. When the
+    // frame due isn't decoded yet, it is requested and the last one returned.
+    pub fn current_frame(&self) -> Option<(Arc<RgbaImage>, Corners)> {
+        let position = self.playback.lock().unwrap().position(Instant::now());
+        if let Some(index) = frame_index(position, self.frame_rate, self.frame_count) {
+            self.request_frame(index);
+        }
+        let state = self.state.lock().unwrap();
+        let (_, frame) = state.decoded.as_ref()?;
+        Some((frame.clone(), self.corners?))
+    }
+
+    pub fn take_error(&self) -> Option<String> {
+        self.state.lock().unwrap().error.take()
+    }
+
+    fn request_frame(&self, index: usize) {
+        let frames = match &self.frames {
+            Some(frames) => frames.clone(),
+            None => return,
+        };
+        {
+            let mut state = self.state.lock().unwrap();
+            let decoded = matches!(state.decoded, Some((decoded, _)) if decoded == index);
+            if decoded || state.decoding.is_some() {
+                return;
+            }
+            state.decoding = Some(index);
         }
+
+        let state = self.state.clone();
+        let (playback, redraw_callback) = (self.playback.clone(), self.redraw_callback.clone());
+        let (frame_rate, frame_count) = (self.frame_rate, self.frame_count);
+        tokio::spawn(async move {
+            let result = decode_frame(frames, index).await;
+            {
+                let mut state = state.lock().unwrap();
+                state.decoding = None;
+                match result {
+                    Ok(frame) => state.decoded = Some((index, Arc::new(frame))),
+                    Err(e) => {
+                        state.error = Some(format!("Failed to decode frame {}: {}", index, e))
+                    }
+                }
+            }
+
+            let redraw_callback = match redraw_callback {
+                Some(redraw_callback) => redraw_callback,
+                None => return,
+            };
+            let delay = {
+                let playback = playback.lock().unwrap();
+                if playback.started.is_none() {
+                    return;
+                }
+                let position = playback.position(Instant::now());
+                next_frame_delay(position, index, frame_rate, frame_count)
+            };
+            redraw_callback.notify();
+            delay_for(delay).await;
+            redraw_callback.notify();
+        });
+    }
+
+    // Opens the url and decodes its first frame.
+    async fn open(&mut self, url: &str) -> Result<()> {
+        let url = url.to_owned();
+        let frames = spawn_blocking(move || VideoFrames::open(&url)).await??;
+        let (frame_rate, frame_count) = (frames.frame_rate(), frames.frame_count());
+        let frames = Arc::new(Mutex::new(frames));
+        let frame = decode_frame(frames.clone(), 0).await?;
+
+        self.state.lock().unwrap().decoded = Some((0, Arc::new(frame)));
+        self.frames = Some(frames);
+        self.frame_rate = frame_rate;
+        self.frame_count = frame_count;
+        Ok(())
     }
 }
 
 #[async_trait]
 impl SourceControl for Video {
     async fn load(&mut self) -> Result<()> {
-        todo!()
+        self.corners = Some(Corners::parse(&self.options.coordinates)?);
+
+        // Like the urls of a video element, these are alternatives and the
+        // first one that can be played is used.
+        let mut errors = Vec::new();
+        for url in &self.options.urls.clone() {
+            match self.open(url).await {
+                Ok(()) => break,
+                Err(e) => errors.push(format!("{}: {}", url, e)),
+            }
+        }
+        if self.frames.is_none() {
+            return Err(eyre!(
+                "Source \"{}\" has no playable url ({})",
+                self.name,
+                errors.join(", ")
+            ));
+        }
+
+        self.play();
+        Ok(())
     }
 
-    fn has_tile(&self, _tile_id: &OverscaledTileId) -> bool {
-        todo!()
+    fn has_tile(&self, tile_id: &OverscaledTileId) -> bool {
+        match &self.corners {
+            Some(corners) => corners.intersects(tile_id.canonical()),
+            None => false,
+        }
     }
 
-    async fn load_tile(&self, _tile: &mut Tile) -> Result<()> {
-        todo!()
+    async fn load_tile(&self, tile: &mut Tile) -> Result<()> {
+        let canonical = tile.tile_id().canonical();
+        let quad = match self.current_frame() {
+            Some((frame, corners)) if corners.intersects(canonical) => {
+                Some(corners.quad(frame, canonical))
+            }
+            _ => None,
+        };
+        tile.set_image_data(quad);
+        Ok(())
     }
 
     fn tile_size(&self) -> u32 {
-        todo!()
+        512
     }
 
     fn min_zoom(&self) -> f32 {
-        todo!()
+        0.0
     }
 
     fn max_zoom(&self) -> f32 {
-        todo!()
+        22.0
     }
 
     fn round_zoom(&self) -> bool {
-        todo!()
+        false
     }
 
     fn reparse_overscaled(&self) -> bool {
-        todo!()
+        false
     }
 
     fn render_world_copies(&self) -> bool {
-        todo!()
+        true
+    }
+}
+
+async fn decode_frame(frames: Arc<Mutex<VideoFrames>>, index: usize) -> Result<RgbaImage> {
+    spawn_blocking(move || frames.lock().unwrap().decode(index)).await?
+}
+
+fn frame_index(position: Duration, frame_rate: f64, frame_count: usize) -> Option<usize> {
+    if frame_count == 0 {
+        return None;
+    }
+    Some((position.as_secs_f64() * frame_rate) as usize % frame_count)
+}
+
+// Time until the frame after `index` is due. Playback that moved past it or
+// looped around asks for a frame right away.
+fn next_frame_delay(
+    position: Duration,
+    index: usize,
+    frame_rate: f64,
+    frame_count: usize,
+) -> Duration {
+    let duration = frame_count as f64 / frame_rate;
+    let position = position.as_secs_f64() % duration;
+    let next = (index + 1) as f64 / frame_rate;
+    if position < index as f64 / frame_rate || position >= next {
+        return Duration::from_secs(0);
+    }
+    Duration::from_secs_f64(next - position)
+}
+
+fn is_frame(path: &Path) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => FRAME_EXTENSIONS.contains(&extension.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+// Width, height, frame rate and frame count from the key=value lines printed
+// by ffprobe.
+#[cfg(feature = "ffmpeg")]
+fn parse_probe(output: &str) -> Result<(u32, u32, f64, usize)> {
+    let values = output
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .collect::<HashMap<_, _>>();
+    let value = |key: &str| {
+        values
+            .get(key)
+            .map(|value| value.trim())
+            .ok_or_else(|| eyre!("ffprobe didn't report the {}", key))
+    };
+
+    let frame_rate = match value("avg_frame_rate")?.split_once('/') {
+        Some((frames, seconds)) => frames.parse::<f64>()? / seconds.parse::<f64>()?,
+        None => value("avg_frame_rate")?.parse()?,
+    };
+    if !frame_rate.is_finite() || frame_rate <= 0.0 {
+        return Err(eyre!("Invalid frame rate {}", frame_rate));
+    }
+    let frame_count = match value("nb_frames")?.parse() {
+        Ok(frame_count) => frame_count,
+        Err(_) => (value("duration")?.parse::<f64>()? * frame_rate).round() as usize,
+    };
+    Ok((
+        value("width")?.parse()?,
+        value("height")?.parse()?,
+        frame_rate,
+        frame_count,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn video_playback() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut playback = Playback::default();
+        assert_eq!(playback.position(at(500)), Duration::from_secs(0));
+
+        playback.play(start);
+        assert_eq!(playback.position(at(500)), Duration::from_millis(500));
+        playback.pause(at(500));
+        assert_eq!(playback.position(at(2000)), Duration::from_millis(500));
+
+        playback.seek(at(2000), Duration::from_secs(3));
+        playback.play(at(2000));
+        assert_eq!(playback.position(at(2100)), Duration::from_millis(3100));
+
+        assert_eq!(frame_index(Duration::from_millis(100), 30.0, 10), Some(3));
+        // Playback loops at the end of the sequence.
+        assert_eq!(frame_index(Duration::from_millis(400), 30.0, 10), Some(2));
+        assert_eq!(frame_index(Duration::from_millis(400), 25.0, 20), Some(10));
+        assert_eq!(frame_index(Duration::from_secs(1), 30.0, 0), None);
+    }
+
+    #[test]
+    fn video_next_frame_delay() {
+        let delay = |ms, index| next_frame_delay(Duration::from_millis(ms), index, 10.0, 10);
+        assert_eq!(delay(0, 0), Duration::from_millis(100));
+        assert_eq!(delay(250, 2), Duration::from_millis(50));
+        // Decoding took longer than the frame lasts.
+        assert_eq!(delay(350, 2), Duration::from_secs(0));
+        // The last frame is followed by the first one.
+        assert_eq!(delay(950, 9), Duration::from_millis(50));
+        assert_eq!(delay(1020, 9), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn video_frames_sequence() {
+        let dir = std::env::temp_dir().join(format!("video-frames-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in &["0002.png", "0001.PNG", "notes.txt"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let frames = VideoFrames::open(&format!("file://{}", dir.display())).unwrap();
+        match &frames {
+            VideoFrames::Sequence(paths) => {
+                assert_eq!(paths, &[dir.join("0001.PNG"), dir.join("0002.png")])
+            }
+            #[cfg(feature = "ffmpeg")]
+            VideoFrames::Encoded(_) => panic!("expected an image sequence"),
+        }
+        assert_eq!(frames.frame_rate(), FRAME_RATE);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(is_frame(Path::new("frames/0001.PNG")));
+        assert!(!is_frame(Path::new("frames")));
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    #[test]
+    fn video_probe_output() {
+        let output = "width=1280\nheight=720\navg_frame_rate=30000/1001\nnb_frames=300\nduration=10.010000\n";
+        let (width, height, frame_rate, frame_count) = parse_probe(output).unwrap();
+        assert_eq!((width, height, frame_count), (1280, 720, 300));
+        assert!((frame_rate - 29.97).abs() < 0.01);
+
+        // Webm and mkv don't store the frame count.
+        let output =
+            "width=640\nheight=360\navg_frame_rate=25/1\nnb_frames=N/A\nduration=4.000000\n";
+        assert_eq!(parse_probe(output).unwrap().3, 100);
+
+        assert!(parse_probe("width=1280\nheight=720\navg_frame_rate=0/0\nnb_frames=1").is_err());
+        assert!(parse_probe("width=1280\nheight=720").is_err());
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    #[test]
+    fn video_encoded_needs_ffmpeg() {
+        let error = VideoFrames::open("file:///videos/drone.mp4").unwrap_err();
+        assert!(error.to_string().contains("ffmpeg feature"));
     }
 }
@@ -0,0 +1,297 @@
+use eyre::{eyre, Result};
+use serde_json::{Map, Number, Value};
+
+const OPERATORS: &[&str] = &[
+    "accumulated",
+    "get",
+    "has",
+    "literal",
+    "+",
+    "-",
+    "*",
+    "/",
+    "%",
+    "max",
+    "min",
+    "!",
+    "all",
+    "any",
+    "==",
+    "!=",
+    "<",
+    "<=",
+    ">",
+    ">=",
+    "case",
+    "coalesce",
+    "concat",
+    "to-number",
+    "to-string",
+];
+
+#[derive(Debug, Clone)]
+struct ClusterProperty {
+    name: String,
+    map: Value,
+    reduce: Value,
+}
+
+// Aggregates declared in the `clusterProperties` of a GeoJSON source. Every
+// property has a map expression evaluated on each point and a reduce
+// expression that folds the mapped values together.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ClusterProperties {
+    properties: Vec<ClusterProperty>,
+}
+
+impl ClusterProperties {
+    pub fn parse(value: &Value) -> Result<Self> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| eyre!("clusterProperties must be an object"))?;
+
+        let mut properties = Vec::new();
+        for (name, property) in object {
+            let (reduce, map) = match property.as_array().map(Vec::as_slice) {
+                Some([reduce, map]) => (reduce, map),
+                _ => {
+                    return Err(eyre!(
+                        "Cluster property \"{}\" must be [operator, map expression]",
+                        name
+                    ))
+                }
+            };
+            // A bare operator reduces the accumulated value with the
+            // property of the next point.
+            let reduce = match reduce {
+                Value::String(operator) => Value::Array(vec![
+                    Value::String(operator.clone()),
+                    Value::Array(vec![Value::from("accumulated")]),
+                    Value::Array(vec![Value::from("get"), Value::from(name.as_str())]),
+                ]),
+                reduce => reduce.clone(),
+            };
+            validate(&reduce)?;
+            validate(map)?;
+            properties.push(ClusterProperty {
+                name: name.clone(),
+                map: map.clone(),
+                reduce,
+            });
+        }
+        Ok(Self { properties })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.properties.is_empty()
+    }
+
+    pub fn map(&self, properties: &Map<String, Value>) -> Map<String, Value> {
+        self.properties
+            .iter()
+            .map(|property| {
+                let value = evaluate(&property.map, properties, &Value::Null);
+                (property.name.clone(), value)
+            })
+            .collect()
+    }
+
+    pub fn reduce(&self, accumulated: &mut Map<String, Value>, properties: &Map<String, Value>) {
+        for property in &self.properties {
+            let current = accumulated
+                .get(&property.name)
+                .cloned()
+                .unwrap_or(Value::Null);
+            let value = evaluate(&property.reduce, properties, &current);
+            accumulated.insert(property.name.clone(), value);
+        }
+    }
+}
+
+fn validate(expression: &Value) -> Result<()> {
+    let args = match expression {
+        Value::Array(args) => args,
+        Value::Object(_) => return Err(eyre!("Unexpected object in expression {}", expression)),
+        _ => return Ok(()),
+    };
+    match args.first() {
+        Some(Value::String(operator)) if operator == "literal" => Ok(()),
+        Some(Value::String(operator)) if OPERATORS.contains(&operator.as_str()) => {
+            args[1..].iter().try_for_each(validate)
+        }
+        Some(operator) => Err(eyre!(
+            "Unsupported operator {} in cluster property expression",
+            operator
+        )),
+        None => Err(eyre!("Empty cluster property expression")),
+    }
+}
+
+fn evaluate(expression: &Value, properties: &Map<String, Value>, accumulated: &Value) -> Value {
+    let args = match expression {
+        Value::Array(args) if !args.is_empty() => args,
+        _ => return expression.clone(),
+    };
+    let operator = args[0].as_str().unwrap_or_default();
+    let args = &args[1..];
+    let eval = |arg: &Value| evaluate(arg, properties, accumulated);
+    let numbers = || {
+        args.iter()
+            .map(|arg| eval(arg).as_f64())
+            .collect::<Option<Vec<_>>>()
+    };
+
+    match operator {
+        "accumulated" => accumulated.clone(),
+        "get" => args
+            .first()
+            .and_then(|key| {
+                eval(key)
+                    .as_str()
+                    .and_then(|key| properties.get(key))
+                    .cloned()
+            })
+            .unwrap_or(Value::Null),
+        "has" => Value::Bool(
+            args.first()
+                .and_then(|key| eval(key).as_str().map(|key| properties.contains_key(key)))
+                .unwrap_or(false),
+        ),
+        "literal" => args.first().cloned().unwrap_or(Value::Null),
+        "+" => number(numbers().map(|n| n.iter().sum())),
+        "*" => number(numbers().map(|n| n.iter().product())),
+        "max" => number(numbers().and_then(|n| n.into_iter().reduce(f64::max))),
+        "min" => number(numbers().and_then(|n| n.into_iter().reduce(f64::min))),
+        "-" => number(numbers().and_then(|n| match n.as_slice() {
+            [a] => Some(-a),
+            [a, b] => Some(a - b),
+            _ => None,
+        })),
+        "/" => number(numbers().and_then(|n| match n.as_slice() {
+            [a, b] => Some(a / b),
+            _ => None,
+        })),
+        "%" => number(numbers().and_then(|n| match n.as_slice() {
+            [a, b] => Some(a % b),
+            _ => None,
+        })),
+        "!" => Value::Bool(!truthy(&args.first().map(eval).unwrap_or(Value::Null))),
+        "all" => Value::Bool(args.iter().all(|arg| truthy(&eval(arg)))),
+        "any" => Value::Bool(args.iter().any(|arg| truthy(&eval(arg)))),
+        "==" | "!=" | "<" | "<=" | ">" | ">=" => match args {
+            [a, b] => compare(operator, &eval(a), &eval(b)),
+            _ => Value::Null,
+        },
+        "case" => {
+            for pair in args.chunks(2) {
+                match pair {
+                    [condition, value] if truthy(&eval(condition)) => return eval(value),
+                    [fallback] => return eval(fallback),
+                    _ => {}
+                }
+            }
+            Value::Null
+        }
+        "coalesce" => args
+            .iter()
+            .map(eval)
+            .find(|value| !value.is_null())
+            .unwrap_or(Value::Null),
+        "concat" => Value::String(args.iter().map(|arg| to_string(&eval(arg))).collect()),
+        "to-number" => number(args.first().and_then(|arg| match eval(arg) {
+            Value::Number(n) => n.as_f64(),
+            Value::String(s) => s.parse().ok(),
+            Value::Bool(b) => Some(if b { 1.0 } else { 0.0 }),
+            _ => None,
+        })),
+        "to-string" => Value::String(
+            args.first()
+                .map(|arg| to_string(&eval(arg)))
+                .unwrap_or_default(),
+        ),
+        _ => Value::Null,
+    }
+}
+
+fn number(value: Option<f64>) -> Value {
+    value
+        .and_then(Number::from_f64)
+        .map(Value::Number)
+        .unwrap_or(Value::Null)
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Bool(b) => *b,
+        Value::Null => false,
+        _ => true,
+    }
+}
+
+fn compare(operator: &str, a: &Value, b: &Value) -> Value {
+    let ordering = match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+    Value::Bool(match (operator, ordering) {
+        ("==", Some(ordering)) => ordering.is_eq(),
+        ("!=", Some(ordering)) => ordering.is_ne(),
+        ("==", None) => a == b,
+        ("!=", None) => a != b,
+        ("<", Some(ordering)) => ordering.is_lt(),
+        ("<=", Some(ordering)) => ordering.is_le(),
+        (">", Some(ordering)) => ordering.is_gt(),
+        (">=", Some(ordering)) => ordering.is_ge(),
+        _ => false,
+    })
+}
+
+fn to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn props(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn cluster_properties_map_reduce() {
+        let cluster_properties = ClusterProperties::parse(&json!({
+            "sum": ["+", ["get", "scalerank"]],
+            "max": ["max", ["get", "scalerank"]],
+            "capitals": [["+", ["accumulated"], ["get", "capitals"]], ["case", ["==", ["get", "capital"], true], 1, 0]]
+        }))
+        .unwrap();
+
+        let a = props(json!({ "scalerank": 2, "capital": true }));
+        let b = props(json!({ "scalerank": 5, "capital": false }));
+
+        let mut accumulated = cluster_properties.map(&a);
+        assert_eq!(accumulated["sum"], json!(2));
+        assert_eq!(accumulated["capitals"], json!(1));
+
+        cluster_properties.reduce(&mut accumulated, &cluster_properties.map(&b));
+        assert_eq!(accumulated["sum"], json!(7.0));
+        assert_eq!(accumulated["max"], json!(5.0));
+        assert_eq!(accumulated["capitals"], json!(1.0));
+    }
+
+    #[test]
+    fn cluster_properties_invalid() {
+        assert!(ClusterProperties::parse(&json!([])).is_err());
+        assert!(ClusterProperties::parse(&json!({ "sum": ["+"] })).is_err());
+        assert!(
+            ClusterProperties::parse(&json!({ "sum": ["interpolate", ["get", "a"]] })).is_err()
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use std::cmp::Ordering;
+
+// Static KD-tree over points, ported from kdbush.
+#[derive(Debug)]
+pub(crate) struct KDBush {
+    node_size: usize,
+    // Point index and coordinates, sorted into tree order.
+    entries: Vec<(usize, f64, f64)>,
+}
+
+impl KDBush {
+    pub fn new(points: impl Iterator<Item = (f64, f64)>, node_size: usize) -> Self {
+        let node_size = node_size.max(1);
+        let mut entries = points
+            .enumerate()
+            .map(|(i, (x, y))| (i, x, y))
+            .collect::<Vec<_>>();
+        if !entries.is_empty() {
+            let right = entries.len() - 1;
+            sort(&mut entries, node_size, 0, right, 0);
+        }
+        Self { node_size, entries }
+    }
+
+    pub fn range(&self, min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Vec<usize> {
+        self.search(
+            |x, y| x >= min_x && x <= max_x && y >= min_y && y <= max_y,
+            |axis, x, y| match axis {
+                0 => (min_x <= x, max_x >= x),
+                _ => (min_y <= y, max_y >= y),
+            },
+        )
+    }
+
+    pub fn within(&self, qx: f64, qy: f64, r: f64) -> Vec<usize> {
+        let r2 = r * r;
+        self.search(
+            |x, y| (x - qx).powi(2) + (y - qy).powi(2) <= r2,
+            |axis, x, y| match axis {
+                0 => (qx - r <= x, qx + r >= x),
+                _ => (qy - r <= y, qy + r >= y),
+            },
+        )
+    }
+
+    // `visit` tells whether the left and right halves of a node can contain
+    // matches.
+    fn search<F, V>(&self, matches: F, visit: V) -> Vec<usize>
+    where
+        F: Fn(f64, f64) -> bool,
+        V: Fn(usize, f64, f64) -> (bool, bool),
+    {
+        let mut result = Vec::new();
+        if self.entries.is_empty() {
+            return result;
+        }
+
+        let mut stack = vec![(0, self.entries.len() - 1, 0)];
+        while let Some((left, right, axis)) = stack.pop() {
+            if right - left <= self.node_size {
+                for &(i, x, y) in &self.entries[left..=right] {
+                    if matches(x, y) {
+                        result.push(i);
+                    }
+                }
+                continue;
+            }
+
+            let m = (left + right) / 2;
+            let (i, x, y) = self.entries[m];
+            if matches(x, y) {
+                result.push(i);
+            }
+            let (visit_left, visit_right) = visit(axis, x, y);
+            if visit_left {
+                stack.push((left, m - 1, 1 - axis));
+            }
+            if visit_right {
+                stack.push((m + 1, right, 1 - axis));
+            }
+        }
+        result
+    }
+}
+
+fn sort(
+    entries: &mut [(usize, f64, f64)],
+    node_size: usize,
+    left: usize,
+    right: usize,
+    axis: usize,
+) {
+    if right - left <= node_size {
+        return;
+    }
+
+    let m = (left + right) / 2;
+    entries[left..=right].select_nth_unstable_by(m - left, |a, b| {
+        let (a, b) = if axis == 0 { (a.1, b.1) } else { (a.2, b.2) };
+        a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+    });
+    sort(entries, node_size, left, m - 1, 1 - axis);
+    sort(entries, node_size, m + 1, right, 1 - axis);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> Vec<(f64, f64)> {
+        (0..100)
+            .map(|i| (f64::from(i % 10), f64::from(i / 10)))
+            .collect()
+    }
+
+    #[test]
+    fn kdbush_range() {
+        let points = grid();
+        let index = KDBush::new(points.iter().copied(), 4);
+        let mut ids = index.range(2.0, 3.0, 4.0, 4.0);
+        ids.sort_unstable();
+        assert_eq!(ids, vec![32, 33, 34, 42, 43, 44]);
+    }
+
+    #[test]
+    fn kdbush_within() {
+        let points = grid();
+        let index = KDBush::new(points.iter().copied(), 4);
+        let mut ids = index.within(5.0, 5.0, 1.0);
+        ids.sort_unstable();
+        assert_eq!(ids, vec![45, 54, 55, 56, 65]);
+        assert!(KDBush::new(std::iter::empty(), 4)
+            .within(0.0, 0.0, 1.0)
+            .is_empty());
+    }
+}
@@ -0,0 +1,572 @@
+mod cluster_properties;
+mod kdbush;
+
+use super::geojson_vt::{project_x, project_y, FeatureType, TileFeature, VectorTile};
+use eyre::{eyre, Result};
+use kdbush::KDBush;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+pub(crate) use cluster_properties::ClusterProperties;
+
+const NODE_SIZE: usize = 64;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Options {
+    pub min_zoom: u32,
+    pub max_zoom: u32,
+    pub min_points: usize,
+    // Cluster radius, in the same units as `extent`.
+    pub radius: f64,
+    pub extent: u32,
+    pub generate_id: bool,
+    pub cluster_properties: ClusterProperties,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            min_zoom: 0,
+            max_zoom: 16,
+            min_points: 2,
+            radius: 40.0,
+            extent: 512,
+            generate_id: false,
+            cluster_properties: ClusterProperties::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Cluster {
+    x: f64,
+    y: f64,
+    // The last zoom the cluster was processed at.
+    zoom: u32,
+    // The index of the input point, or the id of the cluster.
+    id: usize,
+    parent_id: Option<usize>,
+    num_points: usize,
+    properties: Map<String, Value>,
+}
+
+impl Cluster {
+    fn is_cluster(&self) -> bool {
+        self.num_points > 1
+    }
+}
+
+#[derive(Debug)]
+struct Tree {
+    index: KDBush,
+    clusters: Vec<Cluster>,
+}
+
+impl Tree {
+    fn new(clusters: Vec<Cluster>) -> Self {
+        let index = KDBush::new(clusters.iter().map(|c| (c.x, c.y)), NODE_SIZE);
+        Self { index, clusters }
+    }
+}
+
+// Hierarchical point clustering ported from supercluster. Each zoom has a
+// KD-tree of the clusters formed at that zoom, built bottom up from the
+// input points.
+#[derive(Debug)]
+pub(crate) struct Supercluster {
+    options: Options,
+    points: Vec<Value>,
+    // Indices of the points with an id.
+    ids: HashMap<String, usize>,
+    trees: Vec<Tree>,
+}
+
+impl Supercluster {
+    pub fn new(data: &Value, options: Options) -> Result<Self> {
+        let points = point_features(data)?;
+
+        let mut clusters = Vec::with_capacity(points.len());
+        for (i, point) in points.iter().enumerate() {
+            let (lng, lat) = coordinates(point).unwrap_or_default();
+            clusters.push(Cluster {
+                x: project_x(lng),
+                y: project_y(lat),
+                zoom: u32::MAX,
+                id: i,
+                parent_id: None,
+                num_points: 1,
+                properties: Map::new(),
+            });
+        }
+
+        let ids = points
+            .iter()
+            .enumerate()
+            .filter_map(|(i, point)| Some((point.get("id")?.to_string(), i)))
+            .collect();
+        let mut index = Self {
+            options,
+            points,
+            ids,
+            trees: Vec::new(),
+        };
+        let (min_zoom, max_zoom) = (index.options.min_zoom, index.options.max_zoom);
+        index
+            .trees
+            .resize_with(max_zoom as usize + 2, || Tree::new(Vec::new()));
+        index.trees[max_zoom as usize + 1] = Tree::new(clusters);
+        for z in (min_zoom..=max_zoom).rev() {
+            let clusters = index.cluster(z);
+            index.trees[z as usize] = Tree::new(clusters);
+        }
+        Ok(index)
+    }
+
+    pub fn points(&self) -> &[Value] {
+        &self.points
+    }
+
+    pub fn point(&self, id: &Value) -> Option<&Value> {
+        Some(&self.points[*self.ids.get(&id.to_string())?])
+    }
+
+    pub fn get_tile(&self, z: u32, x: u32, y: u32) -> Option<VectorTile> {
+        let tree = &self.trees[self.limit_zoom(z)];
+        let z2 = f64::from(1u32 << z);
+        let p = self.options.radius / f64::from(self.options.extent);
+        let (fx, fy) = (f64::from(x), f64::from(y));
+        let top = (fy - p) / z2;
+        let bottom = (fy + 1.0 + p) / z2;
+
+        let mut features = Vec::new();
+        let ids = tree
+            .index
+            .range((fx - p) / z2, top, (fx + 1.0 + p) / z2, bottom);
+        self.add_tile_features(&mut features, tree, &ids, fx, fy, z2);
+        // Clusters near the antimeridian are also drawn on the other side.
+        if x == 0 {
+            let ids = tree.index.range(1.0 - p / z2, top, 1.0, bottom);
+            self.add_tile_features(&mut features, tree, &ids, z2, fy, z2);
+        }
+        if x == (1u32 << z) - 1 {
+            let ids = tree.index.range(0.0, top, p / z2, bottom);
+            self.add_tile_features(&mut features, tree, &ids, -1.0, fy, z2);
+        }
+
+        if features.is_empty() {
+            None
+        } else {
+            Some(VectorTile::new(features))
+        }
+    }
+
+    pub fn get_children(&self, cluster_id: usize) -> Result<Vec<Value>> {
+        let error = || eyre!("No cluster with id {}", cluster_id);
+        let (origin_id, origin_zoom) = self.origin(cluster_id).ok_or_else(error)?;
+        let tree = self.trees.get(origin_zoom).ok_or_else(error)?;
+        let origin = tree.clusters.get(origin_id).ok_or_else(error)?;
+
+        let r = self.options.radius
+            / (f64::from(self.options.extent) * 2f64.powi(origin_zoom as i32 - 1));
+        let children = tree
+            .index
+            .within(origin.x, origin.y, r)
+            .into_iter()
+            .map(|id| &tree.clusters[id])
+            .filter(|cluster| cluster.parent_id == Some(cluster_id))
+            .map(|cluster| self.cluster_json(cluster))
+            .collect::<Vec<_>>();
+
+        if children.is_empty() {
+            return Err(error());
+        }
+        Ok(children)
+    }
+
+    pub fn get_leaves(&self, cluster_id: usize, limit: usize, offset: usize) -> Result<Vec<Value>> {
+        let mut leaves = Vec::new();
+        self.append_leaves(&mut leaves, cluster_id, limit, offset, 0)?;
+        Ok(leaves)
+    }
+
+    pub fn get_cluster_expansion_zoom(&self, mut cluster_id: usize) -> Result<u32> {
+        let mut expansion_zoom = self
+            .origin(cluster_id)
+            .and_then(|(_, origin_zoom)| (origin_zoom as u32).checked_sub(1))
+            .ok_or_else(|| eyre!("No cluster with id {}", cluster_id))?;
+        while expansion_zoom <= self.options.max_zoom {
+            let children = self.get_children(cluster_id)?;
+            expansion_zoom += 1;
+            match children.as_slice() {
+                [child] => match cluster_id_of(child) {
+                    Some(id) => cluster_id = id,
+                    None => break,
+                },
+                _ => break,
+            }
+        }
+        Ok(expansion_zoom)
+    }
+
+    fn cluster(&mut self, zoom: u32) -> Vec<Cluster> {
+        let Options {
+            radius,
+            extent,
+            min_points,
+            ..
+        } = self.options;
+        let num_inputs = self.points.len();
+        let r = radius / (f64::from(extent) * 2f64.powi(zoom as i32));
+        let tree = &mut self.trees[zoom as usize + 1];
+        let cluster_properties = &self.options.cluster_properties;
+        let reduce = !cluster_properties.is_empty();
+        let points = &self.points;
+        let map = |cluster: &Cluster| {
+            if cluster.is_cluster() {
+                cluster.properties.clone()
+            } else {
+                cluster_properties.map(&properties(&points[cluster.id]))
+            }
+        };
+
+        let mut clusters = Vec::new();
+        for i in 0..tree.clusters.len() {
+            if tree.clusters[i].zoom <= zoom {
+                continue;
+            }
+            tree.clusters[i].zoom = zoom;
+
+            let p = &tree.clusters[i];
+            let neighbor_ids = tree.index.within(p.x, p.y, r);
+            let num_points_origin = p.num_points;
+            let num_points = num_points_origin
+                + neighbor_ids
+                    .iter()
+                    .map(|&id| &tree.clusters[id])
+                    .filter(|b| b.zoom > zoom)
+                    .map(|b| b.num_points)
+                    .sum::<usize>();
+
+            if num_points > num_points_origin && num_points >= min_points {
+                let mut wx = p.x * num_points_origin as f64;
+                let mut wy = p.y * num_points_origin as f64;
+                let mut properties = if reduce { map(p) } else { Map::new() };
+                // The id encodes the zoom and the index of the point the
+                // cluster originated from.
+                let id = (i << 5) + (zoom as usize + 1) + num_inputs;
+
+                for neighbor_id in neighbor_ids {
+                    let b = &mut tree.clusters[neighbor_id];
+                    if b.zoom <= zoom {
+                        continue;
+                    }
+                    b.zoom = zoom;
+                    b.parent_id = Some(id);
+                    wx += b.x * b.num_points as f64;
+                    wy += b.y * b.num_points as f64;
+                    if reduce {
+                        cluster_properties.reduce(&mut properties, &map(b));
+                    }
+                }
+
+                tree.clusters[i].parent_id = Some(id);
+                clusters.push(Cluster {
+                    x: wx / num_points as f64,
+                    y: wy / num_points as f64,
+                    zoom: u32::MAX,
+                    id,
+                    parent_id: None,
+                    num_points,
+                    properties,
+                });
+            } else {
+                clusters.push(tree.clusters[i].clone());
+                if num_points > 1 {
+                    for neighbor_id in neighbor_ids {
+                        let b = &mut tree.clusters[neighbor_id];
+                        if b.zoom <= zoom {
+                            continue;
+                        }
+                        b.zoom = zoom;
+                        clusters.push(b.clone());
+                    }
+                }
+            }
+        }
+        clusters
+    }
+
+    fn append_leaves(
+        &self,
+        leaves: &mut Vec<Value>,
+        cluster_id: usize,
+        limit: usize,
+        offset: usize,
+        mut skipped: usize,
+    ) -> Result<usize> {
+        for child in self.get_children(cluster_id)? {
+            if let Some(child_id) = cluster_id_of(&child) {
+                let point_count = child["properties"]["point_count"].as_u64().unwrap_or(0) as usize;
+                if skipped + point_count <= offset {
+                    skipped += point_count;
+                } else {
+                    skipped = self.append_leaves(leaves, child_id, limit, offset, skipped)?;
+                }
+            } else if skipped < offset {
+                skipped += 1;
+            } else {
+                leaves.push(child);
+            }
+            if leaves.len() == limit {
+                break;
+            }
+        }
+        Ok(skipped)
+    }
+
+    fn add_tile_features(
+        &self,
+        features: &mut Vec<TileFeature>,
+        tree: &Tree,
+        ids: &[usize],
+        x: f64,
+        y: f64,
+        z2: f64,
+    ) {
+        let extent = f64::from(self.options.extent);
+        for &i in ids {
+            let cluster = &tree.clusters[i];
+            let (id, properties, px, py) = if cluster.is_cluster() {
+                (
+                    Some(Value::from(cluster.id)),
+                    cluster_properties(cluster),
+                    cluster.x,
+                    cluster.y,
+                )
+            } else {
+                let point = &self.points[cluster.id];
+                let id = if self.options.generate_id {
+                    Some(Value::from(cluster.id))
+                } else {
+                    point.get("id").cloned()
+                };
+                (id, properties(point), cluster.x, cluster.y)
+            };
+            features.push(TileFeature {
+                id,
+                kind: FeatureType::Point,
+                geometry: vec![vec![[
+                    (extent * (px * z2 - x)).round() as i32,
+                    (extent * (py * z2 - y)).round() as i32,
+                ]]],
+                properties,
+            });
+        }
+    }
+
+    fn cluster_json(&self, cluster: &Cluster) -> Value {
+        if !cluster.is_cluster() {
+            return self.points[cluster.id].clone();
+        }
+        json!({
+            "type": "Feature",
+            "id": cluster.id,
+            "properties": cluster_properties(cluster),
+            "geometry": {
+                "type": "Point",
+                "coordinates": [x_lng(cluster.x), y_lat(cluster.y)]
+            }
+        })
+    }
+
+    fn origin(&self, cluster_id: usize) -> Option<(usize, usize)> {
+        let id = cluster_id.checked_sub(self.points.len())?;
+        Some((id >> 5, id % 32))
+    }
+
+    fn limit_zoom(&self, z: u32) -> usize {
+        z.min(self.options.max_zoom + 1).max(self.options.min_zoom) as usize
+    }
+}
+
+fn point_features(data: &Value) -> Result<Vec<Value>> {
+    let features = match data.get("type").and_then(Value::as_str) {
+        Some("FeatureCollection") => data
+            .get("features")
+            .and_then(Value::as_array)
+            .cloned()
+            .ok_or_else(|| eyre!("FeatureCollection has no features"))?,
+        Some("Feature") => vec![data.clone()],
+        _ => {
+            return Err(eyre!(
+                "Clustered sources need a Feature or FeatureCollection"
+            ))
+        }
+    };
+    // Only points can be clustered.
+    Ok(features
+        .into_iter()
+        .filter(|feature| coordinates(feature).is_some())
+        .collect())
+}
+
+fn coordinates(feature: &Value) -> Option<(f64, f64)> {
+    let geometry = feature.get("geometry")?;
+    if geometry.get("type")?.as_str()? != "Point" {
+        return None;
+    }
+    let coordinates = geometry.get("coordinates")?;
+    Some((coordinates.get(0)?.as_f64()?, coordinates.get(1)?.as_f64()?))
+}
+
+fn properties(feature: &Value) -> Map<String, Value> {
+    feature
+        .get("properties")
+        .and_then(Value::as_object)
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn cluster_properties(cluster: &Cluster) -> Map<String, Value> {
+    let count = cluster.num_points;
+    let abbreviated = if count >= 10000 {
+        json!(format!("{}k", (count as f64 / 1000.0).round()))
+    } else if count >= 1000 {
+        json!(format!("{}k", (count as f64 / 100.0).round() / 10.0))
+    } else {
+        json!(count)
+    };
+
+    let mut properties = cluster.properties.clone();
+    properties.insert("cluster".to_owned(), json!(true));
+    properties.insert("cluster_id".to_owned(), json!(cluster.id));
+    properties.insert("point_count".to_owned(), json!(count));
+    properties.insert("point_count_abbreviated".to_owned(), abbreviated);
+    properties
+}
+
+fn cluster_id_of(feature: &Value) -> Option<usize> {
+    let properties = feature.get("properties")?;
+    if properties.get("cluster")?.as_bool()? {
+        properties.get("cluster_id")?.as_u64().map(|id| id as usize)
+    } else {
+        None
+    }
+}
+
+fn x_lng(x: f64) -> f64 {
+    (x - 0.5) * 360.0
+}
+
+fn y_lat(y: f64) -> f64 {
+    let y2 = (180.0 - y * 360.0) * PI / 180.0;
+    360.0 * y2.exp().atan() / PI - 90.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn places() -> Value {
+        let point = |lng: f64, lat: f64, rank: u32| {
+            json!({
+                "type": "Feature",
+                "properties": { "rank": rank },
+                "geometry": { "type": "Point", "coordinates": [lng, lat] }
+            })
+        };
+        json!({
+            "type": "FeatureCollection",
+            "features": [
+                point(10.0, 10.0, 1),
+                point(10.01, 10.0, 2),
+                point(10.0, 10.01, 3),
+                point(-60.0, -30.0, 4),
+                { "type": "Feature", "properties": {}, "geometry": { "type": "LineString", "coordinates": [[0, 0], [1, 1]] } }
+            ]
+        })
+    }
+
+    fn options() -> Options {
+        Options {
+            cluster_properties: ClusterProperties::parse(&json!({ "sum": ["+", ["get", "rank"]] }))
+                .unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn supercluster_clusters_nearby_points() {
+        let index = Supercluster::new(&places(), options()).unwrap();
+        assert_eq!(index.points.len(), 4);
+
+        let tile = index.get_tile(0, 0, 0).unwrap();
+        assert_eq!(tile.features.len(), 2);
+        let cluster = tile
+            .features
+            .iter()
+            .find(|feature| feature.properties.contains_key("cluster"))
+            .unwrap();
+        assert_eq!(cluster.properties["point_count"], json!(3));
+        assert_eq!(cluster.properties["sum"], json!(6.0));
+
+        // Past the cluster max zoom every point is on its own.
+        let z2 = f64::from(1u32 << 17);
+        let (x, y) = (project_x(10.0) * z2, project_y(10.0) * z2);
+        let tile = index.get_tile(17, x as u32, y as u32).unwrap();
+        assert_eq!(tile.features.len(), 1);
+        assert_eq!(tile.features[0].properties["rank"], json!(1));
+    }
+
+    #[test]
+    fn supercluster_points_by_id() {
+        let data = json!({
+            "type": "Feature",
+            "id": "harbour",
+            "properties": {},
+            "geometry": { "type": "Point", "coordinates": [10.0, 10.0] }
+        });
+        let index = Supercluster::new(&data, options()).unwrap();
+        assert_eq!(index.point(&json!("harbour")), Some(&data));
+        assert_eq!(index.point(&json!("station")), None);
+        assert_eq!(
+            Supercluster::new(&places(), options()).unwrap().ids.len(),
+            0
+        );
+    }
+
+    #[test]
+    fn supercluster_children_and_leaves() {
+        let index = Supercluster::new(&places(), options()).unwrap();
+        let tile = index.get_tile(0, 0, 0).unwrap();
+        let cluster_id = tile
+            .features
+            .iter()
+            .find_map(|feature| feature.id.as_ref().and_then(Value::as_u64))
+            .unwrap() as usize;
+
+        let leaves = index.get_leaves(cluster_id, 10, 0).unwrap();
+        let mut ranks = leaves
+            .iter()
+            .map(|leaf| leaf["properties"]["rank"].as_u64().unwrap())
+            .collect::<Vec<_>>();
+        ranks.sort_unstable();
+        assert_eq!(ranks, vec![1, 2, 3]);
+        assert_eq!(index.get_leaves(cluster_id, 1, 1).unwrap().len(), 1);
+
+        let expansion_zoom = index.get_cluster_expansion_zoom(cluster_id).unwrap();
+        let children = index.get_children(cluster_id).unwrap();
+        assert!(!children.is_empty());
+        assert!(expansion_zoom > 0 && expansion_zoom <= 17);
+
+        assert!(index.get_children(0).is_err());
+        assert!(index.get_children(usize::MAX >> 8).is_err());
+    }
+
+    #[test]
+    fn unproject_roundtrip() {
+        assert!((x_lng(project_x(12.5)) - 12.5).abs() < 1e-9);
+        assert!((y_lat(project_y(-33.0)) + 33.0).abs() < 1e-9);
+    }
+}
@@ -1,8 +1,20 @@
+use super::dem_data::DEMData;
+use super::geojson_vt::{encode_value, VectorTile};
+use super::sources::ImageQuad;
 use super::tile_id::OverscaledTileId;
-use crate::util::unique_id;
+use crate::util::{unique_id, Field, Reader};
+use eyre::Result;
+use image::RgbaImage;
+use std::sync::Arc;
+use std::time::SystemTime;
 
-#[derive(Debug)]
-enum TileState {
+// Rough sizes of the parts of a decoded vector tile.
+const FEATURE_SIZE: usize = 64;
+const POINT_SIZE: usize = 16;
+const PROPERTY_SIZE: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TileState {
     Loading,
     Loaded,
     Reloading,
@@ -17,7 +29,14 @@ pub(crate) struct Tile {
     state: TileState,
     uid: usize,
     size: usize,
-    vector_data: mvt::Tile<mvt::FeatureWithCoordinates>,
+    // Shared with the tiles overscaling this one.
+    vector_data: Arc<mvt::Tile<mvt::FeatureWithCoordinates>>,
+    // Estimated size of the decoded vector data.
+    vector_size: usize,
+    raster_data: Option<Arc<RgbaImage>>,
+    dem_data: Option<DEMData>,
+    image_data: Option<ImageQuad>,
+    expires: Option<SystemTime>,
 }
 
 impl Tile {
@@ -28,6 +47,27 @@ impl Tile {
             uid: unique_id(),
             size,
             vector_data: Default::default(),
+            vector_size: 0,
+            raster_data: None,
+            dem_data: None,
+            image_data: None,
+            expires: None,
+        }
+    }
+
+    // A tile past the source's max zoom, drawn from the data of this one.
+    pub fn overscaled(&self, tile_id: OverscaledTileId, size: usize) -> Self {
+        Self {
+            tile_id,
+            state: TileState::Loaded,
+            uid: unique_id(),
+            size,
+            vector_data: self.vector_data.clone(),
+            vector_size: self.vector_size,
+            raster_data: self.raster_data.clone(),
+            dem_data: self.dem_data.clone(),
+            image_data: self.image_data.clone(),
+            expires: self.expires,
         }
     }
 
@@ -35,7 +75,316 @@ impl Tile {
         &self.tile_id
     }
 
-    pub fn set_vector_data(&mut self, vector_data: mvt::Tile<mvt::FeatureWithCoordinates>) {
-        self.vector_data = vector_data;
+    // Cached tiles are shared between world copies.
+    pub fn set_tile_id(&mut self, tile_id: OverscaledTileId) {
+        self.tile_id = tile_id;
+    }
+
+    pub fn uid(&self) -> usize {
+        self.uid
+    }
+
+    pub fn state(&self) -> TileState {
+        self.state
+    }
+
+    // Whether the tile can be drawn, possibly with outdated data.
+    pub fn has_data(&self) -> bool {
+        matches!(
+            self.state,
+            TileState::Loaded | TileState::Expired | TileState::Reloading
+        )
+    }
+
+    pub fn set_reloading(&mut self) {
+        if self.has_data() {
+            self.state = TileState::Reloading;
+        }
+    }
+
+    // Back to the previous data after a failed reload.
+    pub fn cancel_reload(&mut self) {
+        if self.state == TileState::Reloading {
+            self.state = TileState::Loaded;
+            self.check_expiry(SystemTime::now());
+        }
+    }
+
+    // Approximate memory held by the tile's data. Data shared with other
+    // tiles is split between them, so it is only counted once in total.
+    pub fn memory_size(&self) -> usize {
+        let vector_size = self.vector_size / Arc::strong_count(&self.vector_data);
+        let raster_size = self.raster_data.as_ref().map_or(0, shared_image_size);
+        let dem_size = self.dem_data.as_ref().map_or(0, DEMData::memory_size);
+        let image_size = self
+            .image_data
+            .as_ref()
+            .map_or(0, |image_data| shared_image_size(&image_data.image));
+        vector_size + raster_size + dem_size + image_size
+    }
+
+    pub fn unload(&mut self) {
+        self.vector_data = Default::default();
+        self.vector_size = 0;
+        self.raster_data = None;
+        self.dem_data = None;
+        self.image_data = None;
+        self.state = TileState::Unloaded;
+    }
+
+    // `size` is the estimate of `vector_data_size`.
+    pub fn set_vector_data(
+        &mut self,
+        vector_data: mvt::Tile<mvt::FeatureWithCoordinates>,
+        size: usize,
+    ) {
+        self.vector_data = Arc::new(vector_data);
+        self.vector_size = size;
+        self.state = TileState::Loaded;
+    }
+
+    pub fn vector_data(&self) -> &mvt::Tile<mvt::FeatureWithCoordinates> {
+        &self.vector_data
+    }
+
+    // `None` is a tile without an image, e.g. outside of the data.
+    pub fn set_raster_data(&mut self, raster_data: Option<RgbaImage>) {
+        self.raster_data = raster_data.map(Arc::new);
+        self.state = TileState::Loaded;
+    }
+
+    pub fn raster_data(&self) -> Option<&RgbaImage> {
+        self.raster_data.as_deref()
+    }
+
+    pub fn set_dem_data(&mut self, dem_data: Option<DEMData>) {
+        self.dem_data = dem_data;
+        self.state = TileState::Loaded;
+    }
+
+    pub fn dem_data(&self) -> Option<&DEMData> {
+        self.dem_data.as_ref()
+    }
+
+    pub fn dem_data_mut(&mut self) -> Option<&mut DEMData> {
+        self.dem_data.as_mut()
+    }
+
+    pub fn set_image_data(&mut self, image_data: Option<ImageQuad>) {
+        self.image_data = image_data;
+        self.state = TileState::Loaded;
+    }
+
+    // Swaps the image of a video tile for its current frame.
+    pub fn set_image_frame(&mut self, frame: Arc<RgbaImage>) {
+        if let Some(image_data) = &mut self.image_data {
+            image_data.image = frame;
+        }
+    }
+
+    pub fn image_data(&self) -> Option<&ImageQuad> {
+        self.image_data.as_ref()
+    }
+
+    pub fn set_errored(&mut self) {
+        self.state = TileState::Errored;
+    }
+
+    pub fn is_errored(&self) -> bool {
+        matches!(self.state, TileState::Errored)
+    }
+
+    pub fn set_expires(&mut self, expires: Option<SystemTime>) {
+        self.expires = expires;
+        self.check_expiry(SystemTime::now());
+    }
+
+    pub fn expires(&self) -> Option<SystemTime> {
+        self.expires
+    }
+
+    // Expired tiles become fresh again when their expiry is pushed back.
+    pub fn check_expiry(&mut self, now: SystemTime) {
+        let expired = match self.expires {
+            Some(expires) => expires <= now,
+            None => false,
+        };
+        match self.state {
+            TileState::Loaded if expired => self.state = TileState::Expired,
+            TileState::Expired if !expired => self.state = TileState::Loaded,
+            _ => {}
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        matches!(self.state, TileState::Expired)
+    }
+}
+
+fn shared_image_size(image: &Arc<RgbaImage>) -> usize {
+    image.as_raw().len() / Arc::strong_count(image)
+}
+
+// Estimates the memory a vector tile takes once decoded from the features,
+// their points and their properties in the encoded data.
+pub(crate) fn vector_data_size(data: &[u8]) -> Result<usize> {
+    let mut size = 0;
+    let mut tile = Reader::new(data);
+    while let Some((field, layer, _)) = tile.field()? {
+        let layer = match (field, layer) {
+            (3, Field::Bytes(layer)) => layer,
+            _ => continue,
+        };
+
+        let mut features = Vec::new();
+        let mut keys = Vec::new();
+        let mut values = Vec::new();
+        let mut reader = Reader::new(layer);
+        while let Some((field, value, _)) = reader.field()? {
+            match (field, value) {
+                (2, Field::Bytes(feature)) => features.push(feature),
+                (3, Field::Bytes(key)) => keys.push(key.len()),
+                (4, Field::Bytes(value)) => values.push(value.len()),
+                _ => {}
+            }
+        }
+
+        for feature in features {
+            size += FEATURE_SIZE;
+            let mut reader = Reader::new(feature);
+            while let Some((field, value, _)) = reader.field()? {
+                match (field, value) {
+                    (2, Field::Bytes(tags)) => {
+                        let mut tags = Reader::new(tags);
+                        while !tags.is_empty() {
+                            let (key, value) = (tags.varint()?, tags.varint()?);
+                            size += PROPERTY_SIZE
+                                + keys.get(key as usize).unwrap_or(&0)
+                                + values.get(value as usize).unwrap_or(&0);
+                        }
+                    }
+                    (4, Field::Bytes(geometry)) => size += POINT_SIZE * point_count(geometry)?,
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(size)
+}
+
+// The same estimate for a tile sliced from GeoJSON, without encoding it.
+pub(crate) fn vector_tile_size(tile: &VectorTile) -> usize {
+    let mut size = 0;
+    for feature in &tile.features {
+        size += FEATURE_SIZE;
+        for (key, value) in &feature.properties {
+            if let Some(value) = encode_value(value) {
+                size += PROPERTY_SIZE + key.len() + value.len();
+            }
+        }
+        // Polygon rings are closed, by the last point or by ClosePath once
+        // encoded.
+        let points = feature.geometry.iter().map(Vec::len).sum::<usize>();
+        size += POINT_SIZE * points;
+    }
+    size
+}
+
+// Points of an encoded geometry, MoveTo and LineTo take two parameters per
+// point and ClosePath none.
+fn point_count(geometry: &[u8]) -> Result<usize> {
+    let mut count = 0;
+    let mut reader = Reader::new(geometry);
+    while !reader.is_empty() {
+        let command = reader.varint()?;
+        let points = (command >> 3) as usize;
+        if command & 0x7 != 7 {
+            for _ in 0..points * 2 {
+                reader.varint()?;
+            }
+        }
+        count += points;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::geojson_vt::{encode, FeatureType, TileFeature, VectorTile};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn tile_expiry() {
+        let now = SystemTime::now();
+        let mut tile = Tile::new(OverscaledTileId::new(1, 0, 1, 0, 0), 512);
+        tile.set_raster_data(Some(RgbaImage::new(1, 1)));
+        tile.set_expires(Some(now - Duration::from_secs(1)));
+        assert_eq!(tile.state(), TileState::Expired);
+
+        // Refreshing keeps the old data around.
+        tile.set_reloading();
+        assert_eq!(tile.state(), TileState::Reloading);
+        assert!(tile.has_data());
+        tile.cancel_reload();
+        assert_eq!(tile.state(), TileState::Expired);
+
+        tile.set_expires(Some(now + Duration::from_secs(60)));
+        assert_eq!(tile.state(), TileState::Loaded);
+        tile.check_expiry(now + Duration::from_secs(61));
+        assert!(tile.is_expired());
+    }
+
+    #[test]
+    fn vector_data_size_counts_decoded_features() {
+        let feature = TileFeature {
+            id: None,
+            kind: FeatureType::LineString,
+            geometry: vec![(0..100).map(|i| [i, i]).collect()],
+            properties: json!({ "name": "a" }).as_object().unwrap().clone(),
+        };
+        let data = encode(&VectorTile::new(vec![feature]), 4096);
+        let size = vector_data_size(&data).unwrap();
+        assert!(size >= FEATURE_SIZE + 100 * POINT_SIZE + PROPERTY_SIZE);
+        assert!(size > data.len());
+        assert_eq!(vector_data_size(&[]).unwrap(), 0);
+    }
+
+    #[test]
+    fn vector_tile_size_matches_encoded_size() {
+        let properties = json!({ "name": "a", "rank": 3, "nested": { "a": 1 }, "none": null });
+        let feature = |kind, geometry| TileFeature {
+            id: Some(json!(1)),
+            kind,
+            geometry,
+            properties: properties.as_object().unwrap().clone(),
+        };
+        let tile = VectorTile::new(vec![
+            feature(FeatureType::Point, vec![vec![[1, 1], [5, 5]]]),
+            feature(
+                FeatureType::LineString,
+                vec![(0..100).map(|i| [i, i]).collect()],
+            ),
+            feature(
+                FeatureType::Polygon,
+                vec![vec![[0, 0], [10, 0], [10, 10], [0, 0]]],
+            ),
+        ]);
+        let data = encode(&tile, 4096);
+        assert_eq!(vector_tile_size(&tile), vector_data_size(&data).unwrap());
+    }
+
+    #[test]
+    fn tile_memory_size_counts_shared_data_once() {
+        let mut tile = Tile::new(OverscaledTileId::new(1, 0, 1, 0, 0), 512);
+        tile.set_raster_data(Some(RgbaImage::new(4, 4)));
+        tile.set_vector_data(Default::default(), 100);
+        assert_eq!(tile.memory_size(), 164);
+
+        let overscaled = tile.overscaled(OverscaledTileId::new(2, 0, 1, 0, 0), 1024);
+        assert_eq!(tile.memory_size() + overscaled.memory_size(), 164);
+        drop(tile);
+        assert_eq!(overscaled.memory_size(), 164);
     }
 }
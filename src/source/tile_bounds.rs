@@ -1,29 +1,105 @@
 use super::tile_id::CanonicalTileId;
-use crate::geo::{mercator_x_from_lng, mercator_y_from_lat, LngLatBounds};
+use crate::geo::{mercator_x_from_lng, mercator_y_from_lat};
 
+const MAX_LATITUDE: f64 = 85.051129;
+
+// Area covered by a tiled source, in projected 0..1 world units. Bounds
+// crossing the antimeridian end past 1 on the east.
 #[derive(Debug)]
 pub(crate) struct TileBounds {
-    bounds: LngLatBounds<f64>,
+    min_x: f64,
+    min_y: f64,
+    max_x: f64,
+    max_y: f64,
 }
 
 impl TileBounds {
+    // `bounds` is [west, south, east, north]. A west bound greater than the
+    // east one, or an east bound past 180, crosses the antimeridian.
     pub fn new(bounds: &[f64; 4]) -> Self {
+        let [mut west, south, mut east, north] = *bounds;
+        if east < west {
+            east += 360.0;
+        }
+        if east - west >= 360.0 {
+            west = -180.0;
+            east = 180.0;
+        } else if !(-180.0..180.0).contains(&west) {
+            let width = east - west;
+            west = (west + 180.0).rem_euclid(360.0) - 180.0;
+            east = west + width;
+        }
+
+        let south = south.clamp(-MAX_LATITUDE, MAX_LATITUDE);
+        let north = north.clamp(-MAX_LATITUDE, MAX_LATITUDE);
         Self {
-            bounds: LngLatBounds::convert(&[
-                bounds[0].max(180.0),
-                bounds[1].max(-90.0),
-                bounds[2].min(180.0),
-                bounds[3].min(90.0),
-            ]),
+            min_x: mercator_x_from_lng(west),
+            min_y: mercator_y_from_lat(north.max(south)),
+            max_x: mercator_x_from_lng(east),
+            max_y: mercator_y_from_lat(south.min(north)),
         }
     }
 
     pub fn contains(&self, tile_id: &CanonicalTileId) -> bool {
-        let world_size = 2.0f64.powf(tile_id.z as f64);
-        let min_x = (mercator_x_from_lng(self.bounds.get_west()) * world_size).floor() as u32;
-        let min_y = (mercator_y_from_lat(self.bounds.get_north()) * world_size).floor() as u32;
-        let max_x = (mercator_x_from_lng(self.bounds.get_east()) * world_size).ceil() as u32;
-        let max_y = (mercator_y_from_lat(self.bounds.get_south()) * world_size).ceil() as u32;
-        tile_id.x >= min_x && tile_id.x < max_x && tile_id.y >= min_y && tile_id.y < max_y
+        let world_size = f64::from(1u32 << tile_id.z);
+        let min_x = (self.min_x * world_size).floor();
+        let max_x = (self.max_x * world_size).ceil();
+        let min_y = (self.min_y * world_size).floor();
+        let max_y = (self.max_y * world_size).ceil();
+
+        let (x, y) = (f64::from(tile_id.x), f64::from(tile_id.y));
+        // Tiles east of the antimeridian are the ones of the next world copy.
+        let contains_x = |x: f64| x >= min_x && x < max_x;
+        (contains_x(x) || contains_x(x + world_size)) && y >= min_y && y < max_y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(bounds: &TileBounds, z: u32, x: u32, y: u32) -> bool {
+        bounds.contains(&CanonicalTileId::new(z, x, y))
+    }
+
+    #[test]
+    fn tile_bounds_regional() {
+        // Roughly Germany.
+        let bounds = TileBounds::new(&[5.87, 47.27, 15.04, 55.06]);
+        assert!(contains(&bounds, 0, 0, 0));
+        assert!(contains(&bounds, 5, 16, 10));
+        assert!(contains(&bounds, 5, 17, 10));
+        assert!(!contains(&bounds, 5, 15, 10));
+        assert!(!contains(&bounds, 5, 18, 10));
+        assert!(!contains(&bounds, 5, 16, 12));
+        assert!(contains(&bounds, 10, 549, 335));
+        assert!(!contains(&bounds, 10, 500, 335));
+    }
+
+    #[test]
+    fn tile_bounds_world() {
+        let bounds = TileBounds::new(&[-180.0, -90.0, 180.0, 90.0]);
+        assert!(contains(&bounds, 2, 0, 0));
+        assert!(contains(&bounds, 2, 3, 3));
+        assert!(contains(&bounds, 14, 16383, 16383));
+    }
+
+    #[test]
+    fn tile_bounds_across_antimeridian() {
+        // Fiji, given either with west > east or with east past 180.
+        for bounds in &[[176.0, -21.0, -178.0, -12.0], [176.0, -21.0, 182.0, -12.0]] {
+            let bounds = TileBounds::new(bounds);
+            assert!(contains(&bounds, 4, 15, 8));
+            assert!(contains(&bounds, 4, 0, 8));
+            assert!(!contains(&bounds, 4, 1, 8));
+            assert!(!contains(&bounds, 4, 14, 8));
+            assert!(!contains(&bounds, 4, 15, 7));
+        }
+
+        // Bounds past the world wrap around it.
+        let bounds = TileBounds::new(&[-190.0, -10.0, -170.0, 10.0]);
+        assert!(contains(&bounds, 4, 15, 7));
+        assert!(contains(&bounds, 4, 0, 7));
+        assert!(!contains(&bounds, 4, 8, 7));
     }
 }
@@ -1,64 +1,292 @@
 use super::tile::Tile;
 use super::tile_id::OverscaledTileId;
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TileCacheStats {
+    pub tiles: usize,
+    pub bytes: u64,
+    pub max_tiles: usize,
+    pub max_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+struct Entry {
+    key: String,
+    source: String,
+    tile: Tile,
+    size: u64,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+// Recently used tiles of all sources, evicted least recently used first once
+// there are too many or they take up more memory than the budget. Entries
+// form a linked list in a slab, so every operation but `retain` is O(1).
 pub(crate) struct TileCache {
-    max: u32,
-    data: HashMap<String, Tile>,
-    order: VecDeque<String>,
+    max_tiles: usize,
+    max_bytes: u64,
+    index: HashMap<String, usize>,
+    entries: Vec<Option<Entry>>,
+    free: Vec<usize>,
+    // Least and most recently added.
+    head: Option<usize>,
+    tail: Option<usize>,
+    bytes: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
 }
 
 impl TileCache {
-    pub fn new(max: u32) -> Self {
+    pub fn new(max_bytes: u64) -> Self {
         Self {
-            max,
-            data: HashMap::new(),
-            order: VecDeque::new(),
+            max_tiles: 0,
+            max_bytes,
+            index: HashMap::new(),
+            entries: Vec::new(),
+            free: Vec::new(),
+            head: None,
+            tail: None,
+            bytes: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
         }
     }
 
-    pub fn reset(&mut self) {
-        self.data.clear();
-        self.order.clear();
+    pub fn max_tiles(&self) -> usize {
+        self.max_tiles
     }
 
-    pub fn add(&mut self, tile_id: &OverscaledTileId, data: Tile) {
-        let key = tile_id.wrapped().key();
+    pub fn set_max_tiles(&mut self, max_tiles: usize) {
+        self.max_tiles = max_tiles;
+        self.evict();
+    }
 
-        self.data.insert(key.clone(), data);
-        self.order.push_back(key);
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    pub fn set_max_bytes(&mut self, max_bytes: u64) {
+        self.max_bytes = max_bytes;
+        self.evict();
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
 
-        if self.order.len() > self.max as usize {
-            if let Some(front) = self.order.pop_front() {
-                self.get_and_remove_by_key(&front);
+    pub fn stats(&self) -> TileCacheStats {
+        TileCacheStats {
+            tiles: self.len(),
+            bytes: self.bytes,
+            max_tiles: self.max_tiles,
+            max_bytes: self.max_bytes,
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.index.clear();
+        self.entries.clear();
+        self.free.clear();
+        self.head = None;
+        self.tail = None;
+        self.bytes = 0;
+    }
+
+    pub fn add(&mut self, source: &str, tile: Tile) {
+        let key = cache_key(source, tile.tile_id());
+        if let Some(index) = self.index.get(&key).copied() {
+            self.remove_entry(index);
+        }
+
+        let size = tile.memory_size() as u64;
+        let entry = Entry {
+            key: key.clone(),
+            source: source.to_owned(),
+            tile,
+            size,
+            prev: self.tail,
+            next: None,
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.entries[index] = Some(entry);
+                index
+            }
+            None => {
+                self.entries.push(Some(entry));
+                self.entries.len() - 1
             }
+        };
+        match self.tail {
+            Some(tail) => self.entry_mut(tail).next = Some(index),
+            None => self.head = Some(index),
         }
+        self.tail = Some(index);
+        self.index.insert(key, index);
+        self.bytes += size;
+        self.evict();
+    }
+
+    // Removes the tile for reuse, whichever world copy it was cached for.
+    pub fn take(&mut self, source: &str, tile_id: &OverscaledTileId) -> Option<Tile> {
+        let index = match self.index.get(&cache_key(source, tile_id)) {
+            Some(index) => *index,
+            None => {
+                self.misses += 1;
+                return None;
+            }
+        };
+        self.hits += 1;
+        let mut tile = self.remove_entry(index);
+        tile.set_tile_id(tile_id.clone());
+        Some(tile)
     }
 
-    fn get_and_remove_by_key(&mut self, key: &str) {
-        self.data.remove(key);
-        if let Some(pos) = self.order.iter().position(|x| *x == key) {
-            self.order.remove(pos);
+    pub fn has(&self, source: &str, tile_id: &OverscaledTileId) -> bool {
+        self.index.contains_key(&cache_key(source, tile_id))
+    }
+
+    pub fn get(&self, source: &str, tile_id: &OverscaledTileId) -> Option<&Tile> {
+        let index = self.index.get(&cache_key(source, tile_id))?;
+        self.entries[*index].as_ref().map(|entry| &entry.tile)
+    }
+
+    pub fn retain<F>(&mut self, source: &str, mut f: F)
+    where
+        F: FnMut(&Tile) -> bool,
+    {
+        let removed = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| match entry {
+                Some(entry) if entry.source == source && !f(&entry.tile) => Some(index),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for index in removed {
+            self.remove_entry(index);
         }
     }
 
-    pub fn has(&self, tile_id: &OverscaledTileId) -> bool {
-        let key = tile_id.wrapped().key();
-        self.data.contains_key(&key)
+    fn evict(&mut self) {
+        while self.len() > self.max_tiles || self.bytes > self.max_bytes {
+            match self.head {
+                Some(head) => {
+                    self.remove_entry(head);
+                    self.evictions += 1;
+                }
+                None => break,
+            }
+        }
     }
 
-    pub fn remove(&mut self, tile_id: &OverscaledTileId) {
-        if self.has(tile_id) {
-            self.get_and_remove_by_key(&tile_id.wrapped().key())
+    fn remove_entry(&mut self, index: usize) -> Tile {
+        let entry = self.entries[index].take().unwrap();
+        match entry.prev {
+            Some(prev) => self.entry_mut(prev).next = entry.next,
+            None => self.head = entry.next,
+        }
+        match entry.next {
+            Some(next) => self.entry_mut(next).prev = entry.prev,
+            None => self.tail = entry.prev,
         }
+        self.free.push(index);
+        self.index.remove(&entry.key);
+        self.bytes -= entry.size;
+        entry.tile
     }
 
-    pub fn get_by_key(&self, key: &str) -> Option<&Tile> {
-        self.data.get(key)
+    fn entry_mut(&mut self, index: usize) -> &mut Entry {
+        self.entries[index].as_mut().unwrap()
+    }
+}
+
+fn cache_key(source: &str, tile_id: &OverscaledTileId) -> String {
+    format!("{}/{}", source, tile_id.wrapped().key())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(x: u32) -> Tile {
+        Tile::new(OverscaledTileId::new(2, 0, 2, x, 0), 512)
     }
 
-    pub fn get(&self, tile_id: &OverscaledTileId) -> Option<&Tile> {
-        let key = tile_id.wrapped().key();
-        self.data.get(&key)
+    fn raster_tile(x: u32, size: u32) -> Tile {
+        let mut tile = tile(x);
+        tile.set_raster_data(Some(image::RgbaImage::new(size, size)));
+        tile
+    }
+
+    #[test]
+    fn tile_cache_evicts_least_recently_added() {
+        let mut cache = TileCache::new(u64::MAX);
+        cache.set_max_tiles(2);
+        cache.add("a", tile(0));
+        cache.add("a", tile(1));
+        cache.add("a", tile(0));
+        cache.add("a", tile(2));
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.has("a", tile(1).tile_id()));
+        assert!(cache.has("a", tile(0).tile_id()));
+
+        cache.set_max_tiles(1);
+        assert!(!cache.has("a", tile(0).tile_id()));
+        assert!(cache.has("a", tile(2).tile_id()));
+        assert_eq!(cache.stats().evictions, 2);
+    }
+
+    #[test]
+    fn tile_cache_take_from_other_world() {
+        let mut cache = TileCache::new(u64::MAX);
+        cache.set_max_tiles(2);
+        cache.add("a", Tile::new(OverscaledTileId::new(2, 1, 2, 3, 0), 512));
+
+        let tile_id = OverscaledTileId::new(2, -1, 2, 3, 0);
+        assert!(cache.take("b", &tile_id).is_none());
+        let tile = cache.take("a", &tile_id).unwrap();
+        assert_eq!(tile.tile_id(), &tile_id);
+        assert!(cache.is_empty());
+        assert!(cache.take("a", &tile_id).is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+    }
+
+    #[test]
+    fn tile_cache_memory_budget() {
+        // Each tile takes 16 * 16 * 4 bytes.
+        let mut cache = TileCache::new(3 * 1024);
+        cache.set_max_tiles(10);
+        cache.add("a", raster_tile(0, 16));
+        cache.add("b", raster_tile(0, 16));
+        cache.add("a", raster_tile(1, 16));
+        assert_eq!(cache.stats().bytes, 3 * 1024);
+
+        // Budget is shared, so the oldest tile of any source goes first.
+        cache.add("b", raster_tile(1, 16));
+        assert!(!cache.has("a", tile(0).tile_id()));
+        assert!(cache.has("b", tile(0).tile_id()));
+
+        cache.retain("b", |_| false);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.stats().bytes, 1024);
+
+        cache.set_max_bytes(0);
+        assert!(cache.is_empty());
     }
 }
@@ -102,7 +102,6 @@ impl OverscaledTileId {
 
     pub fn scaled_to(&self, target_z: u32) -> OverscaledTileId {
         assert!(target_z <= self.overscaled_z);
-        let z_diff = self.canonical.z - target_z;
         if target_z > self.canonical.z {
             OverscaledTileId::new(
                 target_z,
@@ -112,6 +111,7 @@ impl OverscaledTileId {
                 self.canonical.y,
             )
         } else {
+            let z_diff = self.canonical.z - target_z;
             OverscaledTileId::new(
                 target_z,
                 self.wrap,
@@ -122,6 +122,10 @@ impl OverscaledTileId {
         }
     }
 
+    pub fn is_descendant_of(&self, parent: &OverscaledTileId) -> bool {
+        parent.overscaled_z < self.overscaled_z && self.scaled_to(parent.overscaled_z) == *parent
+    }
+
     pub fn children(&self, source_max_zoom: u32) -> Vec<OverscaledTileId> {
         if self.overscaled_z >= source_max_zoom {
             return vec![OverscaledTileId::new(
@@ -145,6 +149,25 @@ impl OverscaledTileId {
         ]
     }
 
+    // The tile next to this one at the same zoom, crossing into the
+    // neighbouring world copy at the antimeridian.
+    pub fn neighbor(&self, dx: i32, dy: i32) -> Option<OverscaledTileId> {
+        let dim = 1i64 << self.canonical.z;
+        let y = i64::from(self.canonical.y) + i64::from(dy);
+        if y < 0 || y >= dim {
+            return None;
+        }
+        let x = i64::from(self.canonical.x) + i64::from(dx);
+        let wrap = self.wrap + x.div_euclid(dim) as i32;
+        Some(OverscaledTileId::new(
+            self.overscaled_z,
+            wrap,
+            self.canonical.z,
+            x.rem_euclid(dim) as u32,
+            y as u32,
+        ))
+    }
+
     pub fn wrapped(&self) -> OverscaledTileId {
         OverscaledTileId::new(
             self.overscaled_z,
@@ -159,6 +182,10 @@ impl OverscaledTileId {
         self.key.clone()
     }
 
+    pub fn overscaled_z(&self) -> u32 {
+        self.overscaled_z
+    }
+
     pub fn canonical(&self) -> &CanonicalTileId {
         &self.canonical
     }
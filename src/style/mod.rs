@@ -1,42 +1,190 @@
+use crate::event::{MapEvent, RedrawCallback};
 use crate::geo::Transform;
 use crate::network::NetworkManager;
-use crate::source::SourceCache;
+use crate::source::{Bounds, CustomSource, GeoJSON, SourceCache, TileCache, TileCacheStats, Video};
 use crate::style_spec;
-use eyre::Result;
+use crate::worker::WorkerPool;
+use eyre::{eyre, Result};
+use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
 
 pub(crate) struct Style {
     _style: style_spec::Style,
     _nm: Arc<NetworkManager>,
+    workers: Arc<WorkerPool>,
+    redraw_callback: Option<RedrawCallback>,
     sources: HashMap<String, SourceCache>,
+    tile_cache: TileCache,
+    events: Vec<MapEvent>,
 }
 
 impl Style {
-    pub async fn new(uri: &str, nm: Arc<NetworkManager>) -> Result<Self> {
+    pub async fn new(
+        uri: &str,
+        nm: Arc<NetworkManager>,
+        workers: Arc<WorkerPool>,
+        max_tile_cache_size: u64,
+        redraw_callback: Option<RedrawCallback>,
+    ) -> Result<Self> {
         let style_str = nm.load_style(uri).await?;
         let style = serde_json::from_str::<style_spec::Style>(&style_str)?;
 
         let mut sources = HashMap::new();
+        let mut events = Vec::new();
         for (name, source) in &style.sources {
             sources.insert(
                 name.clone(),
-                SourceCache::new(nm.clone(), name, source).await?,
+                SourceCache::new(
+                    nm.clone(),
+                    workers.clone(),
+                    name,
+                    source,
+                    redraw_callback.clone(),
+                )
+                .await?,
             );
+            events.push(MapEvent::SourceLoaded {
+                source: name.clone(),
+            });
         }
 
         Ok(Self {
             _style: style,
             _nm: nm,
+            workers,
+            redraw_callback,
             sources,
+            tile_cache: TileCache::new(max_tile_cache_size),
+            events,
         })
     }
 
+    pub async fn add_custom_source(
+        &mut self,
+        id: &str,
+        source: Arc<dyn CustomSource>,
+    ) -> Result<()> {
+        if self.sources.contains_key(id) {
+            return Err(eyre!("Source \"{}\" already exists", id));
+        }
+        let source = SourceCache::custom(
+            self.workers.clone(),
+            id,
+            source,
+            self.redraw_callback.clone(),
+        )
+        .await?;
+        self.sources.insert(id.to_owned(), source);
+        self.events.push(MapEvent::SourceLoaded {
+            source: id.to_owned(),
+        });
+        Ok(())
+    }
+
+    pub fn geojson_source(&self, id: &str) -> Result<&GeoJSON> {
+        let source = self
+            .sources
+            .get(id)
+            .ok_or_else(|| eyre!("Source \"{}\" not found", id))?;
+        source
+            .geojson()
+            .ok_or_else(|| eyre!("Source \"{}\" is not a GeoJSON source", id))
+    }
+
+    pub async fn set_geojson_data(&mut self, id: &str, data: Value) -> Result<()> {
+        let bounds = self.geojson_source(id)?.set_data(data).await?;
+        self.invalidate_tiles(id, &bounds);
+        Ok(())
+    }
+
+    pub fn add_geojson_features(&mut self, id: &str, features: Vec<Value>) -> Result<()> {
+        let bounds = self.geojson_source(id)?.add_features(features)?;
+        self.invalidate_tiles(id, &bounds);
+        Ok(())
+    }
+
+    pub fn update_geojson_features(&mut self, id: &str, features: Vec<Value>) -> Result<()> {
+        let bounds = self.geojson_source(id)?.update_features(features)?;
+        self.invalidate_tiles(id, &bounds);
+        Ok(())
+    }
+
+    pub fn remove_geojson_features(&mut self, id: &str, ids: &[Value]) -> Result<()> {
+        let bounds = self.geojson_source(id)?.remove_features(ids)?;
+        self.invalidate_tiles(id, &bounds);
+        Ok(())
+    }
+
+    pub fn video_source(&self, id: &str) -> Result<&Video> {
+        let source = self
+            .sources
+            .get(id)
+            .ok_or_else(|| eyre!("Source \"{}\" not found", id))?;
+        source
+            .video()
+            .ok_or_else(|| eyre!("Source \"{}\" is not a video source", id))
+    }
+
+    pub async fn update_image(
+        &mut self,
+        id: &str,
+        url: &str,
+        coordinates: &[[f64; 2]; 4],
+    ) -> Result<()> {
+        let image = self
+            .sources
+            .get(id)
+            .ok_or_else(|| eyre!("Source \"{}\" not found", id))?
+            .image()
+            .ok_or_else(|| eyre!("Source \"{}\" is not an image source", id))?;
+        let bounds = image.update_image(url, coordinates).await?;
+        self.invalidate_tiles(id, &bounds);
+        Ok(())
+    }
+
+    fn invalidate_tiles(&mut self, id: &str, bounds: &[Bounds]) {
+        if let Some(source) = self.sources.get_mut(id) {
+            let buffer = source
+                .geojson()
+                .map(GeoJSON::tile_buffer)
+                .unwrap_or_default();
+            source.invalidate(bounds, buffer, &mut self.tile_cache);
+        }
+    }
+
     pub async fn update_sources(&mut self, transform: &Transform) -> Result<()> {
+        let max_tiles = self
+            .sources
+            .values()
+            .map(|source| source.cache_size(transform))
+            .sum();
+        if self.tile_cache.max_tiles() != max_tiles {
+            self.tile_cache.set_max_tiles(max_tiles);
+        }
+
         for source in self.sources.values_mut() {
-            source.update(transform).await?;
+            source.update(transform, &mut self.tile_cache).await?;
         }
 
         Ok(())
     }
+
+    // Whether every source has the data for the viewport it was last updated
+    // for.
+    pub fn is_loaded(&self) -> bool {
+        self.sources.values().all(SourceCache::is_loaded)
+    }
+
+    pub fn take_events(&mut self) -> Vec<MapEvent> {
+        let mut events = std::mem::take(&mut self.events);
+        for source in self.sources.values_mut() {
+            events.extend(source.take_events());
+        }
+        events
+    }
+
+    pub fn tile_cache_stats(&self) -> TileCacheStats {
+        self.tile_cache.stats()
+    }
 }
@@ -6,6 +6,7 @@ pub(crate) mod util;
 
 pub(crate) use expression::Expression;
 pub(crate) use layer::Layer;
+pub(crate) use source::Encoding;
 pub(crate) use source::GeoJSON;
 pub(crate) use source::Image;
 pub(crate) use source::Raster;
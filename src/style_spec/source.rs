@@ -18,7 +18,7 @@ pub(crate) enum Source {
     Image(Image),
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub(crate) enum Scheme {
     #[serde(rename = "xyz")]
     XYZ,
@@ -29,47 +29,23 @@ pub(crate) enum Scheme {
 #[derive(Deserialize, Debug, Clone)]
 pub(crate) struct Vector {
     pub attribution: Option<String>,
-    #[serde(default = "default_bounds")]
-    pub bounds: Vec<f64>,
-    #[serde(default = "default_maxzoom")]
-    pub maxzoom: f32,
-    #[serde(default = "default_minzoom")]
-    pub minzoom: f32,
+    pub bounds: Option<Vec<f64>>,
+    pub maxzoom: Option<f32>,
+    pub minzoom: Option<f32>,
     #[serde(rename = "promoteId")]
     pub promote_id: Option<Value>,
-    #[serde(default = "default_scheme")]
-    pub scheme: Scheme,
+    pub scheme: Option<Scheme>,
     pub tiles: Option<Vec<String>>,
     pub url: Option<String>,
 }
 
-fn default_bounds() -> Vec<f64> {
-    vec![-180.0, -85.051129, 180.0, 85.051129]
-}
-
-fn default_maxzoom() -> f32 {
-    22.0
-}
-
-fn default_minzoom() -> f32 {
-    0.0
-}
-
-fn default_scheme() -> Scheme {
-    Scheme::XYZ
-}
-
 #[derive(Deserialize, Debug, Clone)]
 pub(crate) struct Raster {
     pub attribution: Option<String>,
-    #[serde(default = "default_bounds")]
-    pub bounds: Vec<f64>,
-    #[serde(default = "default_maxzoom")]
-    pub maxzoom: f32,
-    #[serde(default = "default_minzoom")]
-    pub minzoom: f32,
-    #[serde(default = "default_scheme")]
-    pub scheme: Scheme,
+    pub bounds: Option<Vec<f64>>,
+    pub maxzoom: Option<f32>,
+    pub minzoom: Option<f32>,
+    pub scheme: Option<Scheme>,
     #[serde(rename = "tileSize", default = "default_tile_size")]
     pub tile_size: f32,
     pub tiles: Option<Vec<String>>,
@@ -83,14 +59,11 @@ fn default_tile_size() -> f32 {
 #[derive(Deserialize, Debug, Clone)]
 pub(crate) struct RasterDEM {
     pub attribution: Option<String>,
-    #[serde(default = "default_bounds")]
-    pub bounds: Vec<f64>,
+    pub bounds: Option<Vec<f64>>,
     #[serde(default = "default_encoding")]
     pub encoding: Encoding,
-    #[serde(default = "default_maxzoom")]
-    pub maxzoom: f32,
-    #[serde(default = "default_minzoom")]
-    pub minzoom: f32,
+    pub maxzoom: Option<f32>,
+    pub minzoom: Option<f32>,
     #[serde(rename = "tileSize", default = "default_tile_size")]
     pub tile_size: f32,
     pub tiles: Option<Vec<String>>,
@@ -118,8 +91,12 @@ pub(crate) struct GeoJSON {
     pub cluster: bool,
     #[serde(rename = "clusterMaxZoom")]
     pub cluster_max_zoom: Option<f32>,
+    #[serde(rename = "clusterMinPoints", default = "default_cluster_min_points")]
+    pub cluster_min_points: usize,
     #[serde(rename = "clusterProperties")]
     pub cluster_properties: Option<Value>,
+    #[serde(rename = "clusterRadius", default = "default_cluster_radius")]
+    pub cluster_radius: f32,
     pub data: Option<Value>,
     #[serde(rename = "generateId", default = "default_generate_id")]
     pub generate_id: bool,
@@ -141,6 +118,14 @@ fn default_cluster() -> bool {
     false
 }
 
+fn default_cluster_min_points() -> usize {
+    2
+}
+
+fn default_cluster_radius() -> f32 {
+    50.0
+}
+
 fn default_generate_id() -> bool {
     false
 }
@@ -159,12 +144,12 @@ fn default_tolerance() -> f32 {
 
 #[derive(Deserialize, Debug, Clone)]
 pub(crate) struct Image {
-    url: String,
-    coordinates: Vec<Vec<f32>>,
+    pub url: String,
+    pub coordinates: Vec<Vec<f32>>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub(crate) struct Video {
-    urls: Vec<String>,
-    coordinates: Vec<Vec<f32>>,
+    pub urls: Vec<String>,
+    pub coordinates: Vec<Vec<f32>>,
 }
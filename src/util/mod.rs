@@ -1,5 +1,6 @@
 mod aabb;
 mod frustum;
+mod pbf;
 
 use num::traits::Float;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -19,5 +20,13 @@ pub(crate) fn unique_id() -> usize {
     COUNTER.fetch_add(1, Ordering::Relaxed)
 }
 
+// 64-bit FNV-1a, for hashes that have to stay the same between runs.
+pub(crate) fn fnv1a<'a>(data: impl IntoIterator<Item = &'a u8>) -> u64 {
+    data.into_iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
 pub(crate) use aabb::*;
 pub(crate) use frustum::*;
+pub(crate) use pbf::*;
@@ -0,0 +1,128 @@
+use eyre::{eyre, Result};
+
+// Protocol buffer wire types, as used by vector tiles.
+pub(crate) const WIRE_VARINT: u32 = 0;
+pub(crate) const WIRE_FIXED64: u32 = 1;
+pub(crate) const WIRE_BYTES: u32 = 2;
+pub(crate) const WIRE_FIXED32: u32 = 5;
+
+pub(crate) enum Field<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| eyre!("Unexpected end of vector tile"))?;
+            self.pos += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        Err(eyre!("Invalid varint in vector tile"))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .ok_or_else(|| eyre!("Invalid length in vector tile"))?;
+        let bytes = self
+            .data
+            .get(self.pos..end)
+            .ok_or_else(|| eyre!("Unexpected end of vector tile"))?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    // The field number, its value and the encoded field for copying it.
+    pub fn field(&mut self) -> Result<Option<(u32, Field<'a>, &'a [u8])>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let start = self.pos;
+        let tag = self.varint()?;
+        let value = match (tag & 0x7) as u32 {
+            WIRE_VARINT => Field::Varint(self.varint()?),
+            WIRE_FIXED64 => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.take(8)?);
+                Field::Fixed64(u64::from_le_bytes(bytes))
+            }
+            WIRE_BYTES => {
+                let len = self.varint()? as usize;
+                Field::Bytes(self.take(len)?)
+            }
+            WIRE_FIXED32 => {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(self.take(4)?);
+                Field::Fixed32(u32::from_le_bytes(bytes))
+            }
+            wire_type => return Err(eyre!("Unknown wire type {} in vector tile", wire_type)),
+        };
+        Ok(Some((
+            (tag >> 3) as u32,
+            value,
+            &self.data[start..self.pos],
+        )))
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Writer {
+    pub data: Vec<u8>,
+}
+
+impl Writer {
+    pub fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.data.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        self.data.push(value as u8);
+    }
+
+    pub fn tag(&mut self, field: u32, wire_type: u32) {
+        self.varint(u64::from(field << 3 | wire_type));
+    }
+
+    pub fn varint_field(&mut self, field: u32, value: u64) {
+        self.tag(field, WIRE_VARINT);
+        self.varint(value);
+    }
+
+    pub fn bytes_field(&mut self, field: u32, bytes: &[u8]) {
+        self.tag(field, WIRE_BYTES);
+        self.varint(bytes.len() as u64);
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn packed_field(&mut self, field: u32, values: &[u32]) {
+        let mut packed = Writer::default();
+        for value in values {
+            packed.varint(u64::from(*value));
+        }
+        self.bytes_field(field, &packed.data);
+    }
+}
@@ -0,0 +1,64 @@
+use crate::source::{
+    encode_features, geojson_tile, promote_ids, vector_data_size, CanonicalTileId, DEMData,
+    GeoJSONIndex, PromoteId,
+};
+use crate::style_spec::Encoding;
+use eyre::Result;
+use mvt::FeatureWithCoordinates;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+// Work sent to the worker threads, which own everything they need.
+#[derive(Debug)]
+pub(crate) enum WorkerRequest {
+    ParseVector(Vec<u8>, Option<PromoteId>),
+    // The index is shared with its source, which updates it in place.
+    GeoJSONTile(Arc<Mutex<GeoJSONIndex>>, CanonicalTileId),
+    // Features of a custom source with its tile size and max zoom.
+    CustomFeatures(Vec<Value>, CanonicalTileId, u32, f32),
+    DecodeRaster(Vec<u8>),
+    DecodeDEM(Vec<u8>, Encoding),
+}
+
+#[derive(Debug)]
+pub(crate) enum WorkerResponse {
+    // The tile and the estimated size of its decoded data.
+    Vector(mvt::Tile<FeatureWithCoordinates>, usize),
+    Raster(image::RgbaImage),
+    DEMData(DEMData),
+}
+
+impl WorkerRequest {
+    pub fn process(self) -> Result<WorkerResponse> {
+        Ok(match self {
+            WorkerRequest::ParseVector(data, None) => parse_vector(&data)?,
+            WorkerRequest::ParseVector(data, Some(promote_id)) => {
+                parse_vector(&promote_ids(&data, &promote_id)?)?
+            }
+            WorkerRequest::GeoJSONTile(index, tile_id) => {
+                let (vector_data, size) = geojson_tile(&index, &tile_id)?;
+                WorkerResponse::Vector(vector_data, size)
+            }
+            WorkerRequest::CustomFeatures(features, tile_id, tile_size, max_zoom) => {
+                match encode_features(features, &tile_id, tile_size, max_zoom)? {
+                    Some(data) => parse_vector(&data)?,
+                    None => WorkerResponse::Vector(Default::default(), 0),
+                }
+            }
+            WorkerRequest::DecodeRaster(data) => {
+                WorkerResponse::Raster(image::load_from_memory(&data)?.to_rgba8())
+            }
+            WorkerRequest::DecodeDEM(data, encoding) => {
+                let image = image::load_from_memory(&data)?.to_rgba8();
+                WorkerResponse::DEMData(DEMData::new(&image, &encoding)?)
+            }
+        })
+    }
+}
+
+fn parse_vector(data: &[u8]) -> Result<WorkerResponse> {
+    Ok(WorkerResponse::Vector(
+        mvt::decode(data)?,
+        vector_data_size(data)?,
+    ))
+}
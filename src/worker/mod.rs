@@ -0,0 +1,5 @@
+mod message;
+mod worker_pool;
+
+pub(crate) use message::{WorkerRequest, WorkerResponse};
+pub(crate) use worker_pool::WorkerPool;
@@ -0,0 +1,185 @@
+use super::{WorkerRequest, WorkerResponse};
+use crate::source::{CanonicalTileId, DEMData, GeoJSONIndex, PromoteId};
+use crate::style_spec::Encoding;
+use eyre::{eyre, Result};
+use futures::channel::oneshot;
+use mvt::FeatureWithCoordinates;
+use serde_json::Value;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+const MAX_WORKERS: usize = 6;
+
+type Job = (WorkerRequest, oneshot::Sender<Result<WorkerResponse>>);
+
+// Threads parsing tile data, so decoding doesn't hold up rendering. Requests
+// are queued and picked up by the first idle worker.
+#[derive(Debug)]
+pub(crate) struct WorkerPool {
+    sender: Mutex<Option<Sender<Job>>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub fn new(count: usize) -> Result<Self> {
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..count.max(1))
+            .map(|i| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("worker-{}", i))
+                    .spawn(move || run(&receiver))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            sender: Mutex::new(Some(sender)),
+            workers,
+        })
+    }
+
+    // Half of the cores, leaving the rest for rendering and networking.
+    pub fn default_count() -> usize {
+        let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+        (cores / 2).clamp(1, MAX_WORKERS)
+    }
+
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+
+    pub async fn send(&self, request: WorkerRequest) -> Result<WorkerResponse> {
+        let (reply, response) = oneshot::channel();
+        match &*self.sender.lock().unwrap() {
+            Some(sender) => sender
+                .send((request, reply))
+                .map_err(|_| eyre!("Workers have stopped"))?,
+            None => return Err(eyre!("Workers have stopped")),
+        }
+        response
+            .await
+            .map_err(|_| eyre!("Worker stopped before replying"))?
+    }
+
+    // The tile and the estimated size of its decoded data.
+    pub async fn parse_vector_tile(
+        &self,
+        data: Vec<u8>,
+        promote_id: Option<PromoteId>,
+    ) -> Result<(mvt::Tile<FeatureWithCoordinates>, usize)> {
+        match self
+            .send(WorkerRequest::ParseVector(data, promote_id))
+            .await?
+        {
+            WorkerResponse::Vector(tile, size) => Ok((tile, size)),
+            response => Err(unexpected(&response)),
+        }
+    }
+
+    pub async fn geojson_tile(
+        &self,
+        index: Arc<Mutex<GeoJSONIndex>>,
+        tile_id: CanonicalTileId,
+    ) -> Result<(mvt::Tile<FeatureWithCoordinates>, usize)> {
+        match self
+            .send(WorkerRequest::GeoJSONTile(index, tile_id))
+            .await?
+        {
+            WorkerResponse::Vector(tile, size) => Ok((tile, size)),
+            response => Err(unexpected(&response)),
+        }
+    }
+
+    pub async fn custom_features_tile(
+        &self,
+        features: Vec<Value>,
+        tile_id: CanonicalTileId,
+        tile_size: u32,
+        max_zoom: f32,
+    ) -> Result<(mvt::Tile<FeatureWithCoordinates>, usize)> {
+        match self
+            .send(WorkerRequest::CustomFeatures(
+                features, tile_id, tile_size, max_zoom,
+            ))
+            .await?
+        {
+            WorkerResponse::Vector(tile, size) => Ok((tile, size)),
+            response => Err(unexpected(&response)),
+        }
+    }
+
+    pub async fn decode_raster_tile(&self, data: Vec<u8>) -> Result<image::RgbaImage> {
+        match self.send(WorkerRequest::DecodeRaster(data)).await? {
+            WorkerResponse::Raster(image) => Ok(image),
+            response => Err(unexpected(&response)),
+        }
+    }
+
+    pub async fn decode_dem_tile(&self, data: Vec<u8>, encoding: Encoding) -> Result<DEMData> {
+        match self.send(WorkerRequest::DecodeDEM(data, encoding)).await? {
+            WorkerResponse::DEMData(dem) => Ok(dem),
+            response => Err(unexpected(&response)),
+        }
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // Workers finish the queued requests and stop once the channel is
+        // closed.
+        self.sender.lock().unwrap().take();
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
+
+fn run(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = receiver.lock().unwrap().recv();
+        match job {
+            Ok((request, reply)) => {
+                // A panicking request fails on its own, the worker keeps
+                // going.
+                let result = panic::catch_unwind(AssertUnwindSafe(|| request.process()))
+                    .unwrap_or_else(|_| Err(eyre!("Worker panicked processing the request")));
+                // The requesting tile may have been cancelled meanwhile.
+                reply.send(result).ok();
+            }
+            Err(_) => break,
+        }
+    }
+}
+
+fn unexpected(response: &WorkerResponse) -> eyre::Report {
+    eyre!("Unexpected worker response {:?}", response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::future::join_all;
+
+    #[test]
+    fn worker_pool_replies_to_every_request() {
+        let pool = WorkerPool::new(2).unwrap();
+        assert_eq!(pool.len(), 2);
+
+        let requests = (0..8).map(|_| pool.parse_vector_tile(Vec::new(), None));
+        for result in block_on(join_all(requests)) {
+            assert!(result.is_ok());
+        }
+        // Errors are sent back rather than stopping the worker.
+        assert!(block_on(pool.decode_raster_tile(vec![0, 1, 2])).is_err());
+        assert!(block_on(pool.decode_dem_tile(vec![0, 1, 2], Encoding::Mapbox)).is_err());
+        assert!(block_on(pool.parse_vector_tile(Vec::new(), None)).is_ok());
+    }
+}